@@ -0,0 +1,437 @@
+//! Alert rules from `alerts.json`, evaluated after every refresh.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::delivery::{Delivery, DeliveryConfig};
+use crate::diagnostics;
+use crate::history::History;
+use crate::models::Pie;
+use crate::returns;
+
+pub const DEFAULT_PATH: &str = "alerts.json";
+
+/// Which conditions have fired, kept between runs.
+pub const DEFAULT_STATE_PATH: &str = "alert_state.json";
+
+/// How far back past its threshold a value must go before the alert can fire again, in
+/// percentage points, when a rule doesn't set `hysteresis`.
+const DEFAULT_HYSTERESIS: f64 = 1.0;
+
+/// Alerts kept for the log panel.
+const LOG_LIMIT: usize = 200;
+
+/// One rule of `alerts.json`, tagged by `kind`. Rules with a `pie_id` apply to that pie only,
+/// otherwise to every pie. Percentages are in percent.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Rule {
+    /// A pie's return rises to `percent` or above.
+    ReturnAbove {
+        pie_id: Option<u64>,
+        percent: f64,
+        hysteresis: Option<f64>,
+    },
+    /// A pie's return falls to `percent` or below.
+    ReturnBelow {
+        pie_id: Option<u64>,
+        percent: f64,
+        hysteresis: Option<f64>,
+    },
+    /// The portfolio's time-weighted return over the last `window_minutes` exceeds `percent`
+    /// either way, so deposits don't count as moves.
+    PortfolioMove {
+        percent: f64,
+        window_minutes: f64,
+        hysteresis: Option<f64>,
+    },
+    /// A pie's status changes.
+    StatusChange { pie_id: Option<u64> },
+    /// An instrument's weight is more than `percent` points away from its target.
+    Drift {
+        pie_id: Option<u64>,
+        percent: f64,
+        hysteresis: Option<f64>,
+    },
+}
+
+/// The contents of `alerts.json`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AlertConfig {
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    #[serde(default = "default_true")]
+    pub desktop_notifications: bool,
+    /// Where else fired alerts are sent.
+    #[serde(default)]
+    pub delivery: DeliveryConfig,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        Self { rules: Vec::new(), desktop_notifications: true, delivery: DeliveryConfig::default() }
+    }
+}
+
+impl AlertConfig {
+    /// Reads `{"rules": [...], "desktop_notifications": true, "delivery": {...}}`. A missing file means no rules.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut data = String::new();
+        match File::open(path) {
+            Ok(mut file) => file.read_to_string(&mut data)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+        Ok(serde_json::from_str(&data)?)
+    }
+}
+
+/// A fired alert.
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub timestamp: f64, // Unix timestamp in seconds
+    /// Index of the rule that fired in `alerts.json`.
+    pub rule: usize,
+    /// What the rule fired for: a pie id, `pie_id/ticker` for drift, empty for the portfolio.
+    pub subject: String,
+    pub title: String,
+    pub message: String,
+}
+
+/// The part of [`Alerts`] saved in the state file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SavedState {
+    active: Vec<(usize, String)>,
+    statuses: Vec<(usize, u64, String)>,
+}
+
+/// Evaluates the rules on successive refreshes.
+///
+/// Each condition (a rule applied to one pie or instrument) fires once when it becomes true,
+/// including when it is already true the first time it is checked, and re-arms only after it
+/// has cleared by the rule's hysteresis. With a state file, the conditions that have fired are
+/// remembered across restarts so they don't fire again.
+#[derive(Debug, Default)]
+pub struct Alerts {
+    config: AlertConfig,
+    /// Conditions currently true, keyed by rule index and subject.
+    active: HashSet<(usize, String)>,
+    /// Last status of each pie, per status rule.
+    statuses: HashMap<(usize, u64), String>,
+    /// Where `active` and `statuses` are saved when they change.
+    state_path: Option<PathBuf>,
+    state_changed: bool,
+    /// Fired alerts, oldest first.
+    pub log: VecDeque<Alert>,
+    /// Started with the first alert that fires, if any targets are configured.
+    delivery: Option<Delivery>,
+}
+
+impl Alerts {
+    pub fn new(config: AlertConfig) -> Self {
+        Self { config, ..Self::default() }
+    }
+
+    /// Restores the state saved at `path`, if there is any, so conditions that fired before a
+    /// restart don't fire again; with `save` the state is written back there as it changes.
+    /// A front end attached to a collector reads the collector's state without saving.
+    pub fn with_state(mut self, path: impl Into<PathBuf>, save: bool) -> io::Result<Self> {
+        let path = path.into();
+        let saved: SavedState = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(io::BufReader::new(file))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => SavedState::default(),
+            Err(e) => return Err(e),
+        };
+        self.active = saved.active.into_iter().collect();
+        self.statuses = saved.statuses.into_iter().map(|(index, pie_id, status)| ((index, pie_id), status)).collect();
+        if save {
+            self.state_path = Some(path);
+        }
+        Ok(self)
+    }
+
+    /// Writes the state through a temporary file, so a crash never leaves half of it.
+    fn save_state(&self, path: &Path) -> io::Result<()> {
+        let mut saved = SavedState {
+            active: self.active.iter().cloned().collect(),
+            statuses: self.statuses.iter().map(|((index, pie_id), status)| (*index, *pie_id, status.clone())).collect(),
+        };
+        saved.active.sort();
+        saved.statuses.sort();
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(&saved)?)?;
+        fs::rename(&tmp_path, path)
+    }
+
+    pub fn config(&self) -> &AlertConfig {
+        &self.config
+    }
+
+    /// The delivery queue, started on first use; `None` without delivery targets.
+    fn delivery(&mut self) -> Option<Delivery> {
+        if self.delivery.is_none() && !self.config.delivery.is_empty() {
+            self.delivery = Some(Delivery::spawn(self.config.delivery.clone()));
+        }
+        self.delivery.clone()
+    }
+
+    /// Checks every rule against the latest data; returns the alerts that fired, which are also
+    /// added to [`Alerts::log`].
+    pub fn evaluate(&mut self, pies: &[Pie], history: &History, now: f64) -> Vec<Alert> {
+        let mut fired = Vec::new();
+        let rules = self.config.rules.clone();
+        for (index, rule) in rules.iter().enumerate() {
+            match rule {
+                Rule::ReturnAbove { pie_id, percent, hysteresis } | Rule::ReturnBelow { pie_id, percent, hysteresis } => {
+                    let above = matches!(rule, Rule::ReturnAbove { .. });
+                    for pie in pies.iter().filter(|pie| pie_id.is_none_or(|id| id == pie.id)) {
+                        let value = pie.result.price_avg_result_coef * 100.0;
+                        let hysteresis = hysteresis.unwrap_or(DEFAULT_HYSTERESIS);
+                        let subject = pie.id.to_string();
+                        if self.latch((index, subject.clone()), value, *percent, hysteresis, above) {
+                            let direction = if above { "above" } else { "below" };
+                            fired.push(Alert {
+                                timestamp: now,
+                                rule: index,
+                                subject,
+                                title: format!("{} return {} {}%", pie_name(pie), direction, percent),
+                                message: format!("Return is now {:.2}%", value),
+                            });
+                        }
+                    }
+                }
+                Rule::PortfolioMove { percent, window_minutes, hysteresis } => {
+                    let Some(moved) = portfolio_move(history, window_minutes * 60.0, now) else {
+                        continue;
+                    };
+                    let hysteresis = hysteresis.unwrap_or(DEFAULT_HYSTERESIS);
+                    if self.latch((index, String::new()), moved.abs(), *percent, hysteresis, true) {
+                        let direction = if moved >= 0.0 { "up" } else { "down" };
+                        fired.push(Alert {
+                            timestamp: now,
+                            rule: index,
+                            subject: String::new(),
+                            title: format!("Portfolio {} {:.2}%", direction, moved.abs()),
+                            message: format!("Moved {:+.2}% in the last {} minutes", moved, window_minutes),
+                        });
+                    }
+                }
+                Rule::StatusChange { pie_id } => {
+                    for pie in pies.iter().filter(|pie| pie_id.is_none_or(|id| id == pie.id)) {
+                        let status = pie.status.clone().unwrap_or_else(|| "Active".to_string());
+                        let previous = self.statuses.insert((index, pie.id), status.clone());
+                        if previous.as_ref() != Some(&status) {
+                            self.state_changed = true;
+                        }
+                        if let Some(previous) = previous
+                            && previous != status
+                        {
+                            fired.push(Alert {
+                                timestamp: now,
+                                rule: index,
+                                subject: pie.id.to_string(),
+                                title: format!("{} status changed", pie_name(pie)),
+                                message: format!("{} → {}", previous, status),
+                            });
+                        }
+                    }
+                }
+                Rule::Drift { pie_id, percent, hysteresis } => {
+                    for pie in pies.iter().filter(|pie| pie_id.is_none_or(|id| id == pie.id)) {
+                        for instrument in &pie.instruments {
+                            let drift = instrument.drift();
+                            let subject = format!("{}/{}", pie.id, instrument.ticker);
+                            let hysteresis = hysteresis.unwrap_or(DEFAULT_HYSTERESIS);
+                            if self.latch((index, subject.clone()), drift.abs(), *percent, hysteresis, true) {
+                                fired.push(Alert {
+                                    timestamp: now,
+                                    rule: index,
+                                    subject,
+                                    title: format!("{} drifted in {}", instrument.ticker, pie_name(pie)),
+                                    message: format!(
+                                        "{:.1}% of the pie against a target of {:.1}% ({:+.1} points)",
+                                        instrument.current_share * 100.0,
+                                        instrument.expected_share * 100.0,
+                                        drift
+                                    ),
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        self.log.extend(fired.iter().cloned());
+        while self.log.len() > LOG_LIMIT {
+            self.log.pop_front();
+        }
+        if self.state_changed
+            && let Some(path) = &self.state_path
+        {
+            match self.save_state(path) {
+                Ok(()) => self.state_changed = false,
+                Err(e) => diagnostics::report(format!("Failed to save alert state to {}: {}", path.display(), e)),
+            }
+        }
+        fired
+    }
+
+    /// Tracks whether `value` is past `threshold` (at or above it when `above`, else at or below);
+    /// true when the condition has just become true.
+    fn latch(&mut self, key: (usize, String), value: f64, threshold: f64, hysteresis: f64, above: bool) -> bool {
+        let beyond = if above { value >= threshold } else { value <= threshold };
+        let cleared = if above { value < threshold - hysteresis } else { value > threshold + hysteresis };
+
+        if self.active.contains(&key) {
+            if cleared {
+                self.active.remove(&key);
+                self.state_changed = true;
+            }
+            false
+        } else if beyond {
+            self.active.insert(key);
+            self.state_changed = true;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Time-weighted return of the total over the last `window` seconds, in percent.
+fn portfolio_move(history: &History, window: f64, now: f64) -> Option<f64> {
+    // Start from the last sample at or before the window start so the whole window is covered
+    let start = history.total.partition_point(|p| p.timestamp <= now - window).saturating_sub(1);
+    let samples = returns::total_flow_samples(&history.total[start..]);
+    returns::twr(&samples).map(|twr| twr * 100.0)
+}
+
+fn pie_name(pie: &Pie) -> String {
+    pie.name.clone().unwrap_or_else(|| format!("Pie {}", pie.id))
+}
+
+/// Evaluates `alerts` against the shared state after a refresh, raises desktop notifications and
+/// queues delivery for the alerts that fired, which are returned.
+pub async fn check(
+    pies: &Mutex<HashMap<usize, Pie>>,
+    history: &Mutex<History>,
+    alerts: &Mutex<Alerts>,
+) -> Vec<Alert> {
+    let pies_data: Vec<Pie> = pies.lock().await.values().cloned().collect();
+    let now = Utc::now().timestamp() as f64;
+    let (fired, desktop_notifications, delivery) = {
+        let history = history.lock().await;
+        let mut alerts = alerts.lock().await;
+        let fired = alerts.evaluate(&pies_data, &history, now);
+        let delivery = if fired.is_empty() { None } else { alerts.delivery() };
+        (fired, alerts.config().desktop_notifications, delivery)
+    };
+    if let Some(delivery) = delivery {
+        delivery.send(&fired);
+    }
+    if desktop_notifications && !fired.is_empty() {
+        let notifications = fired.clone();
+        tokio::task::spawn_blocking(move || notifications.iter().for_each(notify_desktop));
+    }
+    fired
+}
+
+/// Shows `alert` as a desktop notification; blocks while talking to the notification service.
+pub fn notify_desktop(alert: &Alert) {
+    let result = notify_rust::Notification::new()
+        .appname("pie_top")
+        .summary(&alert.title)
+        .body(&alert.message)
+        .show();
+    if let Err(e) = result {
+        diagnostics::report(format!("Failed to show notification: {}", e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pie(return_percent: f64, status: &str) -> Pie {
+        serde_json::from_value(serde_json::json!({
+            "id": 1, "cash": 0.0, "dividendDetails": {"gained": 0.0, "reinvested": 0.0, "inCash": 0.0},
+            "result": {"priceAvgInvestedValue": 100.0, "priceAvgValue": 100.0 + return_percent, "priceAvgResult": return_percent, "priceAvgResultCoef": return_percent / 100.0},
+            "progress": null, "status": status, "name": "Growth"
+        }))
+        .unwrap()
+    }
+
+    fn config() -> AlertConfig {
+        AlertConfig {
+            rules: vec![
+                Rule::ReturnAbove { pie_id: None, percent: 10.0, hysteresis: Some(2.0) },
+                Rule::StatusChange { pie_id: Some(1) },
+            ],
+            desktop_notifications: false,
+            delivery: DeliveryConfig::default(),
+        }
+    }
+
+    fn titles(alerts: &mut Alerts, return_percent: f64, status: &str) -> Vec<String> {
+        let fired = alerts.evaluate(&[pie(return_percent, status)], &History::default(), 0.0);
+        fired.into_iter().map(|alert| alert.title).collect()
+    }
+
+    fn state_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("piers-alerts-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn fires_once_and_rearms_past_the_hysteresis() {
+        let mut alerts = Alerts::new(config());
+        // Already true the first time it's checked
+        assert_eq!(titles(&mut alerts, 12.0, "Active"), ["Growth return above 10%"]);
+        assert!(titles(&mut alerts, 15.0, "Active").is_empty());
+        // Back under the threshold but within the hysteresis: still latched
+        assert!(titles(&mut alerts, 8.5, "Active").is_empty());
+        assert!(titles(&mut alerts, 10.0, "Active").is_empty());
+        // Cleared, then true again
+        assert!(titles(&mut alerts, 7.9, "Active").is_empty());
+        assert_eq!(titles(&mut alerts, 10.0, "Active"), ["Growth return above 10%"]);
+        assert_eq!(alerts.log.len(), 2);
+    }
+
+    #[test]
+    fn status_changes_fire_after_the_first_status_is_known() {
+        let mut alerts = Alerts::new(config());
+        assert!(titles(&mut alerts, 0.0, "Active").is_empty());
+        assert_eq!(titles(&mut alerts, 0.0, "Paused"), ["Growth status changed"]);
+        assert!(titles(&mut alerts, 0.0, "Paused").is_empty());
+    }
+
+    #[test]
+    fn saved_state_stops_alerts_repeating_after_a_restart() {
+        let path = state_path("restart");
+        let mut alerts = Alerts::new(config()).with_state(&path, true).unwrap();
+        assert_eq!(titles(&mut alerts, 12.0, "Active"), ["Growth return above 10%"]);
+
+        let mut restarted = Alerts::new(config()).with_state(&path, true).unwrap();
+        assert_eq!(titles(&mut restarted, 12.0, "Paused"), ["Growth status changed"]);
+        assert!(titles(&mut restarted, 5.0, "Paused").is_empty());
+
+        // Reading without saving leaves the file as the collector wrote it
+        let saved = fs::read_to_string(&path).unwrap();
+        let mut attached = Alerts::new(config()).with_state(&path, false).unwrap();
+        assert_eq!(titles(&mut attached, 12.0, "Active"), ["Growth return above 10%", "Growth status changed"]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), saved);
+        fs::remove_file(&path).unwrap();
+    }
+}
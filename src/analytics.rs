@@ -0,0 +1,179 @@
+//! Returns and risk figures for the dashboard, worked out in a background task whenever new
+//! samples are recorded, so drawing a frame only reads the latest results.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::Utc;
+use tokio::sync::{Mutex, watch};
+
+use crate::history::History;
+use crate::ledger::Ledger;
+use crate::models::Pie;
+use crate::returns::{self, FlowSample, TwrPeriod, TwrSpan};
+use crate::risk::{self, RiskMetrics};
+
+/// What the risk figures are computed over; chosen in the dashboard.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RiskSettings {
+    pub period: TwrPeriod,
+    /// Annual, as a fraction.
+    pub risk_free_rate: f64,
+}
+
+/// Returns are fractions (0.07 = 7%).
+#[derive(Debug, Clone, Default)]
+pub struct Analytics {
+    /// Money-weighted return per pie.
+    pub irr: HashMap<u64, f64>,
+    pub portfolio_irr: Option<f64>,
+    /// Time-weighted return per pie and period, for the periods its history covers.
+    pub twr: HashMap<u64, HashMap<TwrPeriod, f64>>,
+    pub portfolio_twr: HashMap<TwrPeriod, f64>,
+    /// Time-weighted growth of each pie over all of its history, to set against benchmarks.
+    pub twr_span: HashMap<u64, TwrSpan>,
+    pub risk: HashMap<u64, RiskMetrics>,
+    pub portfolio_risk: Option<RiskMetrics>,
+}
+
+impl Analytics {
+    pub fn compute(pies: &[Pie], history: &History, ledger: &Ledger, settings: RiskSettings, now: f64) -> Self {
+        let total_samples = returns::total_flow_samples(&history.total);
+        let pie_samples: Vec<(u64, Vec<FlowSample>)> = pies.iter()
+            .map(|pie| (pie.id, returns::pie_flow_samples(history.pie(pie.id))))
+            .collect();
+        Self {
+            irr: pies.iter()
+                .filter_map(|pie| returns::pie_irr(history, pie, ledger, now).map(|irr| (pie.id, irr)))
+                .collect(),
+            portfolio_irr: returns::portfolio_irr(history, pies, ledger, now),
+            twr: pie_samples.iter().map(|(id, samples)| (*id, period_twrs(samples, now))).collect(),
+            portfolio_twr: period_twrs(&total_samples, now),
+            twr_span: pie_samples.iter()
+                .filter_map(|(id, samples)| returns::twr_span(samples).map(|span| (*id, span)))
+                .collect(),
+            risk: risk::pie_risks(pies, history, settings.period, settings.risk_free_rate, now),
+            portfolio_risk: risk::portfolio_risk(history, settings.period, settings.risk_free_rate, now),
+        }
+    }
+}
+
+fn period_twrs(samples: &[FlowSample], now: f64) -> HashMap<TwrPeriod, f64> {
+    TwrPeriod::ALL.into_iter()
+        .filter_map(|period| returns::period_twr(samples, period, now).map(|twr| (period, twr)))
+        .collect()
+}
+
+/// The dashboard's side of the task started by [`spawn`].
+#[derive(Debug)]
+pub struct AnalyticsHandle {
+    results: Arc<Mutex<Analytics>>,
+    settings: watch::Sender<RiskSettings>,
+}
+
+impl AnalyticsHandle {
+    /// The figures from the last run; the task only holds the lock to replace them.
+    pub fn results(&self) -> &Arc<Mutex<Analytics>> {
+        &self.results
+    }
+
+    /// Recomputes the risk figures with `settings` if they changed.
+    pub fn set_risk_settings(&self, settings: RiskSettings) {
+        self.settings.send_if_modified(|current| {
+            let changed = *current != settings;
+            *current = settings;
+            changed
+        });
+    }
+}
+
+/// Starts the task that recomputes [`Analytics`] each time `history` gets new samples (from the
+/// poller or the follower) and whenever the risk settings change.
+pub fn spawn(
+    pies: Arc<Mutex<HashMap<usize, Pie>>>,
+    history: Arc<Mutex<History>>,
+    ledger: Ledger,
+    settings: RiskSettings,
+) -> AnalyticsHandle {
+    let results = Arc::new(Mutex::new(Analytics::default()));
+    let (sender, mut receiver) = watch::channel(settings);
+    let results_for_task = results.clone();
+    let ledger = Arc::new(ledger);
+    tokio::spawn(async move {
+        let updated = history.lock().await.updated();
+        loop {
+            let settings = *receiver.borrow_and_update();
+            let pies: Vec<Pie> = pies.lock().await.values().cloned().collect();
+            // Work on a copy so neither the poller nor the UI waits for the figures
+            let samples = history.lock().await.snapshot();
+            let ledger = ledger.clone();
+            let now = Utc::now().timestamp() as f64;
+            let computed = tokio::task::spawn_blocking(move || Analytics::compute(&pies, &samples, &ledger, settings, now)).await;
+            if let Ok(analytics) = computed {
+                *results_for_task.lock().await = analytics;
+            }
+
+            tokio::select! {
+                _ = updated.notified() => {}
+                changed = receiver.changed() => {
+                    if changed.is_err() {
+                        return; // The dashboard is gone
+                    }
+                }
+            }
+        }
+    });
+    AnalyticsHandle { results, settings: sender }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn pie(value: f64) -> Pie {
+        serde_json::from_value(serde_json::json!({
+            "id": 1, "cash": 0.0, "dividendDetails": {"gained": 0.0, "reinvested": 0.0, "inCash": 0.0},
+            "result": {"priceAvgInvestedValue": 100.0, "priceAvgValue": value, "priceAvgResult": value - 100.0, "priceAvgResultCoef": value / 100.0 - 1.0},
+            "progress": null, "status": null, "created_at": null, "name": "One"
+        }))
+        .unwrap()
+    }
+
+    /// Waits for the task to publish figures that satisfy `done`.
+    async fn wait_for(handle: &AnalyticsHandle, done: impl Fn(&Analytics) -> bool) -> Analytics {
+        for _ in 0..200 {
+            let analytics = handle.results().lock().await.clone();
+            if done(&analytics) {
+                return analytics;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("figures were not updated");
+    }
+
+    #[tokio::test]
+    async fn recomputes_when_samples_arrive_and_settings_change() {
+        let pies = Arc::new(Mutex::new(HashMap::from([(1, pie(100.0))])));
+        let history = Arc::new(Mutex::new(History::in_memory()));
+        let settings = RiskSettings { period: TwrPeriod::SinceInception, risk_free_rate: 0.0 };
+        let handle = spawn(pies.clone(), history.clone(), Ledger::default(), settings);
+
+        let now = Utc::now().timestamp() as f64;
+        for (i, value) in [100.0, 110.0, 99.0].into_iter().enumerate() {
+            let pie = pie(value);
+            *pies.lock().await = HashMap::from([(1, pie.clone())]);
+            history.lock().await.record(now - 3.0 * 86400.0 + i as f64 * 86400.0, &[pie]).unwrap();
+        }
+        let analytics = wait_for(&handle, |a| a.risk.contains_key(&1)).await;
+        assert!((analytics.portfolio_twr[&TwrPeriod::SinceInception] + 0.01).abs() < 1e-9);
+        assert!((analytics.twr[&1][&TwrPeriod::SinceInception] + 0.01).abs() < 1e-9);
+        assert!(!analytics.twr[&1].contains_key(&TwrPeriod::OneMonth));
+        assert!((analytics.twr_span[&1].growth - 0.99).abs() < 1e-9);
+        assert!((analytics.risk[&1].max_drawdown + 0.1).abs() < 1e-9);
+        let sharpe = analytics.risk[&1].sharpe.unwrap();
+
+        handle.set_risk_settings(RiskSettings { risk_free_rate: 0.5, ..settings });
+        wait_for(&handle, |a| a.risk[&1].sharpe.is_some_and(|s| s < sharpe)).await;
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+
+use crate::models::{AccountCash, AccountInfo, DividendsPage, Pie, PieDetail, Position};
+use crate::ratelimit::{Endpoint, RateLimitHeaders, RateLimiter};
+
+pub const LIVE_BASE_URL: &str = "https://live.trading212.com";
+pub const DEMO_BASE_URL: &str = "https://demo.trading212.com";
+
+/// Errors returned by [`Trading212Client`].
+#[derive(Debug)]
+pub enum ApiError {
+    /// The request never produced a response (DNS, TLS, connection reset, ...).
+    Transport(reqwest::Error),
+    /// HTTP 429 from the API.
+    RateLimited,
+    /// Any other non-success HTTP status.
+    Status { status: u16, body: String },
+    /// A 2xx response whose body is a Trading 212 business error.
+    Business { body: String },
+    /// A 2xx response that could not be decoded into the expected type.
+    Decode { source: serde_json::Error, body: String },
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Transport(e) => write!(f, "Request failed: {}", e),
+            ApiError::RateLimited => write!(f, "Rate limited (HTTP 429)"),
+            ApiError::Status { status, body } => write!(f, "HTTP Error: {} - {}", status, body),
+            ApiError::Business { body } => write!(f, "API Business Error: {}", body),
+            ApiError::Decode { source, body } => {
+                write!(f, "Failed to decode response: {} (raw response: {})", source, body)
+            }
+        }
+    }
+}
+
+impl ApiError {
+    /// Whether the failure is likely transient and worth retrying after a backoff.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ApiError::Transport(_) | ApiError::RateLimited => true,
+            ApiError::Status { status, .. } => *status >= 500,
+            ApiError::Business { .. } | ApiError::Decode { .. } => false,
+        }
+    }
+}
+
+impl std::error::Error for ApiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ApiError::Transport(e) => Some(e),
+            ApiError::Decode { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(e: reqwest::Error) -> Self {
+        ApiError::Transport(e)
+    }
+}
+
+/// Client for the Trading 212 public API.
+///
+/// Cloning is cheap: clones share the same connection pool and rate limiter, so calls
+/// made through any clone are spaced according to the per-endpoint limits.
+#[derive(Debug, Clone)]
+pub struct Trading212Client {
+    http: reqwest::Client,
+    base_url: String,
+    token: String,
+    limiter: Arc<RateLimiter>,
+}
+
+impl Trading212Client {
+    /// Creates a client talking to the live environment.
+    pub fn new(token: impl Into<String>) -> Self {
+        Self::with_base_url(token, LIVE_BASE_URL)
+    }
+
+    /// Creates a client talking to `base_url` (e.g. `http://127.0.0.1:8080` for a local mock).
+    pub fn with_base_url(token: impl Into<String>, base_url: impl Into<String>) -> Self {
+        let base_url = base_url.into().trim_end_matches('/').to_string();
+        Self {
+            http: reqwest::Client::new(),
+            base_url,
+            token: token.into(),
+            limiter: Arc::new(RateLimiter::new()),
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.limiter
+    }
+
+    /// `GET /api/v0/equity/pies`
+    pub async fn pies(&self) -> Result<Vec<Pie>, ApiError> {
+        let body = self.get_text(Endpoint::Pies, "/api/v0/equity/pies").await?;
+
+        // The endpoint returns an array, but older responses came back keyed by id
+        match serde_json::from_str::<Vec<Pie>>(&body) {
+            Ok(pies) => Ok(pies),
+            Err(source) => match serde_json::from_str::<HashMap<String, Pie>>(&body) {
+                Ok(pies_map) => Ok(pies_map.into_values().collect()),
+                Err(_) => Err(ApiError::Decode { source, body }),
+            },
+        }
+    }
+
+    /// `GET /api/v0/equity/pies/{id}`
+    pub async fn pie(&self, id: u64) -> Result<PieDetail, ApiError> {
+        self.get(Endpoint::Pie, &format!("/api/v0/equity/pies/{}", id)).await
+    }
+
+    /// `GET /api/v0/equity/account/cash`
+    pub async fn account_cash(&self) -> Result<AccountCash, ApiError> {
+        self.get(Endpoint::AccountCash, "/api/v0/equity/account/cash").await
+    }
+
+    /// `GET /api/v0/equity/account/info`
+    pub async fn account_info(&self) -> Result<AccountInfo, ApiError> {
+        self.get(Endpoint::AccountInfo, "/api/v0/equity/account/info").await
+    }
+
+    /// `GET /api/v0/equity/portfolio`
+    pub async fn portfolio(&self) -> Result<Vec<Position>, ApiError> {
+        self.get(Endpoint::Portfolio, "/api/v0/equity/portfolio").await
+    }
+
+    /// `GET /api/v0/history/dividends`, newest first.
+    ///
+    /// Pass the previous page's `next_page_path` to continue, or `None` for the first page.
+    pub async fn dividends_page(&self, next_page_path: Option<&str>) -> Result<DividendsPage, ApiError> {
+        let path = match next_page_path {
+            Some(path) if path.starts_with('/') => path.to_string(),
+            Some(path) => format!("/{}", path),
+            None => "/api/v0/history/dividends?limit=50".to_string(),
+        };
+        self.get(Endpoint::Dividends, &path).await
+    }
+
+    async fn get<T: DeserializeOwned>(&self, endpoint: Endpoint, path: &str) -> Result<T, ApiError> {
+        let body = self.get_text(endpoint, path).await?;
+        serde_json::from_str(&body).map_err(|source| ApiError::Decode { source, body })
+    }
+
+    async fn get_text(&self, endpoint: Endpoint, path: &str) -> Result<String, ApiError> {
+        self.limiter.acquire(endpoint).await;
+        let response = self
+            .http
+            .get(format!("{}{}", self.base_url, path))
+            .header("Authorization", &self.token)
+            .send()
+            .await?;
+
+        let headers = RateLimitHeaders::from_headers(response.headers());
+        self.limiter.observe(endpoint, &headers);
+
+        let status = response.status();
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            if headers.reset.is_none() {
+                self.limiter.defer(endpoint, endpoint.default_interval());
+            }
+            return Err(ApiError::RateLimited);
+        }
+        let body = response.text().await?;
+        if !status.is_success() {
+            return Err(ApiError::Status { status: status.as_u16(), body });
+        }
+        if is_business_error(&body) {
+            return Err(ApiError::Business { body });
+        }
+        Ok(body)
+    }
+}
+
+/// Trading 212 reports some failures as a 2xx with a `{"code": "BusinessException", ...}` body.
+fn is_business_error(body: &str) -> bool {
+    match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(serde_json::Value::Object(obj)) => obj
+            .get("code")
+            .and_then(|c| c.as_str())
+            .is_some_and(|c| c.contains("BusinessException")),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use axum::Router;
+
+    /// A client for a local stub answering each endpoint with a fixed status and body.
+    async fn stub() -> Trading212Client {
+        let router = Router::new()
+            .route("/api/v0/equity/account/info", get(|| async { r#"{"id": 7, "currencyCode": "GBP"}"# }))
+            .route("/api/v0/equity/pies", get(|| async {
+                r#"{"1": {"id": 1, "cash": 0.0, "dividendDetails": {"gained": 0.0, "reinvested": 0.0, "inCash": 0.0},
+                   "result": {"priceAvgInvestedValue": 1.0, "priceAvgValue": 1.0, "priceAvgResult": 0.0, "priceAvgResultCoef": 0.0},
+                   "progress": null, "status": null, "created_at": null, "name": null}}"#
+            }))
+            .route("/api/v0/equity/account/cash", get(|| async { (StatusCode::TOO_MANY_REQUESTS, "slow down") }))
+            .route("/api/v0/equity/portfolio", get(|| async { (StatusCode::UNAUTHORIZED, "bad token") }))
+            .route("/api/v0/equity/pies/1", get(|| async { r#"{"code": "BusinessException", "context": "pie"}"# }))
+            .route("/api/v0/history/dividends", get(|| async { r#"{"items": "#.to_string() }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        Trading212Client::with_base_url("token", base_url)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn decodes_successful_responses() {
+        let client = stub().await;
+        let info = client.account_info().await.unwrap();
+        assert_eq!((info.id, info.currency_code.as_str()), (7, "GBP"));
+        // Pies keyed by id are accepted as well as an array
+        let pies = client.pies().await.unwrap();
+        assert_eq!(pies.iter().map(|pie| pie.id).collect::<Vec<_>>(), [1]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn maps_failures_to_errors() {
+        let client = stub().await;
+        let rate_limited = client.account_cash().await.unwrap_err();
+        assert!(matches!(rate_limited, ApiError::RateLimited));
+        assert!(rate_limited.is_retryable());
+
+        match client.portfolio().await.unwrap_err() {
+            error @ ApiError::Status { status: 401, .. } => {
+                assert!(!error.is_retryable());
+                assert_eq!(error.to_string(), "HTTP Error: 401 - bad token");
+            }
+            error => panic!("{error:?}"),
+        }
+
+        let business = client.pie(1).await.unwrap_err();
+        assert!(matches!(&business, ApiError::Business { body } if body.contains("BusinessException")));
+        assert!(!business.is_retryable());
+
+        match client.dividends_page(None).await.unwrap_err() {
+            ApiError::Decode { body, .. } => assert_eq!(body, r#"{"items": "#),
+            error => panic!("{error:?}"),
+        }
+    }
+
+    #[test]
+    fn business_errors_are_recognised_by_their_code() {
+        assert!(is_business_error(r#"{"code": "BusinessException", "context": null}"#));
+        assert!(!is_business_error(r#"{"code": "Other"}"#));
+        assert!(!is_business_error(r#"[{"code": "BusinessException"}]"#));
+        assert!(!is_business_error("not json"));
+    }
+}
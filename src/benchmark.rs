@@ -0,0 +1,361 @@
+//! Benchmark price series (an index or ETF) imported from CSV into the history store, to compare
+//! pies against.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::history;
+use crate::returns::TwrSpan;
+
+/// One `<name>.jsonl` per benchmark, next to the pie history.
+pub const DEFAULT_DIR: &str = "history/benchmarks";
+
+/// Date formats tried when the CSV doesn't say; day-first and month-first dates are ambiguous, so
+/// those need an explicit format.
+const DATE_FORMATS: &[&str] = &["%Y-%m-%d", "%Y/%m/%d", "%Y.%m.%d", "%Y%m%d"];
+const DATETIME_FORMATS: &[&str] = &["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S"];
+
+/// Price columns looked for, best first: adjusted closes include dividends, as pie values do.
+const PRICE_COLUMNS: &[&str] = &["adj close", "adj. close", "adjusted close", "adj_close", "close", "price", "last", "value"];
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct BenchmarkPoint {
+    pub timestamp: f64, // Unix timestamp in seconds
+    pub price: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Benchmark {
+    pub name: String,
+    /// Ordered by time.
+    pub points: Vec<BenchmarkPoint>,
+}
+
+impl Benchmark {
+    /// The last price at or before `timestamp`; `None` before the series starts.
+    pub fn price_at(&self, timestamp: f64) -> Option<f64> {
+        let index = self.points.partition_point(|p| p.timestamp <= timestamp);
+        index.checked_sub(1).map(|i| self.points[i].price)
+    }
+
+    /// Return from `from` to `to` as a growth factor (1.1 = +10%).
+    pub fn growth(&self, from: f64, to: f64) -> Option<f64> {
+        let start = self.price_at(from).filter(|price| *price > 0.0)?;
+        Some(self.price_at(to)? / start)
+    }
+
+    /// Prices from `from` to `to` scaled so the price at `from` is `base`, as `[timestamp, value]`.
+    /// Starts with `[from, base]` and ends at `to`, so the line spans the same range as the series
+    /// it is drawn against.
+    pub fn rebased(&self, from: f64, to: f64, base: f64) -> Vec<[f64; 2]> {
+        let Some(start) = self.price_at(from).filter(|price| *price > 0.0) else {
+            return Vec::new();
+        };
+        let mut points = vec![[from, base]];
+        points.extend(
+            self.points
+                .iter()
+                .filter(|p| p.timestamp > from && p.timestamp < to)
+                .map(|p| [p.timestamp, p.price / start * base]),
+        );
+        if let Some(price) = self.price_at(to) {
+            points.push([to, price / start * base]);
+        }
+        points
+    }
+
+    /// How a holding with time-weighted growth `span` did against this benchmark over the same
+    /// dates, so deposits and their timing don't count for or against it; `None` if the benchmark
+    /// doesn't cover the start of the span.
+    pub fn compare(&self, span: TwrSpan) -> Option<Comparison> {
+        let TwrSpan { from, to, growth } = span;
+        let benchmark_growth = self.growth(from, to)?;
+        let years = (to - from) / (365.0 * 86400.0);
+        if years <= 0.0 || growth <= 0.0 {
+            return None;
+        }
+        // Same compounding as returns::calculate_annual_rate
+        let annual_rate = (growth.powf(1.0 / years) - 1.0) * 100.0;
+        let benchmark_annual_rate = (benchmark_growth.powf(1.0 / years) - 1.0) * 100.0;
+        Some(Comparison {
+            annual_rate,
+            benchmark_return: (benchmark_growth - 1.0) * 100.0,
+            benchmark_annual_rate,
+            excess_return: annual_rate - benchmark_annual_rate,
+            tracking_difference: (growth - benchmark_growth) * 100.0,
+        })
+    }
+}
+
+/// A return set against a benchmark's over the same period, in percent or percentage points.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Comparison {
+    /// The holding's own annualised return.
+    pub annual_rate: f64,
+    /// The benchmark's return over the whole period.
+    pub benchmark_return: f64,
+    pub benchmark_annual_rate: f64,
+    /// Annual rate minus the benchmark's.
+    pub excess_return: f64,
+    /// Return minus the benchmark's return over the whole period.
+    pub tracking_difference: f64,
+}
+
+/// Every benchmark in `dir`, ordered by name; none if the directory doesn't exist.
+pub fn load_all(dir: impl AsRef<Path>) -> io::Result<Vec<Benchmark>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut benchmarks = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "jsonl")
+            && let Some(name) = path.file_stem().and_then(|stem| stem.to_str())
+        {
+            benchmarks.push(Benchmark { name: name.to_string(), points: load_points(&path)? });
+        }
+    }
+    benchmarks.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(benchmarks)
+}
+
+fn load_points(path: &Path) -> io::Result<Vec<BenchmarkPoint>> {
+    let mut points: Vec<BenchmarkPoint> = history::read_lines(path)?;
+    points.sort_by(|a, b| a.timestamp.partial_cmp(&b.timestamp).unwrap_or(std::cmp::Ordering::Equal));
+    Ok(points)
+}
+
+/// Which CSV columns hold the dates and prices; detected from the header when not given.
+#[derive(Debug, Clone, Default)]
+pub struct CsvColumns {
+    pub date_column: Option<String>,
+    pub price_column: Option<String>,
+    /// A chrono format such as `%d/%m/%Y`; ISO dates and Unix timestamps are recognised without one.
+    pub date_format: Option<String>,
+}
+
+/// What [`import`] stored.
+#[derive(Debug, Clone)]
+pub struct ImportSummary {
+    pub path: PathBuf,
+    pub imported: usize,
+    /// Rows without a usable price, such as `null` for market holidays.
+    pub skipped: usize,
+    /// Prices stored for the benchmark, including earlier imports.
+    pub total: usize,
+    pub first: f64,
+    pub last: f64,
+}
+
+/// Parses the CSV in `data` and merges its prices into benchmark `name` in `dir`, replacing
+/// prices at the same time from earlier imports.
+pub fn import(dir: impl AsRef<Path>, name: &str, data: &str, columns: &CsvColumns) -> io::Result<ImportSummary> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(invalid(format!("{:?} can't be used as a benchmark name", name)));
+    }
+    let (parsed, skipped) = parse_csv(data, columns).map_err(invalid)?;
+    if parsed.is_empty() {
+        return Err(invalid("no prices found".to_string()));
+    }
+
+    fs::create_dir_all(&dir)?;
+    let path = dir.as_ref().join(format!("{}.jsonl", name));
+    let mut merged: BTreeMap<i64, BenchmarkPoint> = load_points(&path)?
+        .into_iter()
+        .map(|point| (point.timestamp as i64, point))
+        .collect();
+    let imported = parsed.len();
+    merged.extend(parsed.into_iter().map(|point| (point.timestamp as i64, point)));
+
+    // Rewritten through a temporary file so a running dashboard never reads half of it
+    let tmp_path = path.with_extension("jsonl.tmp");
+    let mut file = File::create(&tmp_path)?;
+    for point in merged.values() {
+        writeln!(file, "{}", serde_json::to_string(point)?)?;
+    }
+    file.sync_all()?;
+    fs::rename(&tmp_path, &path)?;
+
+    let first = merged.values().next().map_or(0.0, |p| p.timestamp);
+    let last = merged.values().next_back().map_or(0.0, |p| p.timestamp);
+    Ok(ImportSummary { path, imported, skipped, total: merged.len(), first, last })
+}
+
+/// Reads `[date, price]` rows from CSV with a header row; returns the prices and the number of
+/// rows skipped for lack of a price.
+pub fn parse_csv(data: &str, columns: &CsvColumns) -> Result<(Vec<BenchmarkPoint>, usize), String> {
+    let mut lines = data.lines().filter(|line| !line.trim().is_empty());
+    let header = lines.next().ok_or("the file is empty")?;
+    // Spreadsheets set to a decimal comma export with semicolons
+    let delimiter = if header.contains(';') && !header.contains(',') { ';' } else { ',' };
+    let titles: Vec<String> = split_row(header, delimiter).iter().map(|title| title.trim().to_lowercase()).collect();
+
+    let find = |wanted: &str| titles.iter().position(|title| title == &wanted.to_lowercase());
+    let date_index = match &columns.date_column {
+        Some(column) => find(column).ok_or_else(|| format!("no column {:?} in {:?}", column, titles))?,
+        None => titles.iter().position(|title| title.contains("date") || title.contains("time")).unwrap_or(0),
+    };
+    let price_index = match &columns.price_column {
+        Some(column) => find(column).ok_or_else(|| format!("no column {:?} in {:?}", column, titles))?,
+        None => match PRICE_COLUMNS.iter().find_map(|column| find(column)) {
+            Some(index) => index,
+            None if titles.len() == 2 => 1 - date_index.min(1),
+            None => return Err(format!("can't tell which of {:?} holds the price; pass the price column", titles)),
+        },
+    };
+
+    let mut points = Vec::new();
+    let mut skipped = 0;
+    for (number, line) in lines.enumerate() {
+        let row = split_row(line, delimiter);
+        let field = |index: usize| row.get(index).map(|field| field.trim()).unwrap_or_default();
+        let date = field(date_index);
+        let timestamp = parse_date(date, columns.date_format.as_deref())
+            .ok_or_else(|| format!("line {}: can't read the date {:?}", number + 2, date))?;
+        // With semicolons a comma is the decimal mark; otherwise it can only be a thousands
+        // separator inside quotes
+        let price = field(price_index);
+        let price = if delimiter == ';' && price.contains(',') {
+            price.replace('.', "").replace(',', ".")
+        } else {
+            price.replace(',', "")
+        };
+        match price.parse::<f64>() {
+            Ok(price) if price.is_finite() && price > 0.0 => points.push(BenchmarkPoint { timestamp, price }),
+            _ => skipped += 1,
+        }
+    }
+    points.sort_by(|a, b| a.timestamp.partial_cmp(&b.timestamp).unwrap_or(std::cmp::Ordering::Equal));
+    Ok((points, skipped))
+}
+
+/// Splits a CSV row, honouring double quotes.
+fn split_row(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Reads a date (as midnight UTC), a date and time (UTC), RFC 3339 or Unix seconds.
+fn parse_date(value: &str, format: Option<&str>) -> Option<f64> {
+    let date = |date: NaiveDate| date.and_hms_opt(0, 0, 0).map(|time| time.and_utc().timestamp() as f64);
+    if let Some(format) = format {
+        return NaiveDateTime::parse_from_str(value, format)
+            .map(|time| time.and_utc().timestamp() as f64)
+            .ok()
+            .or_else(|| NaiveDate::parse_from_str(value, format).ok().and_then(date));
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.timestamp() as f64);
+    }
+    if let Some(time) = DATETIME_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+    {
+        return Some(time.and_utc().timestamp() as f64);
+    }
+    if let Some(parsed) = DATE_FORMATS.iter().find_map(|format| NaiveDate::parse_from_str(value, format).ok()) {
+        return date(parsed);
+    }
+    // Ten-digit numbers are Unix seconds; shorter ones were tried as YYYYMMDD above
+    value.parse::<f64>().ok().filter(|timestamp| *timestamp >= 1e9)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: f64 = 86400.0;
+    /// 2024-01-02 00:00 UTC
+    const JAN_2: f64 = 1704153600.0;
+
+    fn prices(points: &[BenchmarkPoint]) -> Vec<f64> {
+        points.iter().map(|point| point.price).collect()
+    }
+
+    #[test]
+    fn reads_semicolons_with_decimal_commas() {
+        let data = "Datum;Eröffnung;Schluss\n03.01.2024;1.234,50;1.240,25\n02.01.2024;1.200,00;1.230,75\n";
+        let columns = CsvColumns {
+            price_column: Some("Schluss".to_string()),
+            date_format: Some("%d.%m.%Y".to_string()),
+            ..CsvColumns::default()
+        };
+        let (points, skipped) = parse_csv(data, &columns).unwrap();
+        assert_eq!(skipped, 0);
+        assert_eq!(points.iter().map(|point| point.timestamp).collect::<Vec<_>>(), [JAN_2, JAN_2 + DAY]);
+        assert_eq!(prices(&points), [1230.75, 1240.25]);
+    }
+
+    #[test]
+    fn reads_quoted_thousands_separators_and_skips_null_rows() {
+        let data = "Date,Open,Close,Adj Close\n\
+                    2024-01-02,\"4,700.00\",\"4,742.83\",\"4,742.83\"\n\
+                    2024-01-03,null,null,null\n\
+                    2024-01-04,\"4,690.57\",\"4,688.68\",\"4,688.68\"\n";
+        let (points, skipped) = parse_csv(data, &CsvColumns::default()).unwrap();
+        assert_eq!(skipped, 1);
+        assert_eq!(prices(&points), [4742.83, 4688.68]);
+        assert_eq!(points[1].timestamp, JAN_2 + 2.0 * DAY);
+    }
+
+    #[test]
+    fn day_first_dates_need_a_format() {
+        let data = "Date,Price\n02/01/2024,100\n";
+        assert!(parse_csv(data, &CsvColumns::default()).is_err());
+        let columns = CsvColumns { date_format: Some("%d/%m/%Y".to_string()), ..CsvColumns::default() };
+        assert_eq!(parse_date("02/01/2024", Some("%d/%m/%Y")), Some(JAN_2));
+        assert_eq!(parse_csv(data, &columns).unwrap().0[0].timestamp, JAN_2);
+        assert_eq!(parse_date("2024-01-02", Some("%d/%m/%Y")), None);
+    }
+
+    #[test]
+    fn parses_dates_without_a_format() {
+        assert_eq!(parse_date("2024-01-02", None), Some(JAN_2));
+        assert_eq!(parse_date("20240102", None), Some(JAN_2));
+        assert_eq!(parse_date("2024-01-02 12:00:00", None), Some(JAN_2 + DAY / 2.0));
+        assert_eq!(parse_date("2024-01-02T12:00:00+02:00", None), Some(JAN_2 + DAY / 2.0 - 7200.0));
+        assert_eq!(parse_date("1704153600", None), Some(JAN_2));
+        assert_eq!(parse_date("null", None), None);
+    }
+
+    #[test]
+    fn compares_time_weighted_growth_over_the_same_dates() {
+        let benchmark = Benchmark {
+            name: "Index".to_string(),
+            points: vec![
+                BenchmarkPoint { timestamp: JAN_2, price: 100.0 },
+                BenchmarkPoint { timestamp: JAN_2 + 365.0 * DAY, price: 110.0 },
+            ],
+        };
+        let span = TwrSpan { from: JAN_2, to: JAN_2 + 365.0 * DAY, growth: 1.2 };
+        let comparison = benchmark.compare(span).unwrap();
+        assert!((comparison.annual_rate - 20.0).abs() < 1e-9);
+        assert!((comparison.benchmark_annual_rate - 10.0).abs() < 1e-9);
+        assert!((comparison.excess_return - 10.0).abs() < 1e-9);
+        assert!((comparison.tracking_difference - 10.0).abs() < 1e-9);
+        assert!(benchmark.compare(TwrSpan { from: JAN_2 - DAY, ..span }).is_none());
+    }
+}
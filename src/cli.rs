@@ -0,0 +1,644 @@
+//! Subcommands: headless reports printed as a table, JSON or CSV, and the terminal dashboard.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{NaiveDate, Utc};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde_json::{Map, Value};
+use tokio::sync::Mutex;
+
+use crate::alerts::{self, AlertConfig, Alerts};
+use crate::api::{ApiError, Trading212Client};
+use crate::benchmark::{self, CsvColumns};
+use crate::config::{listen_address_from_env, Config, ConfigError};
+use crate::fetch::{fetch_account, fetch_pies, spawn_poller};
+use crate::history::{History, HistoryStore};
+use crate::ledger::Ledger;
+use crate::models::{Account, Pie};
+use crate::server::{self, ServerState};
+use crate::snapshot::{load_account, load_map, save_account, save_map};
+use crate::sort::{sort_pies, SortColumn, SortDirection};
+use crate::summary::{pie_irrs, PieSummary, PortfolioSummary};
+use crate::{history, ledger, returns, snapshot};
+
+/// Exit codes of the headless commands; 0 is success and 2 is clap's usage error.
+pub const EXIT_API: i32 = 3;
+pub const EXIT_RATE_LIMITED: i32 = 4;
+pub const EXIT_IO: i32 = 5;
+pub const EXIT_CONFIG: i32 = 6;
+
+#[derive(Debug, Parser)]
+#[command(name = "piers", about = "A way to (just) look at your Trading 212 pies")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Open the dashboard window (the default)
+    Gui {
+        /// Follow the store written by `piers collect` instead of polling the API
+        #[arg(long)]
+        attach: bool,
+    },
+    /// Show the dashboard in the terminal
+    Tui,
+    /// Poll the API without a window and keep writing snapshots and history
+    Collect,
+    /// Portfolio and account totals
+    Summary(OutputArgs),
+    /// One row per pie, like the dashboard table
+    Pies {
+        #[command(flatten)]
+        output: OutputArgs,
+        /// Column to sort by
+        #[arg(long, value_enum, default_value_t = SortArg::None)]
+        sort: SortArg,
+        /// Sort ascending instead of descending
+        #[arg(long)]
+        ascending: bool,
+        /// Add each pie's return against this imported benchmark
+        #[arg(long)]
+        benchmark: Option<String>,
+    },
+    /// Recorded value samples, from the local history store
+    History {
+        #[command(flatten)]
+        output: OutputArgs,
+        /// Only this pie instead of the portfolio total
+        #[arg(long)]
+        pie: Option<u64>,
+        /// First day to include (YYYY-MM-DD)
+        #[arg(long)]
+        from: Option<NaiveDate>,
+        /// Last day to include (YYYY-MM-DD)
+        #[arg(long)]
+        to: Option<NaiveDate>,
+    },
+    /// Benchmark price series to compare pies against
+    Benchmark {
+        #[command(subcommand)]
+        action: BenchmarkAction,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum BenchmarkAction {
+    /// Add prices from a CSV file with a header row, e.g. a download of an index or ETF's history
+    Import {
+        file: PathBuf,
+        /// Name to show the benchmark under; importing again under the same name adds to it
+        #[arg(long)]
+        name: String,
+        /// Column holding the dates (default: the first with "date" or "time" in its title)
+        #[arg(long)]
+        date_column: Option<String>,
+        /// Column holding the prices (default: Adj Close, Close, Price, ...)
+        #[arg(long)]
+        price_column: Option<String>,
+        /// Date format such as %d/%m/%Y, for dates that aren't ISO or Unix timestamps
+        #[arg(long)]
+        date_format: Option<String>,
+    },
+    /// Imported benchmarks and the dates they cover
+    List(OutputArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct OutputArgs {
+    #[arg(long, short, value_enum, default_value_t = Format::Table)]
+    pub format: Format,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Format {
+    Table,
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum SortArg {
+    None,
+    Initial,
+    Current,
+    Return,
+    ReturnValue,
+    AnnualRate,
+    Irr,
+    Dividends,
+}
+
+impl From<SortArg> for SortColumn {
+    fn from(sort: SortArg) -> Self {
+        match sort {
+            SortArg::None => SortColumn::None,
+            SortArg::Initial => SortColumn::InitialValue,
+            SortArg::Current => SortColumn::CurrentValue,
+            SortArg::Return => SortColumn::Return,
+            SortArg::ReturnValue => SortColumn::ReturnValue,
+            SortArg::AnnualRate => SortColumn::AnnualRate,
+            SortArg::Irr => SortColumn::Irr,
+            SortArg::Dividends => SortColumn::Dividends,
+        }
+    }
+}
+
+/// Runs a subcommand and returns the process exit code. [`Command::Gui`] is not handled here.
+pub async fn run(command: Command) -> i32 {
+    let result = match command {
+        Command::Gui { .. } => Ok(()),
+        Command::Tui => tui().await,
+        Command::Collect => collect().await,
+        Command::Summary(output) => summary(output.format).await,
+        Command::Pies { output, sort, ascending, benchmark } => {
+            let direction = if ascending { SortDirection::Ascending } else { SortDirection::Descending };
+            pies(output.format, sort.into(), direction, benchmark.as_deref()).await
+        }
+        Command::History { output, pie, from, to } => history(output.format, pie, from, to),
+        Command::Benchmark { action } => match action {
+            BenchmarkAction::Import { file, name, date_column, price_column, date_format } => {
+                import_benchmark(&file, &name, CsvColumns { date_column, price_column, date_format })
+            }
+            BenchmarkAction::List(output) => list_benchmarks(output.format),
+        },
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error: {}", e.message);
+            e.code
+        }
+    }
+}
+
+struct CliError {
+    code: i32,
+    message: String,
+}
+
+impl From<ApiError> for CliError {
+    fn from(e: ApiError) -> Self {
+        let code = if matches!(e, ApiError::RateLimited) { EXIT_RATE_LIMITED } else { EXIT_API };
+        Self { code, message: e.to_string() }
+    }
+}
+
+impl From<std::io::Error> for CliError {
+    fn from(e: std::io::Error) -> Self {
+        Self { code: EXIT_IO, message: e.to_string() }
+    }
+}
+
+impl From<ConfigError> for CliError {
+    fn from(e: ConfigError) -> Self {
+        Self { code: EXIT_CONFIG, message: e.to_string() }
+    }
+}
+
+fn config() -> Result<Config, CliError> {
+    Ok(Config::from_env()?)
+}
+
+fn client() -> Result<Trading212Client, CliError> {
+    let config = config()?;
+    Ok(Trading212Client::with_base_url(config.token, config.environment.base_url()))
+}
+
+async fn tui() -> Result<(), CliError> {
+    let config = config()?;
+    Ok(crate::tui::run(config, listen_address_from_env()?).await?)
+}
+
+/// Runs the GUI's polling loop until Ctrl-C, saving the snapshots every few seconds.
+async fn collect() -> Result<(), CliError> {
+    let client = client()?;
+    let listen_address = listen_address_from_env()?;
+    let pies = Arc::new(Mutex::new(load_map(snapshot::DEFAULT_PATH).unwrap_or_default()));
+    // Starting from the last account snapshot keeps the dividend fetch incremental across restarts
+    let account = Arc::new(Mutex::new(load_account(snapshot::DEFAULT_ACCOUNT_PATH).unwrap_or_default()));
+    let history = Arc::new(Mutex::new(History::load(HistoryStore::open(history::DEFAULT_DIR)?)?));
+    // Alerts are evaluated here too, but a collector has no desktop to show them on
+    let mut alert_config = AlertConfig::load(alerts::DEFAULT_PATH)?;
+    alert_config.desktop_notifications = false;
+    let alerts = Arc::new(Mutex::new(Alerts::new(alert_config).with_state(alerts::DEFAULT_STATE_PATH, true)?));
+    let poller_status = spawn_poller(client, pies.clone(), account.clone(), history.clone(), alerts);
+    if let Some(address) = listen_address {
+        let state = ServerState {
+            pies: pies.clone(),
+            account: account.clone(),
+            history,
+            ledger: Arc::new(Ledger::load(ledger::DEFAULT_PATH)?),
+            poller_status,
+        };
+        server::spawn(address, state).await?;
+        eprintln!("Serving on http://{}", address);
+    }
+    eprintln!("Collecting into {} and {}; stop with Ctrl-C", snapshot::DEFAULT_PATH, history::DEFAULT_DIR);
+
+    let mut save_interval = tokio::time::interval(Duration::from_secs(5));
+    loop {
+        tokio::select! {
+            _ = save_interval.tick() => {}
+            _ = tokio::signal::ctrl_c() => {
+                save_snapshots(&pies, &account).await?;
+                return Ok(());
+            }
+        }
+        if let Err(e) = save_snapshots(&pies, &account).await {
+            eprintln!("Failed to save snapshots: {}", e.message);
+        }
+    }
+}
+
+async fn save_snapshots(pies: &Mutex<HashMap<usize, Pie>>, account: &Mutex<Account>) -> Result<(), CliError> {
+    save_map(&*pies.lock().await, snapshot::DEFAULT_PATH)?;
+    save_account(&*account.lock().await, snapshot::DEFAULT_ACCOUNT_PATH)?;
+    Ok(())
+}
+
+/// Refreshes the cached pie snapshot from the API and returns it.
+async fn load_pies(client: &Trading212Client) -> Result<Vec<Pie>, CliError> {
+    let pies = Arc::new(Mutex::new(load_map(snapshot::DEFAULT_PATH).unwrap_or_default()));
+    fetch_pies(client, pies.clone()).await?;
+    let pies_map = pies.lock().await;
+    if let Err(e) = save_map(&pies_map, snapshot::DEFAULT_PATH) {
+        eprintln!("Failed to save pies: {}", e);
+    }
+    Ok(pies_map.values().cloned().collect())
+}
+
+/// History and ledger for the return figures. Either may be missing, which leaves the figures
+/// that need them empty, but one that can't be read is an error.
+fn load_history_and_ledger() -> Result<(History, Ledger), CliError> {
+    let history = History::attach(HistoryStore::open_read_only(history::DEFAULT_DIR))
+        .map_err(|e| CliError { code: EXIT_IO, message: format!("{}: {}", history::DEFAULT_DIR, e) })?;
+    let ledger = Ledger::load(ledger::DEFAULT_PATH)
+        .map_err(|e| CliError { code: EXIT_IO, message: format!("{}: {}", ledger::DEFAULT_PATH, e) })?;
+    Ok((history, ledger))
+}
+
+async fn summary(format: Format) -> Result<(), CliError> {
+    let client = client()?;
+    let pies = load_pies(&client).await?;
+    let account = Arc::new(Mutex::new(Account::default()));
+    fetch_account(&client, account.clone()).await?;
+    let account = account.lock().await;
+    let (history, ledger) = load_history_and_ledger()?;
+    let summary = PortfolioSummary::new(&pies, &account, &history, &ledger, Utc::now().timestamp() as f64);
+
+    let mut table = Table::new(&[("metric", "Metric"), ("value", "Value")]);
+    let mut metric = |name: &str, value: Cell| table.rows.push(vec![Cell::Text(name.to_string()), value]);
+    metric("pies_initial", Cell::Number(summary.pies_initial, 2));
+    metric("pies_current", Cell::Number(summary.pies_current, 2));
+    metric("pies_return_percent", Cell::Number(summary.pies_return_percent, 2));
+    metric("pies_return_value", Cell::Number(summary.pies_return_value, 2));
+    metric("pies_irr_percent", Cell::from(summary.pies_irr_percent, 2));
+    metric("pies_twr_percent", Cell::from(summary.pies_twr_percent, 2));
+    if let Some(currency) = summary.currency {
+        metric("currency", Cell::Text(currency));
+    }
+    if account.cash.is_some() {
+        metric("total_equity", Cell::from(summary.total_equity, 2));
+        metric("free_cash", Cell::from(summary.free_cash, 2));
+        metric("blocked_cash", Cell::from(summary.blocked_cash, 2));
+        metric("invested", Cell::from(summary.invested, 2));
+        metric("pie_cash", Cell::from(summary.pie_cash, 2));
+        metric("unrealised_pl", Cell::from(summary.unrealised_pl, 2));
+    }
+
+    // A summary reads better as one object than as a list of metric rows
+    if format == Format::Json {
+        let fields: Map<String, Value> = table.rows.iter().map(|row| (row[0].to_text(), row[1].to_json())).collect();
+        println!("{:#}", Value::Object(fields));
+        return Ok(());
+    }
+    table.print(format);
+    Ok(())
+}
+
+async fn pies(
+    format: Format,
+    sort: SortColumn,
+    direction: SortDirection,
+    benchmark_name: Option<&str>,
+) -> Result<(), CliError> {
+    let benchmark = match benchmark_name {
+        Some(name) => Some(
+            benchmark::load_all(benchmark::DEFAULT_DIR)?
+                .into_iter()
+                .find(|benchmark| benchmark.name == name)
+                .ok_or_else(|| CliError { code: EXIT_IO, message: format!("no benchmark named {:?}", name) })?,
+        ),
+        None => None,
+    };
+    let client = client()?;
+    let mut pies = load_pies(&client).await?;
+    let (history, ledger) = load_history_and_ledger()?;
+    let irr = pie_irrs(&pies, &history, &ledger, Utc::now().timestamp() as f64);
+    // Pie order in the snapshot is arbitrary; start from a stable one
+    pies.sort_by_key(|pie| pie.id);
+    sort_pies(&mut pies, sort, direction, &irr, &HashMap::new(), &HashMap::new());
+
+    let mut columns = vec![
+        ("id", "ID"),
+        ("name", "Name"),
+        ("initial", "Initial"),
+        ("current", "Current"),
+        ("return_percent", "Return %"),
+        ("return_value", "Return Value"),
+        ("annual_rate_percent", "Annual Rate %"),
+    ];
+    if benchmark.is_some() {
+        columns.extend([
+            ("benchmark_annual_rate_percent", "Benchmark Annual %"),
+            ("excess_return_percent", "Excess %"),
+            ("tracking_difference_percent", "Tracking Diff %"),
+        ]);
+    }
+    columns.extend([("irr_percent", "IRR %"), ("dividends", "Dividends"), ("status", "Status")]);
+    let mut table = Table::new(&columns);
+    for pie in &pies {
+        let row = PieSummary::new(pie, irr.get(&pie.id).copied());
+        let mut cells = vec![
+            Cell::Integer(row.id),
+            Cell::Text(row.name),
+            Cell::Number(row.initial, 2),
+            Cell::Number(row.current, 2),
+            Cell::Number(row.return_percent, 2),
+            Cell::Number(row.return_value, 2),
+            Cell::Number(row.annual_rate_percent, 2),
+        ];
+        if let Some(benchmark) = &benchmark {
+            // Over the pie's recorded history, where deposits are known
+            let comparison = returns::twr_span(&returns::pie_flow_samples(history.pie(pie.id)))
+                .and_then(|span| benchmark.compare(span));
+            cells.extend([
+                Cell::from(comparison.map(|c| c.benchmark_annual_rate), 2),
+                Cell::from(comparison.map(|c| c.excess_return), 2),
+                Cell::from(comparison.map(|c| c.tracking_difference), 2),
+            ]);
+        }
+        cells.extend([Cell::from(row.irr_percent, 2), Cell::Number(row.dividends_gained, 2), Cell::Text(row.status)]);
+        table.rows.push(cells);
+    }
+    table.print(format);
+    Ok(())
+}
+
+fn history(format: Format, pie: Option<u64>, from: Option<NaiveDate>, to: Option<NaiveDate>) -> Result<(), CliError> {
+    // Straight from the files: the in-memory history thins out older samples
+    let store = HistoryStore::open_read_only(history::DEFAULT_DIR);
+    let from = from.map(|d| d.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc().timestamp() as f64);
+    let to = to.map(|d| d.and_hms_opt(23, 59, 59).unwrap_or_default().and_utc().timestamp() as f64);
+    let in_range = |timestamp: f64| from.is_none_or(|from| timestamp >= from) && to.is_none_or(|to| timestamp <= to);
+    let time = |timestamp: f64| {
+        chrono::DateTime::from_timestamp(timestamp as i64, 0)
+            .map(|t| t.to_rfc3339())
+            .unwrap_or_default()
+    };
+
+    let mut table = Table::new(&[("timestamp", "Time"), ("series", "Series"), ("value", "Value"), ("invested", "Invested")]);
+    match pie {
+        None => {
+            for point in store.load_total()?.iter().filter(|p| in_range(p.timestamp)) {
+                table.rows.push(vec![
+                    Cell::Text(time(point.timestamp)),
+                    Cell::Text("Total".to_string()),
+                    Cell::Number(point.total_value, 2),
+                    Cell::Number(point.invested_value, 2),
+                ]);
+            }
+        }
+        Some(pie_id) => {
+            let name = store.load_names()?.remove(&pie_id).unwrap_or_else(|| format!("Pie {}", pie_id));
+            for point in store.load_pie(pie_id)?.iter().filter(|p| in_range(p.timestamp)) {
+                table.rows.push(vec![
+                    Cell::Text(time(point.timestamp)),
+                    Cell::Text(name.clone()),
+                    Cell::Number(point.value, 2),
+                    Cell::Number(point.invested_value, 2),
+                ]);
+            }
+        }
+    }
+    table.print(format);
+    Ok(())
+}
+
+fn format_date(timestamp: f64) -> String {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0)
+        .map(|t| t.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+fn import_benchmark(file: &std::path::Path, name: &str, columns: CsvColumns) -> Result<(), CliError> {
+    let data = std::fs::read_to_string(file)?;
+    let summary = benchmark::import(benchmark::DEFAULT_DIR, name, &data, &columns)?;
+    eprintln!(
+        "Imported {} prices into {} ({} stored, {} to {}){}",
+        summary.imported,
+        summary.path.display(),
+        summary.total,
+        format_date(summary.first),
+        format_date(summary.last),
+        if summary.skipped > 0 { format!("; skipped {} rows without a price", summary.skipped) } else { String::new() },
+    );
+    Ok(())
+}
+
+fn list_benchmarks(format: Format) -> Result<(), CliError> {
+    let mut table = Table::new(&[("name", "Name"), ("prices", "Prices"), ("first", "First"), ("last", "Last"), ("last_price", "Last Price")]);
+    for benchmark in benchmark::load_all(benchmark::DEFAULT_DIR)? {
+        let (Some(first), Some(last)) = (benchmark.points.first(), benchmark.points.last()) else {
+            continue;
+        };
+        table.rows.push(vec![
+            Cell::Text(benchmark.name.clone()),
+            Cell::Integer(benchmark.points.len() as u64),
+            Cell::Text(format_date(first.timestamp)),
+            Cell::Text(format_date(last.timestamp)),
+            Cell::Number(last.price, 2),
+        ]);
+    }
+    table.print(format);
+    Ok(())
+}
+
+enum Cell {
+    Text(String),
+    Integer(u64),
+    Number(f64, usize),
+    Missing,
+}
+
+impl Cell {
+    fn from(value: Option<f64>, precision: usize) -> Self {
+        value.map_or(Cell::Missing, |value| Cell::Number(value, precision))
+    }
+
+    fn to_text(&self) -> String {
+        match self {
+            Cell::Text(text) => text.clone(),
+            Cell::Integer(value) => value.to_string(),
+            Cell::Number(value, precision) => format!("{:.*}", precision, value),
+            Cell::Missing => String::new(),
+        }
+    }
+
+    /// Numbers are rounded as in the other formats; NaN and infinities become `null`.
+    fn to_json(&self) -> Value {
+        match self {
+            Cell::Text(text) => Value::from(text.as_str()),
+            Cell::Integer(value) => Value::from(*value),
+            Cell::Number(value, precision) => format!("{:.*}", precision, value).parse::<f64>().map_or(Value::Null, Value::from),
+            Cell::Missing => Value::Null,
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        matches!(self, Cell::Integer(_) | Cell::Number(..))
+    }
+}
+
+struct Table {
+    /// `(key, title)`: keys for JSON/CSV, titles for the text table.
+    columns: Vec<(&'static str, &'static str)>,
+    rows: Vec<Vec<Cell>>,
+}
+
+impl Table {
+    fn new(columns: &[(&'static str, &'static str)]) -> Self {
+        Self { columns: columns.to_vec(), rows: Vec::new() }
+    }
+
+    fn print(&self, format: Format) {
+        match format {
+            Format::Table => self.print_table(),
+            Format::Json => self.print_json(),
+            Format::Csv => self.print_csv(),
+        }
+    }
+
+    fn print_table(&self) {
+        let cells: Vec<Vec<String>> = self.rows.iter().map(|row| row.iter().map(Cell::to_text).collect()).collect();
+        let widths: Vec<usize> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, (_, title))| {
+                cells.iter().map(|row| row[i].chars().count()).chain([title.chars().count()]).max().unwrap_or(0)
+            })
+            .collect();
+
+        let header: Vec<String> = self.columns.iter().zip(&widths).map(|((_, title), w)| format!("{:<w$}", title, w = w)).collect();
+        println!("{}", header.join("  ").trim_end());
+        let rule: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+        println!("{}", rule.join("  "));
+        for (row, texts) in self.rows.iter().zip(&cells) {
+            let line: Vec<String> = row
+                .iter()
+                .zip(texts)
+                .zip(&widths)
+                .map(|((cell, text), w)| if cell.is_numeric() { format!("{:>w$}", text, w = w) } else { format!("{:<w$}", text, w = w) })
+                .collect();
+            println!("{}", line.join("  ").trim_end());
+        }
+    }
+
+    fn print_json(&self) {
+        println!("{:#}", self.to_json());
+    }
+
+    /// An array with one object per row, keyed by column.
+    fn to_json(&self) -> Value {
+        self.rows
+            .iter()
+            .map(|row| {
+                let fields: Map<String, Value> =
+                    self.columns.iter().zip(row).map(|((key, _), cell)| (key.to_string(), cell.to_json())).collect();
+                Value::Object(fields)
+            })
+            .collect()
+    }
+
+    fn print_csv(&self) {
+        print!("{}", self.to_csv());
+    }
+
+    fn to_csv(&self) -> String {
+        let header: Vec<String> = self.columns.iter().map(|(key, _)| csv_field(key)).collect();
+        let mut csv = header.join(",") + "\n";
+        for row in &self.rows {
+            let fields: Vec<String> = row.iter().map(|cell| csv_field(&cell.to_text())).collect();
+            csv += &(fields.join(",") + "\n");
+        }
+        csv
+    }
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> Table {
+        let mut table = Table::new(&[("name", "Name"), ("pies", "Pies"), ("value", "Value"), ("irr_percent", "IRR %")]);
+        table.rows.push(vec![Cell::Text("Growth, \"core\"".to_string()), Cell::Integer(3), Cell::Number(1234.5678, 2), Cell::Missing]);
+        table.rows.push(vec![Cell::Text("C:\\pies\nnew".to_string()), Cell::Integer(0), Cell::Number(f64::NAN, 2), Cell::Number(f64::INFINITY, 2)]);
+        table
+    }
+
+    #[test]
+    fn csv_quotes_fields_that_need_it() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(
+            table().to_csv(),
+            "name,pies,value,irr_percent\n\"Growth, \"\"core\"\"\",3,1234.57,\n\"C:\\pies\nnew\",0,NaN,inf\n"
+        );
+    }
+
+    #[test]
+    fn json_survives_awkward_names_and_figures() {
+        let json = table().to_json();
+        let parsed: Value = serde_json::from_str(&format!("{:#}", json)).unwrap();
+        assert_eq!(parsed, json);
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {"name": "Growth, \"core\"", "pies": 3, "value": 1234.57, "irr_percent": null},
+                {"name": "C:\\pies\nnew", "pies": 0, "value": null, "irr_percent": null},
+            ])
+        );
+        let keys: Vec<&String> = json[0].as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["name", "pies", "value", "irr_percent"]);
+        assert_eq!(Table::new(&[("name", "Name")]).to_json(), serde_json::json!([]));
+    }
+
+    #[test]
+    fn errors_map_to_exit_codes() {
+        assert_eq!(CliError::from(ApiError::RateLimited).code, EXIT_RATE_LIMITED);
+        assert_eq!(CliError::from(ApiError::Status { status: 500, body: String::new() }).code, EXIT_API);
+        assert_eq!(CliError::from(ApiError::Business { body: String::new() }).code, EXIT_API);
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "history/pie_1.jsonl");
+        assert_eq!(CliError::from(io).code, EXIT_IO);
+        let config = CliError::from(ConfigError::MissingToken);
+        assert_eq!((config.code, config.message.as_str()), (EXIT_CONFIG, "TRADE212_API_TOKEN must be set"));
+        // Distinct, and clear of success and clap's usage error
+        let codes: std::collections::HashSet<i32> = [0, 2, EXIT_API, EXIT_RATE_LIMITED, EXIT_IO, EXIT_CONFIG].into();
+        assert_eq!(codes.len(), 6);
+    }
+}
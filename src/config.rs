@@ -0,0 +1,181 @@
+use std::env;
+use std::fmt;
+use std::net::SocketAddr;
+
+use crate::api::{DEMO_BASE_URL, LIVE_BASE_URL};
+
+/// Which Trading 212 server the dashboard talks to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Environment {
+    Live,
+    Demo,
+    /// Any other server, e.g. a local mock used in integration tests.
+    Custom(String),
+}
+
+impl Environment {
+    pub fn base_url(&self) -> &str {
+        match self {
+            Environment::Live => LIVE_BASE_URL,
+            Environment::Demo => DEMO_BASE_URL,
+            Environment::Custom(url) => url,
+        }
+    }
+}
+
+impl fmt::Display for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Environment::Live => write!(f, "Live"),
+            Environment::Demo => write!(f, "Demo"),
+            Environment::Custom(url) => write!(f, "Custom ({})", url),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    MissingToken,
+    InvalidEnvironment(String),
+    MissingBaseUrl,
+    InvalidListenAddress(String),
+    InvalidRiskFreeRate(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::MissingToken => write!(f, "TRADE212_API_TOKEN must be set"),
+            ConfigError::InvalidEnvironment(value) => write!(
+                f,
+                "TRADE212_ENV must be one of live, demo or custom (got {:?})",
+                value
+            ),
+            ConfigError::MissingBaseUrl => {
+                write!(f, "TRADE212_BASE_URL must be set when TRADE212_ENV=custom")
+            }
+            ConfigError::InvalidListenAddress(value) => write!(
+                f,
+                "PIERS_LISTEN must be a loopback address like 127.0.0.1:9184 (got {:?})",
+                value
+            ),
+            ConfigError::InvalidRiskFreeRate(value) => write!(
+                f,
+                "PIERS_RISK_FREE_RATE must be an annual rate in percent like 4.5 (got {:?})",
+                value
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Settings read from the process environment (and `.env`, once `dotenv` has run).
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub token: String,
+    pub environment: Environment,
+}
+
+impl Config {
+    /// Reads `TRADE212_API_TOKEN`, `TRADE212_ENV` (`live` by default) and `TRADE212_BASE_URL`.
+    ///
+    /// Setting `TRADE212_BASE_URL` without `TRADE212_ENV` selects a custom environment.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_vars(|name| env::var(name).ok())
+    }
+
+    /// [`Config::from_env`] with the variables looked up through `var`.
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let token = var("TRADE212_API_TOKEN").ok_or(ConfigError::MissingToken)?;
+        let base_url = var("TRADE212_BASE_URL").filter(|url| !url.is_empty());
+
+        let environment = match var("TRADE212_ENV").as_deref().map(str::to_lowercase) {
+            None => match base_url {
+                Some(url) => Environment::Custom(url),
+                None => Environment::Live,
+            },
+            Some(value) => match value.as_str() {
+                "live" => Environment::Live,
+                "demo" => Environment::Demo,
+                "custom" => Environment::Custom(base_url.ok_or(ConfigError::MissingBaseUrl)?),
+                _ => return Err(ConfigError::InvalidEnvironment(value)),
+            },
+        };
+
+        Ok(Self { token, environment })
+    }
+}
+
+/// Reads `PIERS_LISTEN`, the address of the local HTTP server; unset or empty disables it.
+///
+/// Only loopback addresses are accepted: the server has no authentication.
+pub fn listen_address_from_env() -> Result<Option<SocketAddr>, ConfigError> {
+    let Some(value) = env::var("PIERS_LISTEN").ok().filter(|value| !value.is_empty()) else {
+        return Ok(None);
+    };
+    match value.parse::<SocketAddr>() {
+        Ok(address) if address.ip().is_loopback() => Ok(Some(address)),
+        _ => Err(ConfigError::InvalidListenAddress(value)),
+    }
+}
+
+/// Reads `PIERS_RISK_FREE_RATE`, the annual risk-free rate in percent for Sharpe and Sortino
+/// ratios, as a fraction; 0 when unset or empty.
+pub fn risk_free_rate_from_env() -> Result<f64, ConfigError> {
+    let Some(value) = env::var("PIERS_RISK_FREE_RATE").ok().filter(|value| !value.is_empty()) else {
+        return Ok(0.0);
+    };
+    match value.trim().trim_end_matches('%').parse::<f64>() {
+        Ok(percent) if percent.is_finite() && percent > -100.0 => Ok(percent / 100.0),
+        _ => Err(ConfigError::InvalidRiskFreeRate(value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(vars: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        Config::from_vars(|name| vars.iter().find(|(key, _)| *key == name).map(|(_, value)| value.to_string()))
+    }
+
+    #[test]
+    fn picks_the_environment() {
+        let token = ("TRADE212_API_TOKEN", "secret");
+        let live = config(&[token]).unwrap();
+        assert_eq!((live.token.as_str(), live.environment), ("secret", Environment::Live));
+        assert_eq!(config(&[token, ("TRADE212_ENV", "live")]).unwrap().environment, Environment::Live);
+        assert_eq!(config(&[token, ("TRADE212_ENV", "Demo")]).unwrap().environment, Environment::Demo);
+        assert_eq!(Environment::Demo.base_url(), DEMO_BASE_URL);
+
+        let custom = config(&[token, ("TRADE212_ENV", "custom"), ("TRADE212_BASE_URL", "http://127.0.0.1:8080")]).unwrap();
+        assert_eq!(custom.environment, Environment::Custom("http://127.0.0.1:8080".to_string()));
+        assert_eq!(custom.environment.base_url(), "http://127.0.0.1:8080");
+
+        // A base URL on its own means a custom server
+        let implied = config(&[token, ("TRADE212_BASE_URL", "http://127.0.0.1:8080")]).unwrap();
+        assert_eq!(implied.environment, Environment::Custom("http://127.0.0.1:8080".to_string()));
+        // ...but not next to an explicit live or demo
+        let demo = config(&[token, ("TRADE212_ENV", "demo"), ("TRADE212_BASE_URL", "http://127.0.0.1:8080")]).unwrap();
+        assert_eq!(demo.environment, Environment::Demo);
+    }
+
+    #[test]
+    fn rejects_incomplete_settings() {
+        let token = ("TRADE212_API_TOKEN", "secret");
+        assert!(matches!(config(&[]), Err(ConfigError::MissingToken)));
+        assert!(matches!(config(&[token, ("TRADE212_ENV", "custom")]), Err(ConfigError::MissingBaseUrl)));
+        assert!(matches!(
+            config(&[token, ("TRADE212_ENV", "custom"), ("TRADE212_BASE_URL", "")]),
+            Err(ConfigError::MissingBaseUrl)
+        ));
+        match config(&[token, ("TRADE212_ENV", "staging")]) {
+            Err(error @ ConfigError::InvalidEnvironment(_)) => assert_eq!(
+                error.to_string(),
+                "TRADE212_ENV must be one of live, demo or custom (got \"staging\")"
+            ),
+            other => panic!("{other:?}"),
+        }
+    }
+}
@@ -0,0 +1,460 @@
+//! Delivery of fired alerts to webhooks and an SMTP relay, configured under `delivery` in
+//! `alerts.json`.
+//!
+//! Alerts are queued to a background task so a slow or unreachable target never holds up polling.
+//! Failed deliveries are retried with a growing delay, and an alert for the same rule and subject
+//! as one delivered to a target within the de-duplication window isn't sent to it again.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::mpsc;
+
+use crate::alerts::Alert;
+use crate::diagnostics;
+
+/// Delay before the first retry; doubled for every further attempt.
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// How long a single webhook request may take.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The `delivery` section of `alerts.json`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeliveryConfig {
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+    pub smtp: Option<SmtpConfig>,
+    /// Further attempts after a failed delivery.
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// An alert for the same rule and subject as one delivered to a target this many minutes ago
+    /// or less isn't delivered to it again.
+    #[serde(default = "default_dedupe_minutes")]
+    pub dedupe_minutes: f64,
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+fn default_dedupe_minutes() -> f64 {
+    60.0
+}
+
+impl Default for DeliveryConfig {
+    fn default() -> Self {
+        Self {
+            webhooks: Vec::new(),
+            smtp: None,
+            retries: default_retries(),
+            dedupe_minutes: default_dedupe_minutes(),
+        }
+    }
+}
+
+impl DeliveryConfig {
+    pub fn is_empty(&self) -> bool {
+        self.webhooks.is_empty() && self.smtp.is_none()
+    }
+}
+
+/// Shape of the body posted to a webhook.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookFormat {
+    /// `{"id", "timestamp", "title", "message"}`
+    #[default]
+    Json,
+    /// `{"text"}`, as Slack incoming webhooks expect.
+    Slack,
+    /// `{"content"}`, as Discord webhooks expect.
+    Discord,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    #[serde(default)]
+    pub format: WebhookFormat,
+    /// A JSON body to post instead of `format`'s, in which `{{title}}`, `{{message}}`, `{{timestamp}}`
+    /// and `{{id}}` are replaced, e.g. `{"msg": "{{title}}: {{message}}"}`.
+    pub template: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SmtpConfig {
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    /// Upgrade the connection with STARTTLS; plain SMTP otherwise, as a local relay usually speaks.
+    #[serde(default)]
+    pub starttls: bool,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+fn default_smtp_port() -> u16 {
+    25
+}
+
+/// Where an alert is delivered.
+#[derive(Debug, Clone)]
+enum Target {
+    Webhook(Webhook),
+    Smtp(SmtpConfig),
+}
+
+impl Target {
+    fn all(config: &DeliveryConfig) -> Vec<Target> {
+        let webhooks = config.webhooks.iter().cloned().map(Target::Webhook);
+        webhooks.chain(config.smtp.clone().map(Target::Smtp)).collect()
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Target::Webhook(webhook) => format!("webhook {}", webhook.url),
+            Target::Smtp(smtp) => format!("SMTP relay {}:{}", smtp.host, smtp.port),
+        }
+    }
+
+    async fn deliver(&self, http: &reqwest::Client, alert: &Alert) -> Result<(), DeliveryError> {
+        match self {
+            Target::Webhook(webhook) => post_webhook(http, webhook, alert).await,
+            Target::Smtp(smtp) => send_mail(smtp, alert).await,
+        }
+    }
+}
+
+/// A target (by position in [`Target::all`]) with the rule and subject of an alert.
+type DedupeKey = (usize, usize, String);
+
+/// What each target has been sent, so the same condition isn't delivered twice in the window.
+#[derive(Debug, Default)]
+struct Dedupe {
+    /// When each key was last delivered.
+    delivered: HashMap<DedupeKey, f64>,
+    /// Keys whose delivery is still being attempted.
+    in_flight: HashSet<DedupeKey>,
+}
+
+/// Queue of alerts waiting to be delivered; cheap to clone.
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    sender: mpsc::UnboundedSender<Alert>,
+}
+
+impl Delivery {
+    /// Starts the background task delivering to the targets in `config`. Must be called within a
+    /// Tokio runtime.
+    pub fn spawn(config: DeliveryConfig) -> Self {
+        Self::spawn_with_retry_delay(config, RETRY_DELAY)
+    }
+
+    fn spawn_with_retry_delay(config: DeliveryConfig, retry_delay: Duration) -> Self {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Alert>();
+        tokio::spawn(async move {
+            let http = reqwest::Client::builder()
+                .timeout(WEBHOOK_TIMEOUT)
+                .build()
+                .unwrap_or_default();
+            let targets = Target::all(&config);
+            let dedupe = Arc::new(Mutex::new(Dedupe::default()));
+            let window = config.dedupe_minutes * 60.0;
+            while let Some(alert) = receiver.recv().await {
+                for (position, target) in targets.iter().enumerate() {
+                    let key = (position, alert.rule, alert.subject.clone());
+                    {
+                        let mut dedupe = dedupe.lock().unwrap_or_else(PoisonError::into_inner);
+                        dedupe.delivered.retain(|_, timestamp| alert.timestamp - *timestamp < window);
+                        if dedupe.delivered.contains_key(&key) || !dedupe.in_flight.insert(key.clone()) {
+                            continue;
+                        }
+                    }
+
+                    let (http, target, alert, dedupe) = (http.clone(), target.clone(), alert.clone(), dedupe.clone());
+                    let retries = config.retries;
+                    tokio::spawn(async move {
+                        let name = target.name();
+                        let delivered = with_retries(&name, retries, retry_delay, || target.deliver(&http, &alert)).await;
+                        // Only a delivery that went through holds back the next alert for this key
+                        let mut dedupe = dedupe.lock().unwrap_or_else(PoisonError::into_inner);
+                        dedupe.in_flight.remove(&key);
+                        if delivered {
+                            dedupe.delivered.insert(key, alert.timestamp);
+                        }
+                    });
+                }
+            }
+        });
+        Self { sender }
+    }
+
+    pub fn send(&self, alerts: &[Alert]) {
+        for alert in alerts {
+            // The task only ends with the runtime, when nothing is left to deliver anyway
+            let _ = self.sender.send(alert.clone());
+        }
+    }
+}
+
+/// Why a delivery attempt failed; only some failures are worth retrying.
+#[derive(Debug)]
+struct DeliveryError {
+    message: String,
+    retry: bool,
+}
+
+/// Runs `attempt` until it succeeds, fails for good or has been retried `retries` times, waiting
+/// `delay` before the first retry and twice as long before each further one; true on success.
+async fn with_retries<F, Fut>(target: &str, retries: u32, mut delay: Duration, mut attempt: F) -> bool
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), DeliveryError>>,
+{
+    for attempt_number in 0..=retries {
+        match attempt().await {
+            Ok(()) => return true,
+            Err(e) if e.retry && attempt_number < retries => {
+                diagnostics::report(format!(
+                    "Alert delivery to {} failed, retrying in {:?}: {}",
+                    target, delay, e.message
+                ));
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) => {
+                diagnostics::report(format!("Alert delivery to {} failed: {}", target, e.message));
+                return false;
+            }
+        }
+    }
+    false
+}
+
+/// Identifies an alert across retries so a receiver can drop repeats of one it already handled.
+fn alert_id(alert: &Alert) -> String {
+    let mut hasher = DefaultHasher::new();
+    alert.title.hash(&mut hasher);
+    alert.message.hash(&mut hasher);
+    format!("{}-{:016x}", alert.timestamp as i64, hasher.finish())
+}
+
+fn webhook_body(webhook: &Webhook, alert: &Alert) -> Result<serde_json::Value, String> {
+    let id = alert_id(alert);
+    if let Some(template) = &webhook.template {
+        // Substitute JSON-escaped text, without the quotes, so values can sit inside template strings
+        let escape = |text: &str| {
+            let quoted = serde_json::Value::from(text).to_string();
+            quoted[1..quoted.len() - 1].to_string()
+        };
+        let body = template
+            .replace("{{title}}", &escape(&alert.title))
+            .replace("{{message}}", &escape(&alert.message))
+            .replace("{{timestamp}}", &(alert.timestamp as i64).to_string())
+            .replace("{{id}}", &escape(&id));
+        return serde_json::from_str(&body).map_err(|e| format!("template is not valid JSON: {}", e));
+    }
+    Ok(match webhook.format {
+        WebhookFormat::Json => json!({
+            "id": id,
+            "timestamp": alert.timestamp,
+            "title": alert.title,
+            "message": alert.message,
+        }),
+        WebhookFormat::Slack => json!({ "text": format!("*{}*\n{}", alert.title, alert.message) }),
+        WebhookFormat::Discord => json!({ "content": format!("**{}**\n{}", alert.title, alert.message) }),
+    })
+}
+
+async fn post_webhook(http: &reqwest::Client, webhook: &Webhook, alert: &Alert) -> Result<(), DeliveryError> {
+    let body = webhook_body(webhook, alert).map_err(|message| DeliveryError { message, retry: false })?;
+    let response = http
+        .post(&webhook.url)
+        .header("Idempotency-Key", alert_id(alert))
+        .json(&body)
+        .send()
+        .await
+        .map_err(|e| DeliveryError { message: e.to_string(), retry: true })?;
+    let status = response.status();
+    if status.is_success() {
+        Ok(())
+    } else {
+        // Other client errors mean the request itself is wrong, so repeating it won't help
+        Err(DeliveryError {
+            message: format!("HTTP {}", status),
+            retry: status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS,
+        })
+    }
+}
+
+/// The email for `alert`, with the alert's id as its Message-ID.
+fn build_mail(smtp: &SmtpConfig, alert: &Alert) -> Result<Message, DeliveryError> {
+    let invalid = |message: String| DeliveryError { message, retry: false };
+    let mut builder = Message::builder()
+        .from(smtp.from.parse::<Mailbox>().map_err(|e| invalid(format!("from {:?}: {}", smtp.from, e)))?)
+        .subject(format!("[pie_top] {}", alert.title))
+        .message_id(Some(format!("<{}@pie_top>", alert_id(alert))));
+    for to in &smtp.to {
+        builder = builder.to(to.parse::<Mailbox>().map_err(|e| invalid(format!("to {:?}: {}", to, e)))?);
+    }
+    let time = chrono::DateTime::from_timestamp(alert.timestamp as i64, 0).unwrap_or_default();
+    builder
+        .body(format!("{}\n\n{}\n", alert.message, time.format("%Y-%m-%d %H:%M:%S UTC")))
+        .map_err(|e| invalid(e.to_string()))
+}
+
+async fn send_mail(smtp: &SmtpConfig, alert: &Alert) -> Result<(), DeliveryError> {
+    let invalid = |message: String| DeliveryError { message, retry: false };
+    let email = build_mail(smtp, alert)?;
+    let mut transport = if smtp.starttls {
+        AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host).map_err(|e| invalid(e.to_string()))?
+    } else {
+        AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&smtp.host)
+    }
+    .port(smtp.port);
+    if let Some(username) = &smtp.username {
+        transport = transport.credentials(Credentials::new(username.clone(), smtp.password.clone().unwrap_or_default()));
+    }
+    transport
+        .build()
+        .send(email)
+        .await
+        .map(|_| ())
+        .map_err(|e| DeliveryError { message: e.to_string(), retry: !e.is_permanent() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use axum::{Json, Router};
+
+    /// Answers each request with the next of `statuses`, then with the last one, and keeps the
+    /// bodies of the requests it accepted.
+    #[derive(Clone)]
+    struct Receiver {
+        statuses: Arc<Vec<StatusCode>>,
+        requests: Arc<AtomicUsize>,
+        accepted: Arc<Mutex<Vec<serde_json::Value>>>,
+    }
+
+    async fn receive(State(receiver): State<Receiver>, Json(body): Json<serde_json::Value>) -> StatusCode {
+        let request = receiver.requests.fetch_add(1, Ordering::SeqCst);
+        let status = receiver.statuses[request.min(receiver.statuses.len() - 1)];
+        if status.is_success() {
+            receiver.accepted.lock().unwrap().push(body);
+        }
+        status
+    }
+
+    /// Starts a webhook receiver and a delivery queue posting to it, retrying without delay.
+    async fn deliver_to(statuses: &[StatusCode]) -> (Delivery, Receiver) {
+        let receiver = Receiver {
+            statuses: Arc::new(statuses.to_vec()),
+            requests: Arc::new(AtomicUsize::new(0)),
+            accepted: Arc::new(Mutex::new(Vec::new())),
+        };
+        let router = Router::new().route("/alerts", post(receive)).with_state(receiver.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let config = DeliveryConfig {
+            webhooks: vec![Webhook { url, format: WebhookFormat::Json, template: None }],
+            ..DeliveryConfig::default()
+        };
+        (Delivery::spawn_with_retry_delay(config, Duration::from_millis(1)), receiver)
+    }
+
+    fn alert(subject: &str, message: &str) -> Alert {
+        Alert {
+            timestamp: 1_700_000_000.0,
+            rule: 0,
+            subject: subject.to_string(),
+            title: "Growth return above 10%".to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    /// Waits until the receiver has had `requests` requests, then a little longer for any extra.
+    async fn settle(receiver: &Receiver, requests: usize) -> usize {
+        for _ in 0..500 {
+            if receiver.requests.load(Ordering::SeqCst) >= requests {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        receiver.requests.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried_until_delivered_once() {
+        let (delivery, receiver) = deliver_to(&[StatusCode::INTERNAL_SERVER_ERROR, StatusCode::OK]).await;
+        delivery.send(&[alert("1", "Return is now 12.00%")]);
+        assert_eq!(settle(&receiver, 2).await, 2);
+        let accepted = receiver.accepted.lock().unwrap();
+        assert_eq!(accepted.len(), 1);
+        assert_eq!(accepted[0]["message"], "Return is now 12.00%");
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried_nor_remembered() {
+        let (delivery, receiver) = deliver_to(&[StatusCode::BAD_REQUEST, StatusCode::OK]).await;
+        delivery.send(&[alert("1", "Return is now 12.00%")]);
+        assert_eq!(settle(&receiver, 1).await, 1);
+        // It never arrived, so the same alert firing again is sent
+        delivery.send(&[alert("1", "Return is now 12.00%")]);
+        assert_eq!(settle(&receiver, 2).await, 2);
+        assert_eq!(receiver.accepted.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn repeats_of_a_delivered_condition_are_dropped() {
+        let (delivery, receiver) = deliver_to(&[StatusCode::OK]).await;
+        delivery.send(&[alert("1", "Return is now 12.00%")]);
+        assert_eq!(settle(&receiver, 1).await, 1);
+        // Same rule and pie with a different value, then another pie
+        delivery.send(&[alert("1", "Return is now 12.50%"), alert("2", "Return is now 11.00%")]);
+        assert_eq!(settle(&receiver, 2).await, 2);
+        let accepted = receiver.accepted.lock().unwrap();
+        assert_eq!(accepted[1]["message"], "Return is now 11.00%");
+    }
+
+    #[test]
+    fn mail_has_the_alert_as_subject_and_body() {
+        let smtp = SmtpConfig {
+            host: "localhost".to_string(),
+            port: 25,
+            starttls: false,
+            username: None,
+            password: None,
+            from: "pie_top <pies@example.com>".to_string(),
+            to: vec!["me@example.com".to_string(), "you@example.com".to_string()],
+        };
+        let alert = alert("1", "Return is now 12.00%");
+        let mail = String::from_utf8(build_mail(&smtp, &alert).unwrap().formatted()).unwrap();
+        assert!(mail.contains("Subject: [pie_top] Growth return above 10%"), "{mail}");
+        assert!(mail.contains("To: me@example.com, you@example.com"), "{mail}");
+        assert!(mail.contains(&format!("Message-ID: <{}@pie_top>", alert_id(&alert))), "{mail}");
+        assert!(mail.contains("Return is now 12.00%\r\n\r\n2023-11-14 22:13:20 UTC"), "{mail}");
+
+        let invalid = SmtpConfig { from: "not an address".to_string(), ..smtp };
+        assert!(build_mail(&invalid, &alert).is_err_and(|e| !e.retry));
+    }
+}
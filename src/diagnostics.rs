@@ -0,0 +1,73 @@
+//! Problems noticed by the background tasks (poller, history, snapshots, HTTP server). They go to
+//! stderr, except while the terminal dashboard owns the screen: then they are appended to a log
+//! file and the latest one is shown in its footer.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::{Mutex, PoisonError};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+pub const DEFAULT_LOG_PATH: &str = "piers.log";
+
+struct Sink {
+    file: Option<File>,
+    last: Option<(DateTime<Utc>, String)>,
+}
+
+static SINK: Mutex<Sink> = Mutex::new(Sink { file: None, last: None });
+
+fn sink() -> std::sync::MutexGuard<'static, Sink> {
+    SINK.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Reports `message` to stderr or, after [`log_to_file`], to the log file.
+pub fn report(message: impl Into<String>) {
+    let message = message.into();
+    let now = Utc::now();
+    let mut sink = sink();
+    match &mut sink.file {
+        Some(file) => {
+            // Nowhere left to report a failing log file
+            let _ = writeln!(file, "{} {}", now.to_rfc3339_opts(SecondsFormat::Secs, true), message);
+        }
+        None => eprintln!("{}", message),
+    }
+    sink.last = Some((now, message));
+}
+
+/// Appends the following reports to `path` instead of writing them to stderr.
+pub fn log_to_file(path: &str) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    sink().file = Some(file);
+    Ok(())
+}
+
+/// Goes back to reporting on stderr.
+pub fn log_to_stderr() {
+    sink().file = None;
+}
+
+/// The latest report and when it was made.
+pub fn last() -> Option<(DateTime<Utc>, String)> {
+    sink().last.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_go_to_the_log_file_while_one_is_set() {
+        let path = std::env::temp_dir().join(format!("piers-diagnostics-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        log_to_file(path.to_str().unwrap()).unwrap();
+        report("Poll failed: HTTP Error: 500");
+        log_to_stderr();
+
+        let logged = std::fs::read_to_string(&path).unwrap();
+        assert!(logged.trim_end().ends_with(" Poll failed: HTTP Error: 500"), "{logged}");
+        assert!(last().is_some());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,286 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::Datelike;
+
+use crate::models::{DividendItem, Pie, Position};
+
+const SECONDS_PER_YEAR: f64 = 365.0 * 86400.0;
+
+/// Dividends paid per calendar month (UTC), keyed by `(year, month)`.
+pub fn monthly_totals(items: &[DividendItem]) -> BTreeMap<(i32, u32), f64> {
+    let mut months = BTreeMap::new();
+    for item in items {
+        let Some(paid_on) = item.paid_on_timestamp().and_then(|t| chrono::DateTime::from_timestamp(t as i64, 0)) else {
+            continue;
+        };
+        *months.entry((paid_on.year(), paid_on.month())).or_default() += item.amount;
+    }
+    months
+}
+
+/// Total dividends per ticker, largest first.
+pub fn totals_by_ticker(items: &[DividendItem]) -> Vec<(String, f64)> {
+    let mut totals: HashMap<&str, f64> = HashMap::new();
+    for item in items {
+        *totals.entry(&item.ticker).or_default() += item.amount;
+    }
+    let mut totals: Vec<(String, f64)> = totals.into_iter().map(|(t, a)| (t.to_string(), a)).collect();
+    totals.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    totals
+}
+
+/// Dividends paid in the 365 days up to `now`.
+pub fn trailing_12_months(items: &[DividendItem], now: f64) -> Vec<DividendItem> {
+    items
+        .iter()
+        .filter(|item| item.paid_on_timestamp().is_some_and(|t| t > now - SECONDS_PER_YEAR && t <= now))
+        .cloned()
+        .collect()
+}
+
+/// Splits each dividend between the pies holding its ticker, by quantity currently held.
+///
+/// The API doesn't say which pie a dividend was paid into, so this uses today's holdings; the
+/// part held outside pies (per `positions`) is left unattributed.
+pub fn attribute_to_pies(items: &[DividendItem], pies: &[Pie], positions: &[Position]) -> HashMap<u64, f64> {
+    let mut holders: HashMap<&str, Vec<(Option<u64>, f64)>> = HashMap::new();
+    for pie in pies {
+        for instrument in &pie.instruments {
+            holders
+                .entry(&instrument.ticker)
+                .or_default()
+                .push((Some(pie.id), instrument.owned_quantity));
+        }
+    }
+    for position in positions {
+        holders
+            .entry(&position.ticker)
+            .or_default()
+            .push((None, position.outside_pies_quantity()));
+    }
+
+    let mut per_pie = HashMap::new();
+    for item in items {
+        let Some(ticker_holders) = holders.get(item.ticker.as_str()) else {
+            continue;
+        };
+        let total_quantity: f64 = ticker_holders.iter().map(|(_, q)| q).sum();
+        if total_quantity <= 0.0 {
+            continue;
+        }
+        for (pie_id, quantity) in ticker_holders {
+            if let Some(pie_id) = pie_id {
+                *per_pie.entry(*pie_id).or_default() += item.amount * quantity / total_quantity;
+            }
+        }
+    }
+    per_pie
+}
+
+/// Running total of dividends over time, as `[timestamp, cumulative amount]`.
+pub fn cumulative(items: &[DividendItem]) -> Vec<[f64; 2]> {
+    let mut paid: Vec<(f64, f64)> = items
+        .iter()
+        .filter_map(|item| item.paid_on_timestamp().map(|t| (t, item.amount)))
+        .collect();
+    paid.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    let mut total = 0.0;
+    paid.into_iter()
+        .map(|(timestamp, amount)| {
+            total += amount;
+            [timestamp, total]
+        })
+        .collect()
+}
+
+/// Trailing-12-month dividends as a fraction of `invested`.
+pub fn yield_on_invested(trailing_amount: f64, invested: f64) -> Option<f64> {
+    (invested > 0.0).then(|| trailing_amount / invested)
+}
+
+/// An expected future dividend payment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForecastPayment {
+    pub ticker: String,
+    pub expected_on: f64, // Unix timestamp in seconds
+    pub amount: f64,
+}
+
+/// Expected dividends over the 12 months after `now`.
+///
+/// Every payment of the last 12 months is assumed to recur one year later, with the same
+/// amount per share, on the quantity held today (in pies and outside them).
+pub fn forecast(items: &[DividendItem], pies: &[Pie], positions: &[Position], now: f64) -> Vec<ForecastPayment> {
+    let mut held: HashMap<&str, f64> = HashMap::new();
+    for pie in pies {
+        for instrument in &pie.instruments {
+            *held.entry(&instrument.ticker).or_default() += instrument.owned_quantity;
+        }
+    }
+    for position in positions {
+        *held.entry(&position.ticker).or_default() += position.outside_pies_quantity();
+    }
+
+    let mut payments: Vec<ForecastPayment> = trailing_12_months(items, now)
+        .into_iter()
+        .filter(|item| item.quantity > 0.0)
+        .filter_map(|item| {
+            let quantity = held.get(item.ticker.as_str()).copied().filter(|q| *q > 0.0)?;
+            let expected_on = item.paid_on_timestamp()? + SECONDS_PER_YEAR;
+            Some(ForecastPayment {
+                amount: item.amount / item.quantity * quantity,
+                ticker: item.ticker,
+                expected_on,
+            })
+        })
+        .collect();
+    payments.sort_by(|a, b| a.expected_on.partial_cmp(&b.expected_on).unwrap_or(std::cmp::Ordering::Equal));
+    payments
+}
+
+/// Forecast totals per calendar month (UTC), keyed by `(year, month)`.
+pub fn forecast_monthly_totals(payments: &[ForecastPayment]) -> BTreeMap<(i32, u32), f64> {
+    let mut months = BTreeMap::new();
+    for payment in payments {
+        let Some(expected_on) = chrono::DateTime::from_timestamp(payment.expected_on as i64, 0) else {
+            continue;
+        };
+        *months.entry((expected_on.year(), expected_on.month())).or_default() += payment.amount;
+    }
+    months
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn dividend(ticker: &str, paid_on: &str, amount: f64, quantity: f64) -> DividendItem {
+        DividendItem {
+            ticker: ticker.to_string(),
+            amount,
+            gross_amount_per_share: 0.0,
+            quantity,
+            paid_on: paid_on.to_string(),
+            reference: None,
+            kind: None,
+        }
+    }
+
+    /// A pie holding `(ticker, quantity)` pairs.
+    fn pie(id: u64, holdings: &[(&str, f64)]) -> Pie {
+        let result = json!({"priceAvgInvestedValue": 0.0, "priceAvgValue": 0.0, "priceAvgResult": 0.0, "priceAvgResultCoef": 0.0});
+        let instruments: Vec<_> = holdings
+            .iter()
+            .map(|(ticker, quantity)| json!({
+                "ticker": ticker, "ownedQuantity": quantity, "expectedShare": 0.5, "currentShare": 0.5, "result": result
+            }))
+            .collect();
+        serde_json::from_value(json!({
+            "id": id, "cash": 0.0, "dividendDetails": {"gained": 0.0, "reinvested": 0.0, "inCash": 0.0},
+            "result": result, "progress": null, "status": null, "created_at": null, "name": null, "instruments": instruments
+        }))
+        .unwrap()
+    }
+
+    fn position(ticker: &str, quantity: f64, pie_quantity: f64) -> Position {
+        Position {
+            ticker: ticker.to_string(),
+            quantity,
+            average_price: 1.0,
+            current_price: 1.0,
+            ppl: 0.0,
+            fx_ppl: None,
+            pie_quantity,
+        }
+    }
+
+    fn timestamp(value: &str) -> f64 {
+        chrono::DateTime::parse_from_rfc3339(value).unwrap().timestamp() as f64
+    }
+
+    #[test]
+    fn months_are_calendar_months_in_utc() {
+        let items = [
+            dividend("VWRL", "2023-12-31T23:30:00Z", 1.0, 1.0),
+            // Still 2023 in UTC
+            dividend("VWRL", "2024-01-01T00:10:00+01:00", 2.0, 1.0),
+            dividend("VWRL", "2024-01-15T12:00:00Z", 4.0, 1.0),
+            dividend("VWRL", "not a date", 8.0, 1.0),
+        ];
+        let months = monthly_totals(&items);
+        assert_eq!(months.into_iter().collect::<Vec<_>>(), [((2023, 12), 3.0), ((2024, 1), 4.0)]);
+    }
+
+    #[test]
+    fn trailing_12_months_cover_the_last_365_days() {
+        let now = timestamp("2024-06-01T00:00:00Z");
+        let items = [
+            dividend("A", "2023-06-02T00:00:00Z", 1.0, 1.0),
+            dividend("B", "2023-06-02T00:00:01Z", 1.0, 1.0),
+            dividend("C", "2024-06-01T00:00:00Z", 1.0, 1.0),
+            dividend("D", "2024-06-01T00:00:01Z", 1.0, 1.0),
+        ];
+        let tickers: Vec<String> = trailing_12_months(&items, now).into_iter().map(|item| item.ticker).collect();
+        assert_eq!(tickers, ["B", "C"]);
+    }
+
+    #[test]
+    fn a_ticker_in_two_pies_is_split_by_quantity() {
+        let pies = [pie(1, &[("VWRL", 3.0)]), pie(2, &[("VWRL", 1.0), ("AAPL", 2.0)])];
+        // Two more shares held outside pies keep their part
+        let positions = [position("VWRL", 6.0, 4.0)];
+        let items = [dividend("VWRL", "2024-03-01T00:00:00Z", 12.0, 6.0), dividend("MSFT", "2024-03-01T00:00:00Z", 5.0, 1.0)];
+        let per_pie = attribute_to_pies(&items, &pies, &positions);
+        assert_eq!(per_pie.len(), 2);
+        assert!((per_pie[&1] - 6.0).abs() < 1e-9);
+        assert!((per_pie[&2] - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn cumulative_totals_follow_payment_dates() {
+        let items = [
+            dividend("A", "2024-02-01T00:00:00Z", 2.0, 1.0),
+            dividend("B", "2024-01-01T00:00:00Z", 1.0, 1.0),
+            dividend("C", "not a date", 4.0, 1.0),
+        ];
+        let series = cumulative(&items);
+        assert_eq!(series, [[timestamp("2024-01-01T00:00:00Z"), 1.0], [timestamp("2024-02-01T00:00:00Z"), 3.0]]);
+    }
+
+    #[test]
+    fn forecast_repeats_last_years_payments_on_todays_holdings() {
+        let now = timestamp("2024-06-01T00:00:00Z");
+        let pies = [pie(1, &[("VWRL", 4.0), ("SOLD", 0.0)])];
+        let positions = [position("VWRL", 6.0, 4.0)];
+        let items = [
+            // 0.5 a share on 4 shares; 6 are held now
+            dividend("VWRL", "2024-03-01T00:00:00Z", 2.0, 4.0),
+            dividend("VWRL", "2023-09-15T00:00:00Z", 1.0, 4.0),
+            // Too old to repeat
+            dividend("VWRL", "2023-05-01T00:00:00Z", 9.0, 4.0),
+            // Nothing held any more, or never in a pie or position
+            dividend("SOLD", "2024-04-01T00:00:00Z", 3.0, 1.0),
+            dividend("GONE", "2024-04-01T00:00:00Z", 3.0, 1.0),
+            // Without a quantity the amount per share is unknown
+            dividend("VWRL", "2024-05-01T00:00:00Z", 3.0, 0.0),
+        ];
+        let payments = forecast(&items, &pies, &positions, now);
+        assert_eq!(
+            payments,
+            [
+                ForecastPayment { ticker: "VWRL".to_string(), expected_on: timestamp("2024-09-14T00:00:00Z"), amount: 1.5 },
+                ForecastPayment { ticker: "VWRL".to_string(), expected_on: timestamp("2025-03-01T00:00:00Z"), amount: 3.0 },
+            ]
+        );
+    }
+
+    #[test]
+    fn forecast_months_add_up_payments() {
+        let payment = |expected_on: &str, amount: f64| ForecastPayment { ticker: "VWRL".to_string(), expected_on: timestamp(expected_on), amount };
+        let payments = [payment("2024-12-01T00:00:00Z", 1.0), payment("2024-12-31T23:00:00Z", 2.0), payment("2025-01-01T00:00:00Z", 4.0)];
+        let months = forecast_monthly_totals(&payments);
+        assert_eq!(months.into_iter().collect::<Vec<_>>(), [((2024, 12), 3.0), ((2025, 1), 4.0)]);
+    }
+}
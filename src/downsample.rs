@@ -0,0 +1,79 @@
+/// Reduces `points` (sorted by x) to at most about `3 * buckets` points for plotting.
+///
+/// The x range is split into `buckets` equal-width buckets; each keeps its minimum, maximum and
+/// last point in their original order, so spikes and the latest value survive the reduction.
+pub fn min_max_last(points: &[[f64; 2]], buckets: usize) -> Vec<[f64; 2]> {
+    if buckets == 0 || points.len() <= buckets * 3 {
+        return points.to_vec();
+    }
+    let (first_x, last_x) = (points[0][0], points[points.len() - 1][0]);
+    let width = (last_x - first_x) / buckets as f64;
+    if width <= 0.0 {
+        return points.to_vec();
+    }
+
+    let mut result = Vec::with_capacity(buckets * 3);
+    let mut start = 0;
+    while start < points.len() {
+        let bucket = (((points[start][0] - first_x) / width) as usize).min(buckets - 1);
+        let mut end = start + 1;
+        while end < points.len() && (((points[end][0] - first_x) / width) as usize).min(buckets - 1) == bucket {
+            end += 1;
+        }
+
+        let slice = &points[start..end];
+        let mut min_i = 0;
+        let mut max_i = 0;
+        for (i, point) in slice.iter().enumerate() {
+            if point[1] < slice[min_i][1] {
+                min_i = i;
+            }
+            if point[1] > slice[max_i][1] {
+                max_i = i;
+            }
+        }
+        let mut keep = [min_i, max_i, slice.len() - 1];
+        keep.sort_unstable();
+        let mut previous = None;
+        for i in keep {
+            if previous != Some(i) {
+                result.push(slice[i]);
+                previous = Some(i);
+            }
+        }
+        start = end;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_series_are_left_alone() {
+        let points: Vec<[f64; 2]> = (0..9).map(|i| [i as f64, i as f64]).collect();
+        assert_eq!(min_max_last(&points, 3), points);
+        assert_eq!(min_max_last(&points, 0), points);
+    }
+
+    #[test]
+    fn keeps_spikes_and_the_latest_value() {
+        let mut points: Vec<[f64; 2]> = (0..1000).map(|i| [i as f64, 1.0]).collect();
+        points[123][1] = 50.0;
+        points[456][1] = -50.0;
+        points[999][1] = 2.0;
+        let reduced = min_max_last(&points, 10);
+        assert!(reduced.len() <= 30);
+        assert!(reduced.contains(&[123.0, 50.0]));
+        assert!(reduced.contains(&[456.0, -50.0]));
+        assert_eq!(reduced.last(), Some(&[999.0, 2.0]));
+        assert!(reduced.windows(2).all(|pair| pair[0][0] < pair[1][0]), "points out of order");
+    }
+
+    #[test]
+    fn same_timestamp_everywhere_is_left_alone() {
+        let points: Vec<[f64; 2]> = (0..100).map(|i| [5.0, i as f64]).collect();
+        assert_eq!(min_max_last(&points, 10), points);
+    }
+}
@@ -0,0 +1,281 @@
+//! Refreshes the shared pie/account state from the API; used by every front end.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use crate::alerts::{self, Alerts};
+use crate::api::{ApiError, Trading212Client};
+use crate::diagnostics;
+use crate::history::History;
+use crate::models::{Account, DividendsResume, Pie};
+use crate::poller::{Poller, PollerStatus};
+
+pub async fn fetch_pies(client: &Trading212Client, pies: Arc<Mutex<HashMap<usize, Pie>>>) -> Result<(), ApiError> {
+    let pies_v = client.pies().await?;
+    
+    // Collect all pie IDs from the API response
+    let current_pie_ids: HashSet<usize> = pies_v.iter()
+        .map(|pie| pie.id as usize)
+        .collect();
+    
+    // Lock the pies HashMap once for all operations
+    let mut pies_map = pies.lock().await;
+    
+    // Remove pies that are no longer in the API response (deleted remotely)
+    let existing_pie_ids: Vec<usize> = pies_map.keys().cloned().collect();
+    for existing_id in existing_pie_ids {
+        if !current_pie_ids.contains(&existing_id) {
+            pies_map.remove(&existing_id);
+            diagnostics::report(format!("Removed deleted pie with ID: {}", existing_id));
+        }
+    }
+    
+    // Update or insert pies from the API response
+    for pie in pies_v {
+        let p = pies_map.entry(pie.id as usize).or_insert(pie.clone());
+        p.cash = pie.cash;
+        p.dividend_details = pie.dividend_details;
+        p.result = pie.result;
+        p.progress = pie.progress;
+        p.status = pie.status;
+    }
+    
+    // Fetch details for pies that never had them, plus the stalest one to keep holdings fresh
+    let mut detail_ids: Vec<u64> = pies_map.values()
+        .filter(|p| p.details_updated_at.is_none() || p.created_at.is_none() || p.name.is_none())
+        .map(|p| p.id)
+        .collect();
+    if detail_ids.is_empty()
+        && let Some(stalest) = pies_map.values()
+            .min_by(|a, b| a.details_updated_at.partial_cmp(&b.details_updated_at).unwrap_or(std::cmp::Ordering::Equal))
+    {
+        detail_ids.push(stalest.id);
+    }
+    // Don't hold the lock while waiting on the detail endpoint's rate limit
+    drop(pies_map);
+    
+    for pie_id in detail_ids {
+        let detail = match client.pie(pie_id).await {
+            Ok(detail) => detail,
+            Err(e) => {
+                diagnostics::report(format!("Failed to fetch details for pie {}: {}", pie_id, e));
+                continue;
+            }
+        };
+        let mut pies_map = pies.lock().await;
+        if let Some(p) = pies_map.get_mut(&(pie_id as usize)) {
+            if p.created_at.is_none() {
+                p.created_at = Some(detail.settings.creation_date);
+            }
+            if p.name.is_none() {
+                p.name = Some(detail.settings.name);
+            }
+            p.instruments = detail.instruments;
+            p.details_updated_at = Some(Utc::now().timestamp() as f64);
+        }
+    }
+    Ok(())
+}
+
+pub async fn record_history(pies: &Mutex<HashMap<usize, Pie>>, history: &Mutex<History>) {
+    let pies_data: Vec<Pie> = pies.lock().await.values().cloned().collect();
+    let current_time = Utc::now().timestamp() as f64;
+    if let Err(e) = history.lock().await.record(current_time, &pies_data) {
+        diagnostics::report(format!("Failed to save history: {}", e));
+    }
+}
+
+/// Dividend history changes rarely, so it is refreshed at most once an hour
+const DIVIDENDS_REFRESH_SECS: f64 = 3600.0;
+
+/// How often the dividend task checks whether a refresh is due, or retries after a failure.
+const DIVIDENDS_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Adds the dividends paid since the last fetch to `account`, a page at a time. Each page is kept
+/// as soon as it arrives, and a failed page is retried from where the fetch stopped, so a long
+/// history is only paged through once.
+pub async fn fetch_dividends(client: &Trading212Client, account: Arc<Mutex<Account>>) -> Result<(), ApiError> {
+    let now = Utc::now().timestamp() as f64;
+    let (known_references, mut next_page_path, mut fetched) = {
+        let account = account.lock().await;
+        let known_references: HashSet<String> = account.dividends.iter().filter_map(|item| item.reference.clone()).collect();
+        match &account.dividends_resume {
+            Some(resume) => (known_references, Some(resume.next_page_path.clone()), resume.fetched),
+            None if account.dividends_updated_at.is_some_and(|updated_at| now - updated_at < DIVIDENDS_REFRESH_SECS) => {
+                return Ok(());
+            }
+            None => (known_references, None, 0),
+        }
+    };
+
+    // Pages come newest first: stop at the first dividend we already have
+    loop {
+        let page = client.dividends_page(next_page_path.as_deref()).await?;
+        let mut reached_known = false;
+        let mut new_items = Vec::new();
+        for item in page.items {
+            if item.reference.as_ref().is_some_and(|reference| known_references.contains(reference)) {
+                reached_known = true;
+                break;
+            }
+            new_items.push(item);
+        }
+
+        let mut account = account.lock().await;
+        // Without references nothing could be matched, so the pages hold the full history
+        if next_page_path.is_none() && known_references.is_empty() {
+            account.dividends.clear();
+        }
+        let at = fetched.min(account.dividends.len());
+        fetched += new_items.len();
+        account.dividends.splice(at..at, new_items);
+        match page.next_page_path {
+            Some(path) if !reached_known && !path.is_empty() => {
+                account.dividends_resume = Some(DividendsResume { next_page_path: path.clone(), fetched });
+                next_page_path = Some(path);
+            }
+            _ => {
+                account.dividends_resume = None;
+                account.dividends_updated_at = Some(now);
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Keeps the dividend history in `account` up to date in a task of its own, so paging through it
+/// never holds up the pie refresh.
+pub fn spawn_dividends(client: Trading212Client, account: Arc<Mutex<Account>>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = fetch_dividends(&client, account.clone()).await {
+                diagnostics::report(format!("Failed to fetch dividends: {}", e));
+            }
+            tokio::time::sleep(DIVIDENDS_CHECK_INTERVAL).await;
+        }
+    })
+}
+
+pub async fn fetch_account(client: &Trading212Client, account: Arc<Mutex<Account>>) -> Result<(), ApiError> {
+    // The account currency never changes, so only ask for it once
+    let needs_info = account.lock().await.info.is_none();
+    if needs_info {
+        let info = client.account_info().await?;
+        account.lock().await.info = Some(info);
+    }
+    
+    let cash = client.account_cash().await?;
+    account.lock().await.cash = Some(cash);
+    
+    let positions = client.portfolio().await?;
+    account.lock().await.positions = positions;
+    Ok(())
+}
+
+/// Starts the background refresh every front end runs: pies, history, alerts and account, with
+/// the dividend history in a task of its own.
+pub fn spawn_poller(
+    client: Trading212Client,
+    pies: Arc<Mutex<HashMap<usize, Pie>>>,
+    account: Arc<Mutex<Account>>,
+    history: Arc<Mutex<History>>,
+    alerts: Arc<Mutex<Alerts>>,
+) -> Arc<Mutex<PollerStatus>> {
+    spawn_dividends(client.clone(), account.clone());
+    let poller = Poller::new(client.clone(), Duration::from_secs(5));
+    let status = poller.status();
+    poller.spawn(move || {
+        let client = client.clone();
+        let pies = pies.clone();
+        let account = account.clone();
+        let history = history.clone();
+        let alerts = alerts.clone();
+        async move {
+            fetch_pies(&client, pies.clone()).await?;
+            record_history(&pies, &history).await;
+            alerts::check(&pies, &history, &alerts).await;
+            fetch_account(&client, account).await
+        }
+    });
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::extract::{Query, State};
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use axum::{Json, Router};
+    use serde_json::{Value, json};
+
+    fn dividend(reference: &str) -> Value {
+        json!({"ticker": "VWRL", "amount": 1.0, "paidOn": "2024-01-01T00:00:00Z", "reference": reference})
+    }
+
+    /// Serves `d4` (only after the first full fetch), `d3` and `d2` on the first page and `d1` on
+    /// the second, which fails the first time it is asked for.
+    async fn dividends_handler(
+        State(requests): State<Arc<AtomicUsize>>,
+        Query(query): Query<HashMap<String, String>>,
+    ) -> Result<Json<Value>, StatusCode> {
+        let request = requests.fetch_add(1, Ordering::SeqCst);
+        match query.get("cursor") {
+            None if request < 3 => Ok(Json(json!({
+                "items": [dividend("d3"), dividend("d2")],
+                "nextPagePath": "/api/v0/history/dividends?cursor=2",
+            }))),
+            None => Ok(Json(json!({
+                "items": [dividend("d4"), dividend("d3"), dividend("d2")],
+                "nextPagePath": "/api/v0/history/dividends?cursor=2",
+            }))),
+            Some(_) if request == 1 => Err(StatusCode::INTERNAL_SERVER_ERROR),
+            Some(_) => Ok(Json(json!({"items": [dividend("d1")], "nextPagePath": null}))),
+        }
+    }
+
+    fn references(account: &Account) -> Vec<&str> {
+        account.dividends.iter().filter_map(|item| item.reference.as_deref()).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dividend_fetch_resumes_after_a_failed_page() {
+        let router = Router::new()
+            .route("/api/v0/history/dividends", get(dividends_handler))
+            .with_state(Arc::new(AtomicUsize::new(0)));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        let client = Trading212Client::with_base_url("token", base_url);
+        let account = Arc::new(Mutex::new(Account::default()));
+
+        assert!(fetch_dividends(&client, account.clone()).await.is_err());
+        {
+            let account = account.lock().await;
+            assert_eq!(references(&account), ["d3", "d2"]);
+            assert_eq!(account.dividends_resume.as_ref().map(|resume| resume.fetched), Some(2));
+            assert_eq!(account.dividends_updated_at, None);
+        }
+
+        // Carries on with the second page instead of starting over
+        fetch_dividends(&client, account.clone()).await.unwrap();
+        {
+            let mut account = account.lock().await;
+            assert_eq!(references(&account), ["d3", "d2", "d1"]);
+            assert_eq!(account.dividends_resume, None);
+            assert!(account.dividends_updated_at.is_some());
+            account.dividends_updated_at = Some(0.0);
+        }
+
+        // A later refresh stops at the first dividend it already has
+        fetch_dividends(&client, account.clone()).await.unwrap();
+        assert_eq!(references(&*account.lock().await), ["d4", "d3", "d2", "d1"]);
+    }
+}
@@ -0,0 +1,504 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::diagnostics;
+use crate::models::Pie;
+
+pub const DEFAULT_DIR: &str = "history";
+
+/// Samples from the last week are kept in memory as recorded; older ones are thinned out.
+const FULL_RESOLUTION_SECS: f64 = 7.0 * 86400.0;
+
+/// Spacing of the thinned samples.
+const THINNED_SPACING_SECS: f64 = 3600.0;
+
+/// How much full-resolution history builds up before it is thinned again.
+const COMPACT_EVERY_SECS: f64 = 86400.0;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TotalValuePoint {
+    pub timestamp: f64, // Unix timestamp in seconds
+    pub total_value: f64,
+    #[serde(default)]
+    pub invested_value: f64,
+    #[serde(default)]
+    pub dividends_reinvested: f64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct PieValuePoint {
+    pub timestamp: f64, // Unix timestamp in seconds
+    pub pie_id: u64,
+    pub value: f64,
+    #[serde(default)]
+    pub invested_value: f64,
+    #[serde(default)]
+    pub dividends_gained: f64,
+    #[serde(default)]
+    pub dividends_reinvested: f64,
+}
+
+/// A line of a pie file. Files written before the names moved to `names.json` repeat the pie's
+/// name on every line.
+#[derive(Deserialize)]
+struct PieLine {
+    #[serde(flatten)]
+    point: PieValuePoint,
+    pie_name: Option<String>,
+}
+
+/// Append-only JSON Lines files: `total.jsonl` plus one `pie_<id>.jsonl` per pie, and the pie
+/// names in `names.json`.
+#[derive(Debug, Clone)]
+pub struct HistoryStore {
+    dir: PathBuf,
+}
+
+impl HistoryStore {
+    /// Opens `dir` for recording, creating it if needed.
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    /// Opens `dir` for reading only, without creating it; a missing directory reads as empty.
+    pub fn open_read_only(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn append_total(&self, point: &TotalValuePoint) -> io::Result<()> {
+        append_line(&self.total_path(), point)
+    }
+
+    pub fn append_pie(&self, point: &PieValuePoint) -> io::Result<()> {
+        append_line(&self.pie_path(point.pie_id), point)
+    }
+
+    pub fn load_total(&self) -> io::Result<Vec<TotalValuePoint>> {
+        read_lines(&self.total_path())
+    }
+
+    /// Every point recorded for `pie_id`, unthinned, ordered by time.
+    pub fn load_pie(&self, pie_id: u64) -> io::Result<Vec<PieValuePoint>> {
+        let lines: Vec<PieLine> = read_lines(&self.pie_path(pie_id))?;
+        Ok(lines.into_iter().map(|line| line.point).collect())
+    }
+
+    /// The last name recorded for each pie.
+    pub fn load_names(&self) -> io::Result<BTreeMap<u64, String>> {
+        match File::open(self.names_path()) {
+            Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Rewritten through a temporary file so a follower never reads half of it.
+    fn save_names(&self, names: &BTreeMap<u64, String>) -> io::Result<()> {
+        let path = self.names_path();
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(names)?)?;
+        fs::rename(&tmp_path, &path)
+    }
+
+    fn total_path(&self) -> PathBuf {
+        self.dir.join("total.jsonl")
+    }
+
+    fn names_path(&self) -> PathBuf {
+        self.dir.join("names.json")
+    }
+
+    fn pie_paths(&self) -> io::Result<Vec<PathBuf>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut paths = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            let is_pie_file = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("pie_") && n.ends_with(".jsonl"));
+            if is_pie_file {
+                paths.push(path);
+            }
+        }
+        Ok(paths)
+    }
+
+    fn pie_path(&self, pie_id: u64) -> PathBuf {
+        self.dir.join(format!("pie_{}.jsonl", pie_id))
+    }
+}
+
+fn append_line<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    let mut line = serde_json::to_string(value)?;
+    line.push('\n');
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(line.as_bytes())
+}
+
+/// Reads one value per line, skipping lines that don't parse (e.g. a write cut short by a crash).
+pub(crate) fn read_lines<T: DeserializeOwned>(path: &Path) -> io::Result<Vec<T>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut values = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(value) => values.push(value),
+            Err(e) => diagnostics::report(format!("Skipping malformed history line in {}: {}", path.display(), e)),
+        }
+    }
+    Ok(values)
+}
+
+/// Reads the complete lines appended to `path` after byte `offset`, returning them with the
+/// offset to continue from. A trailing line without a newline is still being written and is
+/// left for the next call.
+fn read_new_lines<T: DeserializeOwned>(path: &Path, offset: u64) -> io::Result<(Vec<T>, u64)> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((Vec::new(), offset)),
+        Err(e) => return Err(e),
+    };
+    file.seek(SeekFrom::Start(offset))?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    let Some(end) = data.iter().rposition(|b| *b == b'\n') else {
+        return Ok((Vec::new(), offset));
+    };
+
+    let mut values = Vec::new();
+    for line in String::from_utf8_lossy(&data[..end]).lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(value) => values.push(value),
+            Err(e) => diagnostics::report(format!("Skipping malformed history line in {}: {}", path.display(), e)),
+        }
+    }
+    Ok((values, offset + end as u64 + 1))
+}
+
+/// Value samples for the total portfolio and each pie, optionally backed by a [`HistoryStore`].
+///
+/// Samples older than a week are thinned to one an hour in memory, keeping the ones either side of
+/// every deposit or withdrawal so returns come out the same; the store keeps every sample.
+#[derive(Debug, Default)]
+pub struct History {
+    /// Ordered by time.
+    pub total: Vec<TotalValuePoint>,
+    /// Samples of each pie, ordered by time.
+    pub pies: BTreeMap<u64, Vec<PieValuePoint>>,
+    /// Last name recorded for each pie.
+    pub names: BTreeMap<u64, String>,
+    store: Option<HistoryStore>,
+    /// Set by [`History::attach`]: another process writes the store, this one only follows it.
+    read_only: bool,
+    /// How far each store file has been read, for [`History::refresh`].
+    offsets: HashMap<PathBuf, u64>,
+    /// Samples before this have been thinned.
+    compacted_until: f64,
+    /// Bumped whenever samples are added, so readers can tell the history changed.
+    revision: u64,
+    /// Notified whenever samples are added.
+    updated: Arc<Notify>,
+}
+
+impl History {
+    /// History that lives only as long as the process.
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Loads everything `store` holds; new samples are appended to it.
+    pub fn load(store: HistoryStore) -> io::Result<Self> {
+        let mut history = Self { store: Some(store), ..Self::default() };
+        history.read_new()?;
+        Ok(history)
+    }
+
+    /// Loads a store written by another process (e.g. `piers collect`) without ever writing to it.
+    /// Call [`History::refresh`] to pick up the samples it appends later.
+    pub fn attach(store: HistoryStore) -> io::Result<Self> {
+        let mut history = Self { store: Some(store), read_only: true, ..Self::default() };
+        history.read_new()?;
+        Ok(history)
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Notified each time samples are recorded or read from an attached store.
+    pub fn updated(&self) -> Arc<Notify> {
+        self.updated.clone()
+    }
+
+    /// A copy of the samples and names, detached from the store.
+    pub fn snapshot(&self) -> History {
+        Self {
+            total: self.total.clone(),
+            pies: self.pies.clone(),
+            names: self.names.clone(),
+            compacted_until: self.compacted_until,
+            revision: self.revision,
+            ..Self::default()
+        }
+    }
+
+    /// Samples of `pie_id`, ordered by time; empty if none were recorded.
+    pub fn pie(&self, pie_id: u64) -> &[PieValuePoint] {
+        self.pies.get(&pie_id).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Reads the samples appended to an attached store since the last call; returns how many.
+    pub fn refresh(&mut self) -> io::Result<usize> {
+        if !self.read_only {
+            return Ok(0);
+        }
+        self.read_new()
+    }
+
+    fn read_new(&mut self) -> io::Result<usize> {
+        let Some(store) = &self.store else {
+            return Ok(0);
+        };
+
+        let total_path = store.total_path();
+        let offset = self.offsets.get(&total_path).copied().unwrap_or(0);
+        let (total, offset) = read_new_lines::<TotalValuePoint>(&total_path, offset)?;
+        self.offsets.insert(total_path, offset);
+        let mut count = total.len();
+        self.total.extend(total);
+
+        let mut names = store.load_names()?;
+        let known_names = names.len();
+        for path in store.pie_paths()? {
+            let offset = self.offsets.get(&path).copied().unwrap_or(0);
+            let (lines, offset) = read_new_lines::<PieLine>(&path, offset)?;
+            self.offsets.insert(path, offset);
+            count += lines.len();
+            for line in lines {
+                if let Some(name) = line.pie_name {
+                    names.entry(line.point.pie_id).or_insert(name);
+                }
+                self.pies.entry(line.point.pie_id).or_default().push(line.point);
+            }
+        }
+        // Names found only on old lines go to names.json, where they are looked up from now on
+        if names.len() > known_names && !self.read_only {
+            store.save_names(&names)?;
+        }
+        self.names.extend(names);
+        if count > 0 {
+            self.revision += 1;
+            self.updated.notify_one();
+        }
+        self.compact();
+        Ok(count)
+    }
+
+    /// Adds a sample for the total and for every pie, taken at `timestamp`.
+    pub fn record(&mut self, timestamp: f64, pies: &[Pie]) -> io::Result<()> {
+        if self.read_only {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "history is attached read-only"));
+        }
+        if pies.is_empty() {
+            return Ok(());
+        }
+
+        let total = TotalValuePoint {
+            timestamp,
+            total_value: pies.iter().map(|p| p.result.price_avg_value).sum(),
+            invested_value: pies.iter().map(|p| p.result.price_avg_invested_value).sum(),
+            dividends_reinvested: pies.iter().map(|p| p.dividend_details.reinvested).sum(),
+        };
+        if let Some(store) = &self.store {
+            store.append_total(&total)?;
+        }
+        self.total.push(total);
+
+        let mut renamed = false;
+        for pie in pies {
+            let point = PieValuePoint {
+                timestamp,
+                pie_id: pie.id,
+                value: pie.result.price_avg_value,
+                invested_value: pie.result.price_avg_invested_value,
+                dividends_gained: pie.dividend_details.gained,
+                dividends_reinvested: pie.dividend_details.reinvested,
+            };
+            if let Some(store) = &self.store {
+                store.append_pie(&point)?;
+            }
+            self.pies.entry(pie.id).or_default().push(point);
+            if let Some(name) = &pie.name
+                && self.names.get(&pie.id) != Some(name)
+            {
+                self.names.insert(pie.id, name.clone());
+                renamed = true;
+            }
+        }
+        if renamed && let Some(store) = &self.store {
+            store.save_names(&self.names)?;
+        }
+        self.revision += 1;
+        self.updated.notify_one();
+        self.compact();
+        Ok(())
+    }
+
+    /// Thins the samples that have fallen out of the full-resolution week, once a day's worth has.
+    fn compact(&mut self) {
+        let Some(latest) = self.total.last().map(|point| point.timestamp) else {
+            return;
+        };
+        let until = latest - FULL_RESOLUTION_SECS;
+        if until - self.compacted_until < COMPACT_EVERY_SECS {
+            return;
+        }
+        thin(&mut self.total, until, |p| (p.timestamp, p.invested_value - p.dividends_reinvested));
+        for points in self.pies.values_mut() {
+            thin(points, until, |p| (p.timestamp, p.invested_value - p.dividends_reinvested));
+        }
+        self.compacted_until = until;
+    }
+}
+
+/// Drops the samples before `until` that neither close an hour nor sit next to a change in the
+/// deposited amount. `key` gives a sample's timestamp and deposited amount. What is left gives the
+/// same cash flows and time-weighted return, and the same daily closes.
+fn thin<T>(points: &mut Vec<T>, until: f64, key: impl Fn(&T) -> (f64, f64)) {
+    let keys: Vec<(f64, f64)> = points.iter().map(key).collect();
+    let hour = |timestamp: f64| (timestamp / THINNED_SPACING_SECS).floor();
+    let mut index = 0;
+    points.retain(|_| {
+        let i = index;
+        index += 1;
+        let (timestamp, deposited) = keys[i];
+        timestamp >= until
+            || i == 0
+            || i + 1 == keys.len()
+            || hour(timestamp) != hour(keys[i + 1].0)
+            || deposited != keys[i + 1].1
+            || deposited != keys[i - 1].1
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::returns;
+
+    fn temp_store(name: &str) -> HistoryStore {
+        let dir = std::env::temp_dir().join(format!("piers-history-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        HistoryStore { dir }
+    }
+
+    /// Ten days every five minutes, with a deposit after three days.
+    fn samples() -> Vec<PieValuePoint> {
+        (0..10 * 288)
+            .map(|i| {
+                let invested = if i < 3 * 288 { 1000.0 } else { 1500.0 };
+                PieValuePoint {
+                    timestamp: 1_700_000_000.0 + i as f64 * 300.0,
+                    pie_id: 1,
+                    value: invested * (1.0 + 0.05 * (i as f64 / 40.0).sin()),
+                    invested_value: invested,
+                    dividends_gained: 0.0,
+                    dividends_reinvested: 0.0,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn thinning_keeps_returns_and_cash_flows() {
+        let mut points = samples();
+        let twr = returns::twr(&returns::pie_flow_samples(&points)).unwrap();
+        let flows = returns::infer_cash_flows(&points, None);
+        let last = points.last().unwrap().timestamp;
+
+        thin(&mut points, last - FULL_RESOLUTION_SECS, |p| (p.timestamp, p.invested_value));
+        // Three days an hour apart (plus the samples around the deposit), then a week as recorded
+        assert!(points.len() < 3 * 24 + 4 + 7 * 288 + 2, "{} samples left", points.len());
+        assert!((returns::twr(&returns::pie_flow_samples(&points)).unwrap() - twr).abs() < 1e-12);
+        assert_eq!(returns::infer_cash_flows(&points, None), flows);
+
+        // Thinning what was already thinned changes nothing
+        let before = points.len();
+        thin(&mut points, last - FULL_RESOLUTION_SECS, |p| (p.timestamp, p.invested_value));
+        assert_eq!(points.len(), before);
+    }
+
+    #[test]
+    fn names_move_from_old_lines_to_names_file() {
+        let store = temp_store("names");
+        fs::write(
+            store.pie_path(7),
+            "{\"timestamp\":1.0,\"pie_id\":7,\"pie_name\":\"Old name\",\"value\":10.0}\n\
+             {\"timestamp\":2.0,\"pie_id\":7,\"pie_name\":\"Old name\",\"value\":11.0}\n",
+        )
+        .unwrap();
+
+        let history = History::load(store.clone()).unwrap();
+        assert_eq!(history.pie(7).len(), 2);
+        assert_eq!(history.names.get(&7).map(String::as_str), Some("Old name"));
+        assert_eq!(store.load_names().unwrap().get(&7).map(String::as_str), Some("Old name"));
+        assert!(history.pie(8).is_empty());
+        let _ = fs::remove_dir_all(store.dir());
+    }
+
+    #[test]
+    fn attached_history_follows_without_writing() {
+        let store = temp_store("attach");
+        let mut writer = History::load(store.clone()).unwrap();
+        let mut reader = History::attach(store.clone()).unwrap();
+        let pie: Pie = serde_json::from_value(serde_json::json!({
+            "id": 3, "cash": 0.0, "dividendDetails": {"gained": 0.0, "reinvested": 0.0, "inCash": 0.0},
+            "result": {"priceAvgInvestedValue": 5.0, "priceAvgValue": 6.0, "priceAvgResult": 1.0, "priceAvgResultCoef": 0.2},
+            "progress": null, "status": null, "created_at": null, "name": "Three"
+        }))
+        .unwrap();
+
+        writer.record(100.0, std::slice::from_ref(&pie)).unwrap();
+        assert!(reader.record(100.0, std::slice::from_ref(&pie)).is_err());
+        let revision = reader.revision();
+        assert_eq!(reader.refresh().unwrap(), 2);
+        assert!(reader.revision() > revision);
+        assert_eq!(reader.pie(3).len(), 1);
+        assert_eq!(reader.names.get(&3).map(String::as_str), Some("Three"));
+        assert!(!fs::read_to_string(store.pie_path(3)).unwrap().contains("Three"));
+        let _ = fs::remove_dir_all(store.dir());
+    }
+}
@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use chrono::NaiveDate;
+use serde::Deserialize;
+
+use crate::returns::CashFlow;
+
+pub const DEFAULT_PATH: &str = "ledger.json";
+
+/// One line of `ledger.json`: a deposit (positive) or withdrawal (negative) made to a pie.
+#[derive(Debug, Deserialize)]
+struct LedgerEntry {
+    pie_id: u64,
+    /// `YYYY-MM-DD`
+    date: String,
+    amount: f64,
+}
+
+/// Cash flows recorded by hand, for pies funded before pie_top started collecting history.
+#[derive(Debug, Clone, Default)]
+pub struct Ledger {
+    flows: HashMap<u64, Vec<CashFlow>>,
+}
+
+impl Ledger {
+    /// Reads a JSON array of `{"pie_id": 1, "date": "2024-01-31", "amount": 500.0}` entries.
+    /// A missing file is an empty ledger.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut data = String::new();
+        match File::open(path) {
+            Ok(mut file) => file.read_to_string(&mut data)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+
+        let entries: Vec<LedgerEntry> = serde_json::from_str(&data)?;
+        let mut flows: HashMap<u64, Vec<CashFlow>> = HashMap::new();
+        for entry in entries {
+            let date = NaiveDate::parse_from_str(&entry.date, "%Y-%m-%d").map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("Invalid ledger date {:?}: {}", entry.date, e))
+            })?;
+            let timestamp = date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc().timestamp() as f64;
+            flows.entry(entry.pie_id).or_default().push(CashFlow { timestamp, amount: entry.amount });
+        }
+        for pie_flows in flows.values_mut() {
+            pie_flows.sort_by(|a, b| a.timestamp.partial_cmp(&b.timestamp).unwrap_or(std::cmp::Ordering::Equal));
+        }
+        Ok(Self { flows })
+    }
+
+    pub fn flows(&self, pie_id: u64) -> &[CashFlow] {
+        self.flows.get(&pie_id).map(Vec::as_slice).unwrap_or_default()
+    }
+}
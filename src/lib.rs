@@ -0,0 +1,28 @@
+//! Data layer for pie_top: the Trading 212 API client, the models it returns and the
+//! analytics shared by the GUI and the command line.
+
+pub mod alerts;
+pub mod analytics;
+pub mod api;
+pub mod benchmark;
+pub mod cli;
+pub mod config;
+pub mod delivery;
+pub mod diagnostics;
+pub mod dividends;
+pub mod downsample;
+pub mod fetch;
+pub mod history;
+pub mod ledger;
+pub mod metrics;
+pub mod models;
+pub mod poller;
+pub mod ratelimit;
+pub mod rebalance;
+pub mod returns;
+pub mod risk;
+pub mod server;
+pub mod snapshot;
+pub mod sort;
+pub mod summary;
+pub mod tui;
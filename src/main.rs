@@ -1,96 +1,95 @@
 use dotenv::dotenv;
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use std::env;
-use std::fs::File;
-use std::io::{Read, Write};
 
 use tokio::sync::Mutex;
 use std::time::Duration;
-use std::error::Error;
 use chrono::Utc;
 
 use eframe::egui;
-use egui_plot::{Line, Plot, PlotPoints};
+use egui_plot::{GridInput, GridMark, Line, Plot, PlotPoints};
 use egui_extras::{TableBuilder, Column};
-use serde::Deserialize;
-
-#[derive(Debug, Deserialize, Clone)]
-#[derive(serde::Serialize)]
-struct Pie {
-    id: u64,
-    cash: f64,
-    #[serde(rename = "dividendDetails")]
-    dividend_details: DividendDetails,
-    result: ResultDetails,
-    progress: Option<f64>,
-    status: Option<String>,
-    created_at: Option<f64>,
-    name: Option<String>,
-}
-
-#[derive(Debug, Deserialize, Clone)]
-#[derive(serde::Serialize)]
-struct DividendDetails {
-    gained: f64,
-    reinvested: f64,
-    #[serde(rename = "inCash")]
-    in_cash: f64,
-}
-
-#[derive(Debug, Deserialize, Clone)]
-#[derive(serde::Serialize)]
-struct ResultDetails {
-    #[serde(rename = "priceAvgInvestedValue")]
-    price_avg_invested_value: f64,
-    #[serde(rename = "priceAvgValue")]
-    price_avg_value: f64,
-    #[serde(rename = "priceAvgResult")]
-    price_avg_result: f64,
-    #[serde(rename = "priceAvgResultCoef")]
-    price_avg_result_coef: f64,
-}
-
-#[derive(Debug, Deserialize, Clone)]
-struct PieDetail {
-    settings: Setting
-}
-
-#[derive(Debug, Deserialize, Clone)]
-struct Setting {
-    #[serde(rename = "creationDate")]
-    creation_date: f64,
-    name: String,
-}
-
-#[derive(Debug, Clone)]
-struct TotalValuePoint {
-    timestamp: f64, // Unix timestamp in seconds
-    total_value: f64,
-}
-
-#[derive(Debug, Clone)]
-struct PieValuePoint {
-    timestamp: f64, // Unix timestamp in seconds
-    pie_id: u64,
-    pie_name: String,
-    value: f64,
-}
+
+use clap::Parser;
+
+use piers::alerts::{self, Alert, AlertConfig, Alerts};
+use piers::analytics::{self, Analytics, AnalyticsHandle, RiskSettings};
+use piers::api::Trading212Client;
+use piers::benchmark::{self, Benchmark};
+use piers::cli::{self, Cli, Command};
+use piers::config::{self, Config, ConfigError, Environment};
+use piers::delivery::DeliveryConfig;
+use piers::{dividends, downsample};
+use piers::history::{self, History, HistoryStore};
+use piers::ledger::{self, Ledger};
+use piers::models::{Account, Pie, Position};
+use piers::poller::PollerStatus;
+use piers::fetch::spawn_poller;
+use piers::rebalance::{self, Plan};
+use piers::returns::{self, calculate_annual_rate, TwrPeriod};
+use piers::risk::{self, RiskMetrics};
+use piers::server::{self, ServerState};
+use piers::snapshot::{self, load_account, load_map, spawn_follower, spawn_saver};
+use piers::sort::{select_column, sort_pies, SortColumn, SortDirection};
+
+/// Width ranges of the pie table's columns, in order. The header and every row, pie or
+/// instrument, have one cell for each.
+const PIE_TABLE_COLUMNS: [(f32, f32); 15] = [
+    (120.0, 250.0), // Name - wider column for pie names
+    (80.0, 200.0),  // Initial Value
+    (80.0, 200.0),  // Current Value
+    (60.0, 150.0),  // Return %
+    (80.0, 200.0),  // Return Value
+    (80.0, 200.0),  // Annual Rate %
+    (60.0, 150.0),  // IRR %
+    (60.0, 150.0),  // TWR %
+    (60.0, 150.0),  // Volatility
+    (60.0, 150.0),  // Max Drawdown
+    (60.0, 150.0),  // Current Drawdown
+    (60.0, 120.0),  // Sharpe
+    (60.0, 120.0),  // Sortino
+    (80.0, 200.0),  // Dividends
+    (60.0, 120.0),  // Status
+];
+
+/// Instrument rows fill the columns up to Annual Rate % (with the weights), then only Status.
+const INSTRUMENT_FIGURE_COLUMNS: usize = 6;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
-enum SortColumn {
+enum PositionSortColumn {
     None,
-    InitialValue,
-    CurrentValue,
-    Return,
-    ReturnValue,
-    AnnualRate,
+    Quantity,
+    Value,
+    Ppl,
+    FxPpl,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HoldingsTab {
+    Pies,
+    Positions,
+    Dividends,
+    Alerts,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
-enum SortDirection {
-    Ascending,
-    Descending,
+enum ChartView {
+    History,
+    Allocation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AllocationBasis {
+    Value,    // price_avg_value
+    Invested, // price_avg_invested_value
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ChartMode {
+    Value,
+    Twr, // Cumulative time-weighted return from the start of the visible window
+    Indexed, // Value rebased to 100 at the start of the visible window
+    Drawdown, // Fall from the highest time-weighted growth in the visible window
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -98,115 +97,294 @@ enum TimeView {
     TenMinutes,
     OneHour,
     OneDay,
+    OneWeek,
+    OneMonth,
+    OneYear,
+    All,
+}
+
+impl TimeView {
+    /// Length of the window in seconds, `None` for all history.
+    fn duration_secs(self) -> Option<f64> {
+        match self {
+            TimeView::TenMinutes => Some(600.0),
+            TimeView::OneHour => Some(3600.0),
+            TimeView::OneDay => Some(86400.0),
+            TimeView::OneWeek => Some(7.0 * 86400.0),
+            TimeView::OneMonth => Some(30.0 * 86400.0),
+            TimeView::OneYear => Some(365.0 * 86400.0),
+            TimeView::All => None,
+        }
+    }
 }
 
 struct PieTopApp {
     pies: Arc<Mutex<HashMap<usize, Pie>>>,
-    token: String,
-    last_update: std::time::Instant,
-    update_interval: Duration,
-    total_value_history: VecDeque<TotalValuePoint>,
-    pie_value_history: VecDeque<PieValuePoint>,
+    account: Arc<Mutex<Account>>,
+    /// `None` when attached read-only to the store of a `piers collect` process.
+    environment: Option<Environment>,
+    poller_status: Arc<Mutex<PollerStatus>>,
+    history: Arc<Mutex<History>>,
+    alerts: Arc<Mutex<Alerts>>,
+    analytics: AnalyticsHandle,
+    figures: Analytics, // Latest IRR, TWR and risk figures from the analytics task
+    twr_period: TwrPeriod,
+    risk_period: TwrPeriod,
+    risk_free_rate: f64, // Annual, as a fraction
+    chart_view: ChartView,
+    allocation_basis: AllocationBasis,
+    chart_mode: ChartMode,
     pie_list_height: f32, // Height allocated to pie list section
     sort_column: SortColumn,
     sort_direction: SortDirection,
     time_view: TimeView,
-    selected_chart_pie: Option<u64>, // None = Total Portfolio, Some(id) = specific pie
+    chart_series: Vec<Option<u64>>, // Plotted series in selection order; None = Total Portfolio, Some(id) = specific pie
+    benchmarks: Vec<Benchmark>, // Imported with `piers benchmark import`
+    chart_benchmarks: Vec<String>, // Benchmarks drawn on the chart; the first is also compared in the table
+    expanded_pies: HashSet<u64>, // Pies whose instrument rows are shown in the table
+    planner_pie: Option<u64>, // Pie the rebalancing planner is open for
+    planner_deposit: f64,
+    holdings_tab: HoldingsTab,
+    position_sort_column: PositionSortColumn,
+    position_sort_direction: SortDirection,
 }
 
 impl PieTopApp {
-    fn new(token: String, pies: Arc<Mutex<HashMap<usize, Pie>>>) -> Self {
+    fn new(
+        environment: Option<Environment>,
+        pies: Arc<Mutex<HashMap<usize, Pie>>>,
+        account: Arc<Mutex<Account>>,
+        history: Arc<Mutex<History>>,
+        analytics: AnalyticsHandle,
+        poller_status: Arc<Mutex<PollerStatus>>,
+        alerts: Arc<Mutex<Alerts>>,
+    ) -> Self {
         Self {
             pies,
-            token,
-            last_update: std::time::Instant::now(),
-            update_interval: Duration::from_secs(5), 
-            total_value_history: VecDeque::new(),
-            pie_value_history: VecDeque::new(),
+            account,
+            environment,
+            poller_status,
+            history,
+            alerts,
+            analytics,
+            figures: Analytics::default(),
+            twr_period: TwrPeriod::SinceInception,
+            risk_period: TwrPeriod::SinceInception,
+            risk_free_rate: 0.0,
+            chart_view: ChartView::History,
+            allocation_basis: AllocationBasis::Value,
+            chart_mode: ChartMode::Value,
             pie_list_height: 300.0, // Default height for pie list section
             sort_column: SortColumn::None,
             sort_direction: SortDirection::Descending,
             time_view: TimeView::TenMinutes,
-            selected_chart_pie: None, // Start with total portfolio selected
+            chart_series: vec![None], // Start with total portfolio selected
+            benchmarks: Vec::new(),
+            chart_benchmarks: Vec::new(),
+            expanded_pies: HashSet::new(),
+            planner_pie: None,
+            planner_deposit: 100.0,
+            holdings_tab: HoldingsTab::Pies,
+            position_sort_column: PositionSortColumn::None,
+            position_sort_direction: SortDirection::Descending,
         }
     }
-}
 
-impl eframe::App for PieTopApp {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        // Update data periodically
-        if self.last_update.elapsed() >= self.update_interval {
-            let token = self.token.clone();
-            let pies = self.pies.clone();
-            
-            // Spawn async task for fetching data
-            tokio::spawn(async move {
-                if let Err(e) = fetch_pies(&token, pies).await {
-                    eprintln!("Failed to fetch pies: {}", e);
-                }
+    /// Table of positions held outside pies, with a reconciliation against the account total.
+    fn show_positions_table(&mut self, ui: &mut egui::Ui, account: &Account, pies_data: &[Pie]) {
+        let mut positions = account.positions_outside_pies();
+        
+        let sort_key = |p: &Position| match self.position_sort_column {
+            PositionSortColumn::Quantity => p.quantity,
+            PositionSortColumn::Value => p.quantity * p.current_price,
+            PositionSortColumn::Ppl => p.ppl,
+            PositionSortColumn::FxPpl => p.fx_ppl.unwrap_or_default(),
+            PositionSortColumn::None => 0.0,
+        };
+        if self.position_sort_column != PositionSortColumn::None {
+            positions.sort_by(|a, b| match self.position_sort_direction {
+                SortDirection::Ascending => sort_key(a).partial_cmp(&sort_key(b)).unwrap_or(std::cmp::Ordering::Equal),
+                SortDirection::Descending => sort_key(b).partial_cmp(&sort_key(a)).unwrap_or(std::cmp::Ordering::Equal),
             });
-            
-            self.last_update = std::time::Instant::now();
-            
-            // Update total value history when we fetch new data
-            let pies_data = if let Ok(pies_guard) = self.pies.try_lock() {
-                pies_guard.values().cloned().collect::<Vec<_>>()
-            } else {
-                Vec::new()
-            };
-            
-            if !pies_data.is_empty() {
-                let total_now: f64 = pies_data.iter().map(|p| p.result.price_avg_value).sum();
-                let current_time = Utc::now().timestamp() as f64;
-                
-                // Add current total value to history
-                self.total_value_history.push_back(TotalValuePoint {
-                    timestamp: current_time,
-                    total_value: total_now,
+        }
+        
+        // Reconcile in the account currency: prices are in each instrument's own currency, so only
+        // the P/L of the positions can be set against the account summary
+        let total_ppl: f64 = positions.iter().map(|p| p.ppl).sum();
+        if let Some(cash) = &account.cash {
+            let pies_invested: f64 = pies_data.iter().map(|p| p.result.price_avg_invested_value).sum();
+            let pies_value: f64 = pies_data.iter().map(|p| p.result.price_avg_value).sum();
+            let pies_result: f64 = pies_data.iter().map(|p| p.result.price_avg_result).sum();
+            let total_value = cash.invested - pies_invested + total_ppl;
+            let implied = cash.total - cash.free - cash.blocked.unwrap_or_default() - cash.pie_cash - pies_value;
+            let implied_ppl = cash.ppl - pies_result;
+            ui.horizontal(|ui| {
+                match &account.info {
+                    Some(info) => ui.label(format!("Outside pies ({}):", info.currency_code)),
+                    None => ui.label("Outside pies:"),
+                };
+                ui.label(format!("Value: {:.2}", total_value))
+                    .on_hover_text("Invested outside pies (account invested − pie invested values) + the positions' P/L");
+                ui.separator();
+                ui.label(format!("Implied by account: {:.2}", implied))
+                    .on_hover_text("Total equity − free cash − blocked cash − pie cash − pie values");
+                ui.separator();
+                let ppl_color = if total_ppl > 0.0 {
+                    egui::Color32::GREEN
+                } else if total_ppl < 0.0 {
+                    egui::Color32::RED
+                } else {
+                    egui::Color32::WHITE
+                };
+                ui.colored_label(ppl_color, format!("P/L: {:+.2}", total_ppl))
+                    .on_hover_text(format!("Sum of the positions' P/L\nImplied by account: {:+.2} (account P/L − pie results)", implied_ppl));
+            });
+        }
+        
+        if positions.is_empty() {
+            ui.label("No positions held outside pies");
+            return;
+        }
+        
+        TableBuilder::new(ui)
+            .striped(true)
+            .resizable(true)
+            .vscroll(true)
+            .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
+            .column(Column::remainder().range(100.0..=200.0)) // Ticker
+            .column(Column::remainder().range(80.0..=200.0)) // Quantity
+            .column(Column::remainder().range(80.0..=200.0)) // Average Price
+            .column(Column::remainder().range(80.0..=200.0)) // Current Price
+            .column(Column::remainder().range(80.0..=200.0)) // Value, in the instrument's currency
+            .column(Column::remainder().range(80.0..=200.0)) // P/L
+            .column(Column::remainder().range(80.0..=200.0)) // FX P/L
+            .header(25.0, |mut header| {
+                header.col(|ui| {
+                    ui.strong("Ticker");
                 });
-                
-                // Add individual pie values to history
-                for pie in &pies_data {
-                    let pie_name = pie.name.clone().unwrap_or_else(|| format!("Pie {}", pie.id));
-                    self.pie_value_history.push_back(PieValuePoint {
-                        timestamp: current_time,
-                        pie_id: pie.id,
-                        pie_name,
-                        value: pie.result.price_avg_value,
+                for (label, column) in [
+                    ("Quantity", Some(PositionSortColumn::Quantity)),
+                    ("Avg Price", None),
+                    ("Current Price", None),
+                    ("Value (instrument ccy)", Some(PositionSortColumn::Value)),
+                    ("P/L", Some(PositionSortColumn::Ppl)),
+                    ("FX P/L", Some(PositionSortColumn::FxPpl)),
+                ] {
+                    header.col(|ui| {
+                        let Some(column) = column else {
+                            ui.strong(label);
+                            return;
+                        };
+                        let text = if self.position_sort_column == column {
+                            match self.position_sort_direction {
+                                SortDirection::Ascending => format!("{} ⬆️", label),
+                                SortDirection::Descending => format!("{} ⬇️", label),
+                            }
+                        } else {
+                            label.to_string()
+                        };
+                        if ui.add(egui::Button::new(egui::RichText::new(text).strong())).clicked() {
+                            if self.position_sort_column == column {
+                                self.position_sort_direction = match self.position_sort_direction {
+                                    SortDirection::Ascending => SortDirection::Descending,
+                                    SortDirection::Descending => SortDirection::Ascending,
+                                };
+                            } else {
+                                self.position_sort_column = column;
+                                self.position_sort_direction = SortDirection::Descending;
+                            }
+                        }
                     });
                 }
-                
-                // Remove data older than 1 day (86400 seconds) to keep memory usage reasonable
-                let one_day_ago = current_time - 86400.0;
-                while let Some(front) = self.total_value_history.front() {
-                    if front.timestamp < one_day_ago {
-                        self.total_value_history.pop_front();
-                    } else {
-                        break;
-                    }
-                }
-                
-                // Also clean up pie value history
-                while let Some(front) = self.pie_value_history.front() {
-                    if front.timestamp < one_day_ago {
-                        self.pie_value_history.pop_front();
-                    } else {
-                        break;
-                    }
+            })
+            .body(|mut body| {
+                for position in &positions {
+                    body.row(22.0, |mut row| {
+                        row.col(|ui| {
+                            ui.add(egui::Label::new(&position.ticker).truncate());
+                        });
+                        row.col(|ui| {
+                            ui.label(format!("{:.4}", position.quantity));
+                        });
+                        row.col(|ui| {
+                            ui.label(format!("{:.2}", position.average_price));
+                        });
+                        row.col(|ui| {
+                            ui.label(format!("{:.2}", position.current_price));
+                        });
+                        row.col(|ui| {
+                            ui.label(format!("{:.2}", position.quantity * position.current_price));
+                        });
+                        for value in [Some(position.ppl), position.fx_ppl] {
+                            row.col(|ui| {
+                                let Some(value) = value else {
+                                    ui.label("-");
+                                    return;
+                                };
+                                let color = if value > 0.0 {
+                                    egui::Color32::GREEN
+                                } else if value < 0.0 {
+                                    egui::Color32::RED
+                                } else {
+                                    egui::Color32::WHITE
+                                };
+                                ui.colored_label(color, format!("{:+.2}", value));
+                            });
+                        }
+                    });
                 }
-            }
-        }
+            });
+    }
+}
+
+impl eframe::App for PieTopApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let poller_status = self.poller_status.try_lock().map(|s| s.clone()).unwrap_or_default();
 
         egui::CentralPanel::default().show(ctx, |ui| {
             // Top bar with title and status
             ui.horizontal(|ui| {
                 ui.heading("🥧 Pie Portfolio Dashboard");
                 
+                match &self.environment {
+                    Some(environment) => {
+                        let env_color = match environment {
+                            Environment::Live => egui::Color32::GREEN,
+                            Environment::Demo => egui::Color32::YELLOW,
+                            Environment::Custom(_) => egui::Color32::LIGHT_BLUE,
+                        };
+                        ui.colored_label(env_color, format!("🌐 {}", environment));
+                    }
+                    None => {
+                        ui.colored_label(egui::Color32::LIGHT_BLUE, "📂 Attached to collector (read-only)");
+                    }
+                }
+                
                 // Push status to the right
                 ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
-                    ui.label(format!("Last update: {:.0}s ago", self.last_update.elapsed().as_secs_f32()));
+                    let now = Utc::now();
+                    match poller_status.last_success {
+                        Some(last) => ui.label(format!("Last update: {}s ago", (now - last).num_seconds())),
+                        None => ui.label("Last update: never"),
+                    };
                     ui.separator();
-                    ui.label("🔄 Auto-refresh every 5 seconds");
+                    if poller_status.polling {
+                        ui.label("🔄 Refreshing...");
+                    } else if let Some(next) = poller_status.next_poll {
+                        ui.label(format!("🔄 Next refresh in {}s", (next - now).num_seconds().max(0)));
+                    }
+                    if poller_status.backoff_level > 0 {
+                        ui.separator();
+                        ui.colored_label(
+                            egui::Color32::YELLOW,
+                            format!("⏳ Backoff level {}", poller_status.backoff_level),
+                        );
+                    }
+                    if let Some(error) = &poller_status.last_error {
+                        ui.separator();
+                        ui.add(egui::Label::new(egui::RichText::new(format!("⚠ {}", error)).color(egui::Color32::RED)).truncate())
+                            .on_hover_text(error);
+                    }
                 });
             });
             ui.separator();
@@ -225,69 +403,18 @@ impl eframe::App for PieTopApp {
                 return;
             }
 
-            // Sort pies data based on current sort settings
-            match self.sort_column {
-                SortColumn::InitialValue => {
-                    pies_data.sort_by(|a, b| {
-                        let a_initial = a.result.price_avg_invested_value;
-                        let b_initial = b.result.price_avg_invested_value;
-                        match self.sort_direction {
-                            SortDirection::Ascending => a_initial.partial_cmp(&b_initial).unwrap_or(std::cmp::Ordering::Equal),
-                            SortDirection::Descending => b_initial.partial_cmp(&a_initial).unwrap_or(std::cmp::Ordering::Equal),
-                        }
-                    });
-                }
-                SortColumn::CurrentValue => {
-                    pies_data.sort_by(|a, b| {
-                        let a_current = a.result.price_avg_value;
-                        let b_current = b.result.price_avg_value;
-                        match self.sort_direction {
-                            SortDirection::Ascending => a_current.partial_cmp(&b_current).unwrap_or(std::cmp::Ordering::Equal),
-                            SortDirection::Descending => b_current.partial_cmp(&a_current).unwrap_or(std::cmp::Ordering::Equal),
-                        }
-                    });
-                }
-                SortColumn::Return => {
-                    pies_data.sort_by(|a, b| {
-                        let a_return = a.result.price_avg_result_coef;
-                        let b_return = b.result.price_avg_result_coef;
-                        match self.sort_direction {
-                            SortDirection::Ascending => a_return.partial_cmp(&b_return).unwrap_or(std::cmp::Ordering::Equal),
-                            SortDirection::Descending => b_return.partial_cmp(&a_return).unwrap_or(std::cmp::Ordering::Equal),
-                        }
-                    });
-                }
-                SortColumn::ReturnValue => {
-                    pies_data.sort_by(|a, b| {
-                        let a_return_value = a.result.price_avg_value - a.result.price_avg_invested_value;
-                        let b_return_value = b.result.price_avg_value - b.result.price_avg_invested_value;
-                        match self.sort_direction {
-                            SortDirection::Ascending => a_return_value.partial_cmp(&b_return_value).unwrap_or(std::cmp::Ordering::Equal),
-                            SortDirection::Descending => b_return_value.partial_cmp(&a_return_value).unwrap_or(std::cmp::Ordering::Equal),
-                        }
-                    });
-                }
-                SortColumn::AnnualRate => {
-                    pies_data.sort_by(|a, b| {
-                        let a_rate = calculate_annual_rate(
-                            a.result.price_avg_invested_value,
-                            a.result.price_avg_value,
-                            a.created_at.unwrap_or_default() as f64,
-                        );
-                        let b_rate = calculate_annual_rate(
-                            b.result.price_avg_invested_value,
-                            b.result.price_avg_value,
-                            b.created_at.unwrap_or_default() as f64,
-                        );
-                        match self.sort_direction {
-                            SortDirection::Ascending => a_rate.partial_cmp(&b_rate).unwrap_or(std::cmp::Ordering::Equal),
-                            SortDirection::Descending => b_rate.partial_cmp(&a_rate).unwrap_or(std::cmp::Ordering::Equal),
-                        }
-                    });
-                }
-                SortColumn::None => {} // No sorting
+            // Returns and risk are worked out in the background; pick up the latest figures
+            self.analytics.set_risk_settings(RiskSettings { period: self.risk_period, risk_free_rate: self.risk_free_rate });
+            if let Ok(figures) = self.analytics.results().try_lock() {
+                self.figures = figures.clone();
             }
 
+            // Sort pies data based on current sort settings
+            let twr: HashMap<u64, f64> = self.figures.twr.iter()
+                .filter_map(|(id, twr)| twr.get(&self.twr_period).map(|twr| (*id, *twr)))
+                .collect();
+            sort_pies(&mut pies_data, self.sort_column, self.sort_direction, &self.figures.irr, &twr, &self.figures.risk);
+
             // Calculate totals
             let total_initial: f64 = pies_data.iter().map(|p| p.result.price_avg_invested_value).sum();
             let total_now: f64 = pies_data.iter().map(|p| p.result.price_avg_value).sum();
@@ -297,6 +424,11 @@ impl eframe::App for PieTopApp {
                 0.0
             };
 
+            let account = self.account.try_lock().map(|a| a.clone()).unwrap_or_default();
+            let (alert_log, alert_rules): (Vec<Alert>, usize) = self.alerts.try_lock()
+                .map(|alerts| (alerts.log.iter().cloned().collect(), alerts.config().rules.len()))
+                .unwrap_or_default();
+
             // Summary section
             ui.group(|ui| {
                 ui.horizontal(|ui| {
@@ -316,6 +448,104 @@ impl eframe::App for PieTopApp {
                     };
                     
                     ui.colored_label(color, format!("Total Return: {:+.2}%", total_result_percent));
+                    
+                    if let Some(irr) = self.figures.portfolio_irr {
+                        ui.separator();
+                        let irr_percent = irr * 100.0;
+                        let irr_color = if irr_percent > 0.0 {
+                            egui::Color32::GREEN
+                        } else if irr_percent < 0.0 {
+                            egui::Color32::RED
+                        } else {
+                            egui::Color32::WHITE
+                        };
+                        ui.colored_label(irr_color, format!("IRR: {:.2}%", irr_percent))
+                            .on_hover_text("Annualised money-weighted return, accounting for when money was deposited");
+                    }
+                    
+                    ui.separator();
+                    egui::ComboBox::from_id_source("twr_period")
+                        .selected_text(format!("TWR {}", self.twr_period.label()))
+                        .show_ui(ui, |ui| {
+                            for period in TwrPeriod::ALL {
+                                ui.selectable_value(&mut self.twr_period, period, period.label());
+                            }
+                        });
+                    match self.figures.portfolio_twr.get(&self.twr_period) {
+                        Some(twr) => {
+                            let twr_percent = twr * 100.0;
+                            let twr_color = if twr_percent > 0.0 {
+                                egui::Color32::GREEN
+                            } else if twr_percent < 0.0 {
+                                egui::Color32::RED
+                            } else {
+                                egui::Color32::WHITE
+                            };
+                            ui.colored_label(twr_color, format!("{:+.2}%", twr_percent))
+                                .on_hover_text("Time-weighted return: performance independent of deposit timing");
+                        }
+                        None => {
+                            ui.label("-").on_hover_text("Not enough history for this period");
+                        }
+                    }
+                });
+                
+                if let Some(cash) = &account.cash {
+                    ui.horizontal(|ui| {
+                        match &account.info {
+                            Some(info) => ui.label(format!("🏦 Account Summary ({}):", info.currency_code)),
+                            None => ui.label("🏦 Account Summary:"),
+                        };
+                        ui.separator();
+                        ui.label(format!("Total Equity: {:.2}", cash.total));
+                        ui.separator();
+                        ui.label(format!("Free Cash: {:.2}", cash.free));
+                        if let Some(blocked) = cash.blocked.filter(|b| *b != 0.0) {
+                            ui.separator();
+                            ui.label(format!("Blocked: {:.2}", blocked));
+                        }
+                        ui.separator();
+                        ui.label(format!("Invested: {:.2}", cash.invested));
+                        ui.separator();
+                        let pies_cash: f64 = pies_data.iter().map(|p| p.cash).sum();
+                        ui.label(format!("Pie Cash: {:.2}", cash.pie_cash))
+                            .on_hover_text(format!("Uninvested cash across pies: {:.2}", pies_cash));
+                        ui.separator();
+                        
+                        let ppl_color = if cash.ppl > 0.0 {
+                            egui::Color32::GREEN
+                        } else if cash.ppl < 0.0 {
+                            egui::Color32::RED
+                        } else {
+                            egui::Color32::WHITE
+                        };
+                        ui.colored_label(ppl_color, format!("Unrealised P/L: {:+.2}", cash.ppl));
+                    });
+                }
+                
+                ui.horizontal(|ui| {
+                    ui.label("📉 Risk:");
+                    egui::ComboBox::from_id_source("risk_period")
+                        .selected_text(self.risk_period.label())
+                        .show_ui(ui, |ui| {
+                            for period in TwrPeriod::ALL {
+                                ui.selectable_value(&mut self.risk_period, period, period.label());
+                            }
+                        });
+                    ui.label("Risk-free rate:");
+                    let mut risk_free_percent = self.risk_free_rate * 100.0;
+                    let response = ui.add(egui::DragValue::new(&mut risk_free_percent).speed(0.05).range(0.0..=20.0).suffix("%"))
+                        .on_hover_text("Annual rate the Sharpe and Sortino ratios measure returns against");
+                    if response.changed() {
+                        self.risk_free_rate = risk_free_percent / 100.0;
+                    }
+                    ui.separator();
+                    match &self.figures.portfolio_risk {
+                        Some(metrics) => show_risk_summary(ui, metrics),
+                        None => {
+                            ui.label("-").on_hover_text("Not enough history for this period");
+                        }
+                    }
                 });
             });
 
@@ -329,21 +559,36 @@ impl eframe::App for PieTopApp {
                 .default_height(self.pie_list_height)
                 .height_range(150.0..=available_height - 150.0)
                 .show_inside(ui, |ui| {
-                    // Pies table with full width
-                    ui.label("📊 Pie Holdings");
+                    ui.horizontal(|ui| {
+                        ui.selectable_value(&mut self.holdings_tab, HoldingsTab::Pies, "📊 Pie Holdings");
+                        ui.selectable_value(&mut self.holdings_tab, HoldingsTab::Positions, "📋 Positions Outside Pies");
+                        ui.selectable_value(&mut self.holdings_tab, HoldingsTab::Dividends, "💰 Dividends");
+                        ui.selectable_value(&mut self.holdings_tab, HoldingsTab::Alerts, format!("🔔 Alerts ({})", alert_log.len()));
+                    });
                     
-                    TableBuilder::new(ui)
+                    if self.holdings_tab == HoldingsTab::Positions {
+                        ui.push_id("positions_table", |ui| self.show_positions_table(ui, &account, &pies_data));
+                        self.pie_list_height = ui.min_rect().height();
+                        return;
+                    }
+                    if self.holdings_tab == HoldingsTab::Dividends {
+                        show_dividends_view(ui, &account, &pies_data);
+                        self.pie_list_height = ui.min_rect().height();
+                        return;
+                    }
+                    if self.holdings_tab == HoldingsTab::Alerts {
+                        show_alerts_view(ui, &alert_log, alert_rules);
+                        self.pie_list_height = ui.min_rect().height();
+                        return;
+                    }
+                    
+                    // Pies table with full width
+                    PIE_TABLE_COLUMNS.iter()
+                        .fold(TableBuilder::new(ui), |table, (min, max)| table.column(Column::remainder().range(*min..=*max)))
                         .striped(true)
                         .resizable(true)
                         .vscroll(true) // Enable vertical scrolling within the table
                         .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
-                        .column(Column::remainder().range(120.0..=250.0)) // Name - wider column for pie names
-                        .column(Column::remainder().range(80.0..=200.0)) // Initial Value
-                        .column(Column::remainder().range(80.0..=200.0)) // Current Value
-                        .column(Column::remainder().range(60.0..=150.0)) // Return %
-                        .column(Column::remainder().range(80.0..=200.0)) // Return Value
-                        .column(Column::remainder().range(80.0..=200.0)) // Annual Rate %
-                        .column(Column::remainder().range(60.0..=120.0)) // Status
                                 .header(25.0, |mut header| {
                                     header.col(|ui| {
                                         ui.style_mut().text_styles.insert(
@@ -366,15 +611,8 @@ impl eframe::App for PieTopApp {
                                             "Initial Value"
                                         };
                                         if ui.add(egui::Button::new(egui::RichText::new(initial_text).strong())).clicked() {
-                                            if self.sort_column == SortColumn::InitialValue {
-                                                self.sort_direction = match self.sort_direction {
-                                                    SortDirection::Ascending => SortDirection::Descending,
-                                                    SortDirection::Descending => SortDirection::Ascending,
-                                                };
-                                            } else {
-                                                self.sort_column = SortColumn::InitialValue;
-                                                self.sort_direction = SortDirection::Descending;
-                                            }
+                                            (self.sort_column, self.sort_direction) =
+                                                select_column(self.sort_column, self.sort_direction, SortColumn::InitialValue);
                                         }
                                     });
                                     header.col(|ui| {
@@ -391,15 +629,8 @@ impl eframe::App for PieTopApp {
                                             "Current Value"
                                         };
                                         if ui.add(egui::Button::new(egui::RichText::new(current_text).strong())).clicked() {
-                                            if self.sort_column == SortColumn::CurrentValue {
-                                                self.sort_direction = match self.sort_direction {
-                                                    SortDirection::Ascending => SortDirection::Descending,
-                                                    SortDirection::Descending => SortDirection::Ascending,
-                                                };
-                                            } else {
-                                                self.sort_column = SortColumn::CurrentValue;
-                                                self.sort_direction = SortDirection::Descending;
-                                            }
+                                            (self.sort_column, self.sort_direction) =
+                                                select_column(self.sort_column, self.sort_direction, SortColumn::CurrentValue);
                                         }
                                     });
                                     header.col(|ui| {
@@ -416,15 +647,8 @@ impl eframe::App for PieTopApp {
                                             "Return %"
                                         };
                                         if ui.add(egui::Button::new(egui::RichText::new(return_text).strong())).clicked() {
-                                            if self.sort_column == SortColumn::Return {
-                                                self.sort_direction = match self.sort_direction {
-                                                    SortDirection::Ascending => SortDirection::Descending,
-                                                    SortDirection::Descending => SortDirection::Ascending,
-                                                };
-                                            } else {
-                                                self.sort_column = SortColumn::Return;
-                                                self.sort_direction = SortDirection::Descending;
-                                            }
+                                            (self.sort_column, self.sort_direction) =
+                                                select_column(self.sort_column, self.sort_direction, SortColumn::Return);
                                         }
                                     });
                                     header.col(|ui| {
@@ -441,15 +665,8 @@ impl eframe::App for PieTopApp {
                                             "Return Value"
                                         };
                                         if ui.add(egui::Button::new(egui::RichText::new(return_value_text).strong())).clicked() {
-                                            if self.sort_column == SortColumn::ReturnValue {
-                                                self.sort_direction = match self.sort_direction {
-                                                    SortDirection::Ascending => SortDirection::Descending,
-                                                    SortDirection::Descending => SortDirection::Ascending,
-                                                };
-                                            } else {
-                                                self.sort_column = SortColumn::ReturnValue;
-                                                self.sort_direction = SortDirection::Descending;
-                                            }
+                                            (self.sort_column, self.sort_direction) =
+                                                select_column(self.sort_column, self.sort_direction, SortColumn::ReturnValue);
                                         }
                                     });
                                     header.col(|ui| {
@@ -466,15 +683,94 @@ impl eframe::App for PieTopApp {
                                             "Annual Rate %"
                                         };
                                         if ui.add(egui::Button::new(egui::RichText::new(annual_text).strong())).clicked() {
-                                            if self.sort_column == SortColumn::AnnualRate {
-                                                self.sort_direction = match self.sort_direction {
-                                                    SortDirection::Ascending => SortDirection::Descending,
-                                                    SortDirection::Descending => SortDirection::Ascending,
-                                                };
-                                            } else {
-                                                self.sort_column = SortColumn::AnnualRate;
-                                                self.sort_direction = SortDirection::Descending;
+                                            (self.sort_column, self.sort_direction) =
+                                                select_column(self.sort_column, self.sort_direction, SortColumn::AnnualRate);
+                                        }
+                                    });
+                                    header.col(|ui| {
+                                        ui.style_mut().text_styles.insert(
+                                            egui::TextStyle::Body,
+                                            egui::FontId::new(16.0, egui::FontFamily::Proportional)
+                                        );
+                                        let irr_text = if self.sort_column == SortColumn::Irr {
+                                            match self.sort_direction {
+                                                SortDirection::Ascending => "IRR % ⬆️",
+                                                SortDirection::Descending => "IRR % ⬇️",
                                             }
+                                        } else {
+                                            "IRR %"
+                                        };
+                                        let response = ui.add(egui::Button::new(egui::RichText::new(irr_text).strong()))
+                                            .on_hover_text("Annualised money-weighted return (XIRR) from the pie's deposits and withdrawals");
+                                        if response.clicked() {
+                                            (self.sort_column, self.sort_direction) =
+                                                select_column(self.sort_column, self.sort_direction, SortColumn::Irr);
+                                        }
+                                    });
+                                    header.col(|ui| {
+                                        ui.style_mut().text_styles.insert(
+                                            egui::TextStyle::Body,
+                                            egui::FontId::new(16.0, egui::FontFamily::Proportional)
+                                        );
+                                        let twr_text = if self.sort_column == SortColumn::Twr {
+                                            match self.sort_direction {
+                                                SortDirection::Ascending => "TWR % ⬆️",
+                                                SortDirection::Descending => "TWR % ⬇️",
+                                            }
+                                        } else {
+                                            "TWR %"
+                                        };
+                                        let response = ui.add(egui::Button::new(egui::RichText::new(twr_text).strong()))
+                                            .on_hover_text(format!(
+                                                "Time-weighted return over the summary's TWR period ({})",
+                                                self.twr_period.label()
+                                            ));
+                                        if response.clicked() {
+                                            (self.sort_column, self.sort_direction) =
+                                                select_column(self.sort_column, self.sort_direction, SortColumn::Twr);
+                                        }
+                                    });
+                                    for (title, hover, column) in [
+                                        ("Volatility", "Annualised standard deviation of daily returns", SortColumn::Volatility),
+                                        ("Max DD", "Largest fall from a peak (maximum drawdown)", SortColumn::MaxDrawdown),
+                                        ("Current DD", "How far below its peak the pie is now", SortColumn::CurrentDrawdown),
+                                        ("Sharpe", "Return above the risk-free rate per unit of volatility", SortColumn::Sharpe),
+                                        ("Sortino", "Like Sharpe, counting only downside volatility", SortColumn::Sortino),
+                                    ] {
+                                        header.col(|ui| {
+                                            ui.style_mut().text_styles.insert(
+                                                egui::TextStyle::Body,
+                                                egui::FontId::new(16.0, egui::FontFamily::Proportional)
+                                            );
+                                            let text = match (self.sort_column == column, self.sort_direction) {
+                                                (true, SortDirection::Ascending) => format!("{} ⬆️", title),
+                                                (true, SortDirection::Descending) => format!("{} ⬇️", title),
+                                                (false, _) => title.to_string(),
+                                            };
+                                            let response = ui.add(egui::Button::new(egui::RichText::new(text).strong()))
+                                                .on_hover_text(format!("{}, over the risk period ({})", hover, self.risk_period.label()));
+                                            if response.clicked() {
+                                                (self.sort_column, self.sort_direction) =
+                                                    select_column(self.sort_column, self.sort_direction, column);
+                                            }
+                                        });
+                                    }
+                                    header.col(|ui| {
+                                        ui.style_mut().text_styles.insert(
+                                            egui::TextStyle::Body,
+                                            egui::FontId::new(16.0, egui::FontFamily::Proportional)
+                                        );
+                                        let dividends_text = if self.sort_column == SortColumn::Dividends {
+                                            match self.sort_direction {
+                                                SortDirection::Ascending => "Dividends ⬆️",
+                                                SortDirection::Descending => "Dividends ⬇️",
+                                            }
+                                        } else {
+                                            "Dividends"
+                                        };
+                                        if ui.add(egui::Button::new(egui::RichText::new(dividends_text).strong())).clicked() {
+                                            (self.sort_column, self.sort_direction) =
+                                                select_column(self.sort_column, self.sort_direction, SortColumn::Dividends);
                                         }
                                     });
                                     header.col(|ui| {
@@ -486,13 +782,22 @@ impl eframe::App for PieTopApp {
                                     });
                                 })
                                 .body(|mut body| {
+                                    // The first benchmark on the chart is compared against each pie's annual rate
+                                    let comparison_benchmark = self.chart_benchmarks.first()
+                                        .and_then(|name| self.benchmarks.iter().find(|benchmark| benchmark.name == *name));
                                     for pie in &pies_data {
                                         let result_percent = pie.result.price_avg_result_coef * 100.0;
                                         let annual_rate = calculate_annual_rate(
                                             pie.result.price_avg_invested_value,
                                             pie.result.price_avg_value,
-                                            pie.created_at.unwrap_or_default() as f64,
+                                            pie.created_at.unwrap_or_default(),
                                         );
+                                        // Over the pie's recorded history, where deposits are known
+                                        let comparison = comparison_benchmark
+                                            .zip(self.figures.twr_span.get(&pie.id))
+                                            .and_then(|(benchmark, span)| {
+                                                benchmark.compare(*span).map(|comparison| (&benchmark.name, *span, comparison))
+                                            });
 
                                         body.row(22.0, |mut row| {
                                             row.col(|ui| {
@@ -502,6 +807,24 @@ impl eframe::App for PieTopApp {
                                                 );
                                                 let pie_name = pie.name.clone().unwrap_or_else(|| format!("Pie {}", pie.id));
                                                 
+                                                // Expand/collapse toggle for the instrument rows
+                                                if !pie.instruments.is_empty() {
+                                                    let expanded = self.expanded_pies.contains(&pie.id);
+                                                    if ui.small_button(if expanded { "▼" } else { "▶" }).clicked() {
+                                                        if expanded {
+                                                            self.expanded_pies.remove(&pie.id);
+                                                        } else {
+                                                            self.expanded_pies.insert(pie.id);
+                                                        }
+                                                    }
+                                                    if ui.small_button("⚖")
+                                                        .on_hover_text("Plan a deposit or a rebalance")
+                                                        .clicked()
+                                                    {
+                                                        self.planner_pie = Some(pie.id);
+                                                    }
+                                                }
+                                                
                                                 // Use truncated label that respects column width
                                                 let response = ui.add(
                                                     egui::Label::new(&pie_name)
@@ -565,6 +888,134 @@ impl eframe::App for PieTopApp {
                                                     egui::Color32::WHITE
                                                 };
                                                 ui.colored_label(annual_color, format!("{:.2}%", annual_rate));
+                                                if let Some((benchmark_name, span, comparison)) = &comparison {
+                                                    let excess_color = if comparison.excess_return >= 0.0 {
+                                                        egui::Color32::GREEN
+                                                    } else {
+                                                        egui::Color32::RED
+                                                    };
+                                                    ui.label(egui::RichText::new(format!("{:+.2}", comparison.excess_return)).small().color(excess_color))
+                                                        .on_hover_text(format!(
+                                                            "Against {} from {} to {}\n\
+                                                             Time-weighted annual rate: {:.2}%\n\
+                                                             Benchmark annual rate: {:.2}%\n\
+                                                             Excess return: {:+.2} points a year\n\
+                                                             Benchmark return: {:+.2}%\n\
+                                                             Tracking difference: {:+.2} points",
+                                                            benchmark_name,
+                                                            format_timestamp(span.from, "%Y-%m-%d"),
+                                                            format_timestamp(span.to, "%Y-%m-%d"),
+                                                            comparison.annual_rate,
+                                                            comparison.benchmark_annual_rate,
+                                                            comparison.excess_return,
+                                                            comparison.benchmark_return,
+                                                            comparison.tracking_difference,
+                                                        ));
+                                                }
+                                            });
+                                            row.col(|ui| {
+                                                ui.style_mut().text_styles.insert(
+                                                    egui::TextStyle::Body,
+                                                    egui::FontId::new(16.0, egui::FontFamily::Proportional)
+                                                );
+                                                match self.figures.irr.get(&pie.id) {
+                                                    Some(irr) => {
+                                                        let irr_percent = irr * 100.0;
+                                                        let irr_color = if irr_percent > 0.0 {
+                                                            egui::Color32::GREEN
+                                                        } else if irr_percent < 0.0 {
+                                                            egui::Color32::RED
+                                                        } else {
+                                                            egui::Color32::WHITE
+                                                        };
+                                                        ui.colored_label(irr_color, format!("{:.2}%", irr_percent));
+                                                    }
+                                                    None => {
+                                                        ui.label("-");
+                                                    }
+                                                }
+                                            });
+                                            row.col(|ui| {
+                                                ui.style_mut().text_styles.insert(
+                                                    egui::TextStyle::Body,
+                                                    egui::FontId::new(16.0, egui::FontFamily::Proportional)
+                                                );
+                                                match self.figures.twr.get(&pie.id).and_then(|twr| twr.get(&self.twr_period)) {
+                                                    Some(twr) => {
+                                                        let twr_percent = twr * 100.0;
+                                                        let twr_color = if twr_percent > 0.0 {
+                                                            egui::Color32::GREEN
+                                                        } else if twr_percent < 0.0 {
+                                                            egui::Color32::RED
+                                                        } else {
+                                                            egui::Color32::WHITE
+                                                        };
+                                                        ui.colored_label(twr_color, format!("{:+.2}%", twr_percent));
+                                                    }
+                                                    None => {
+                                                        ui.label("-").on_hover_text("Not enough history for this period");
+                                                    }
+                                                }
+                                            });
+                                            let metrics = self.figures.risk.get(&pie.id);
+                                            row.col(|ui| {
+                                                ui.style_mut().text_styles.insert(
+                                                    egui::TextStyle::Body,
+                                                    egui::FontId::new(16.0, egui::FontFamily::Proportional)
+                                                );
+                                                match metrics.and_then(|m| m.volatility) {
+                                                    Some(volatility) => ui.label(format!("{:.2}%", volatility * 100.0)),
+                                                    None => ui.label("-"),
+                                                };
+                                            });
+                                            row.col(|ui| {
+                                                ui.style_mut().text_styles.insert(
+                                                    egui::TextStyle::Body,
+                                                    egui::FontId::new(16.0, egui::FontFamily::Proportional)
+                                                );
+                                                match metrics {
+                                                    Some(metrics) => {
+                                                        ui.colored_label(drawdown_color(metrics.max_drawdown), format!("{:.2}%", metrics.max_drawdown * 100.0))
+                                                            .on_hover_text(drawdown_dates(metrics));
+                                                    }
+                                                    None => {
+                                                        ui.label("-");
+                                                    }
+                                                }
+                                            });
+                                            row.col(|ui| {
+                                                ui.style_mut().text_styles.insert(
+                                                    egui::TextStyle::Body,
+                                                    egui::FontId::new(16.0, egui::FontFamily::Proportional)
+                                                );
+                                                match metrics {
+                                                    Some(metrics) => ui.colored_label(drawdown_color(metrics.current_drawdown), format!("{:.2}%", metrics.current_drawdown * 100.0)),
+                                                    None => ui.label("-"),
+                                                };
+                                            });
+                                            for ratio in [metrics.and_then(|m| m.sharpe), metrics.and_then(|m| m.sortino)] {
+                                                row.col(|ui| {
+                                                    ui.style_mut().text_styles.insert(
+                                                        egui::TextStyle::Body,
+                                                        egui::FontId::new(16.0, egui::FontFamily::Proportional)
+                                                    );
+                                                    match ratio {
+                                                        Some(ratio) => ui.colored_label(ratio_color(ratio), format!("{:.2}", ratio)),
+                                                        None => ui.label("-"),
+                                                    };
+                                                });
+                                            }
+                                            row.col(|ui| {
+                                                ui.style_mut().text_styles.insert(
+                                                    egui::TextStyle::Body,
+                                                    egui::FontId::new(16.0, egui::FontFamily::Proportional)
+                                                );
+                                                let dividends = &pie.dividend_details;
+                                                ui.label(format!("${:.2}", dividends.gained))
+                                                    .on_hover_text(format!(
+                                                        "Reinvested: ${:.2}\nIn cash: ${:.2}",
+                                                        dividends.reinvested, dividends.in_cash,
+                                                    ));
                                             });
                                             row.col(|ui| {
                                                 ui.style_mut().text_styles.insert(
@@ -574,6 +1025,82 @@ impl eframe::App for PieTopApp {
                                                 ui.label(pie.status.as_deref().unwrap_or("Active"));
                                             });
                                         });
+                                        
+                                        if !self.expanded_pies.contains(&pie.id) {
+                                            continue;
+                                        }
+                                        
+                                        // Instrument rows, largest holding first
+                                        let mut instruments: Vec<_> = pie.instruments.iter().collect();
+                                        instruments.sort_by(|a, b| b.result.price_avg_value.partial_cmp(&a.result.price_avg_value).unwrap_or(std::cmp::Ordering::Equal));
+                                        for instrument in instruments {
+                                            body.row(20.0, |mut row| {
+                                                row.col(|ui| {
+                                                    let response = ui.add(
+                                                        egui::Label::new(egui::RichText::new(format!("    ↳ {}", instrument.ticker)).weak())
+                                                            .truncate()
+                                                    );
+                                                    response.on_hover_text(format!("{} ({:.4} shares)", instrument.ticker, instrument.owned_quantity));
+                                                });
+                                                row.col(|ui| {
+                                                    ui.label(format!("${:.2}", instrument.result.price_avg_invested_value));
+                                                });
+                                                row.col(|ui| {
+                                                    ui.label(format!("${:.2}", instrument.result.price_avg_value));
+                                                });
+                                                row.col(|ui| {
+                                                    let result_percent = instrument.result.price_avg_result_coef * 100.0;
+                                                    let return_color = if result_percent > 0.0 {
+                                                        egui::Color32::GREEN
+                                                    } else if result_percent < 0.0 {
+                                                        egui::Color32::RED
+                                                    } else {
+                                                        egui::Color32::WHITE
+                                                    };
+                                                    ui.colored_label(return_color, format!("{:+.2}%", result_percent));
+                                                });
+                                                row.col(|ui| {
+                                                    let return_value = instrument.result.price_avg_result;
+                                                    let return_color = if return_value > 0.0 {
+                                                        egui::Color32::GREEN
+                                                    } else if return_value < 0.0 {
+                                                        egui::Color32::RED
+                                                    } else {
+                                                        egui::Color32::WHITE
+                                                    };
+                                                    ui.colored_label(return_color, format!("{:+.2}", return_value));
+                                                });
+                                                row.col(|ui| {
+                                                    ui.label(format!(
+                                                        "Weight {:.2}% / {:.2}%",
+                                                        instrument.current_share * 100.0,
+                                                        instrument.expected_share * 100.0,
+                                                    ))
+                                                    .on_hover_text("Current / target weight in the pie");
+                                                });
+                                                // Nothing per instrument from IRR to Dividends; drift goes under Status
+                                                for _ in INSTRUMENT_FIGURE_COLUMNS..PIE_TABLE_COLUMNS.len() - 1 {
+                                                    row.col(|_ui| {});
+                                                }
+                                                row.col(|ui| {
+                                                    let drift = instrument.drift();
+                                                    let drift_color = if drift.abs() >= 5.0 {
+                                                        egui::Color32::YELLOW
+                                                    } else {
+                                                        egui::Color32::WHITE
+                                                    };
+                                                    let response = ui.colored_label(drift_color, format!("Drift {:+.2}%", drift));
+                                                    if !instrument.issues.is_empty() {
+                                                        let issues: Vec<String> = instrument.issues.iter()
+                                                            .map(|issue| format!("{} ({})", issue.name, issue.severity))
+                                                            .collect();
+                                                        ui.colored_label(egui::Color32::RED, "⚠").on_hover_text(issues.join("\n"));
+                                                    } else {
+                                                        response.on_hover_text("Current minus target weight, in percentage points");
+                                                    }
+                                                });
+                                            });
+                                        }
                                     }
                                 });
                     
@@ -581,179 +1108,242 @@ impl eframe::App for PieTopApp {
                     self.pie_list_height = ui.min_rect().height();
                 });
 
+            if let Some(pie) = self.planner_pie.and_then(|id| pies_data.iter().find(|pie| pie.id == id)) {
+                let mut open = true;
+                egui::Window::new(format!("⚖ Rebalancing planner – {}", pie.name.as_deref().unwrap_or("Pie")))
+                    .id(egui::Id::new("rebalancing_planner"))
+                    .open(&mut open)
+                    .collapsible(false)
+                    .resizable(true)
+                    .show(ctx, |ui| show_rebalance_planner(ui, pie, &mut self.planner_deposit));
+                if !open {
+                    self.planner_pie = None;
+                }
+            }
+
             // Chart section in the remaining space
             egui::CentralPanel::default().show_inside(ui, |ui| {
                 // Portfolio Value Chart with time view controls
                 ui.horizontal(|ui| {
-                    ui.heading("📈 Portfolio Value Chart");
+                    ui.selectable_value(&mut self.chart_view, ChartView::History, egui::RichText::new("📈 Portfolio Value Chart").heading());
+                    ui.selectable_value(&mut self.chart_view, ChartView::Allocation, egui::RichText::new("🍩 Allocation").heading());
                     
                     ui.separator();
                     
-                    // Pie selection dropdown
+                    if self.chart_view == ChartView::Allocation {
+                        ui.label("By:");
+                        ui.selectable_value(&mut self.allocation_basis, AllocationBasis::Value, "Current value");
+                        ui.selectable_value(&mut self.allocation_basis, AllocationBasis::Invested, "Invested value");
+                        return;
+                    }
+                    
+                    // Series selection dropdown; several series are drawn on top of each other
                     ui.label("Show:");
                     egui::ComboBox::from_label("")
-                        .selected_text(match self.selected_chart_pie {
-                            None => "Total Portfolio".to_string(),
-                            Some(pie_id) => {
-                                // Find the pie name from current data
-                                let pies_data = if let Ok(pies_guard) = self.pies.try_lock() {
-                                    pies_guard.values().cloned().collect::<Vec<_>>()
-                                } else {
-                                    Vec::new()
-                                };
-                                
-                                pies_data.iter()
-                                    .find(|p| p.id == pie_id)
-                                    .and_then(|p| p.name.as_ref())
-                                    .cloned()
-                                    .unwrap_or_else(|| format!("Pie {}", pie_id))
-                            }
+                        .selected_text(match self.chart_series.as_slice() {
+                            [] => "Nothing".to_string(),
+                            [series] => series_name(*series, &pies_data),
+                            series => format!("{} series", series.len()),
                         })
                         .show_ui(ui, |ui| {
-                            // Total Portfolio option
-                            ui.selectable_value(&mut self.selected_chart_pie, None, "Total Portfolio");
-                            
-                            // Individual pie options
-                            let pies_data = if let Ok(pies_guard) = self.pies.try_lock() {
-                                pies_guard.values().cloned().collect::<Vec<_>>()
-                            } else {
-                                Vec::new()
-                            };
-                            
-                            for pie in &pies_data {
-                                let pie_name = pie.name.clone().unwrap_or_else(|| format!("Pie {}", pie.id));
-                                ui.selectable_value(&mut self.selected_chart_pie, Some(pie.id), pie_name);
+                            // Total Portfolio first, then the pies in table order
+                            let options = std::iter::once(None).chain(pies_data.iter().map(|pie| Some(pie.id)));
+                            for series in options {
+                                let mut shown = self.chart_series.contains(&series);
+                                let label = egui::RichText::new(series_name(series, &pies_data)).color(series_color(series));
+                                if ui.checkbox(&mut shown, label).changed() {
+                                    if shown {
+                                        self.chart_series.push(series);
+                                    } else {
+                                        self.chart_series.retain(|s| *s != series);
+                                    }
+                                }
+                            }
+                            if !self.benchmarks.is_empty() {
+                                ui.separator();
+                                ui.weak("Benchmarks, rebased to the first series");
+                                for benchmark in &self.benchmarks {
+                                    let mut shown = self.chart_benchmarks.contains(&benchmark.name);
+                                    let label = egui::RichText::new(&benchmark.name).color(benchmark_color(&benchmark.name));
+                                    if ui.checkbox(&mut shown, label).changed() {
+                                        if shown {
+                                            self.chart_benchmarks.push(benchmark.name.clone());
+                                        } else {
+                                            self.chart_benchmarks.retain(|name| *name != benchmark.name);
+                                        }
+                                    }
+                                }
+                            }
+                            ui.separator();
+                            if ui.button("Only Total Portfolio").clicked() {
+                                self.chart_series = vec![None];
+                                self.chart_benchmarks.clear();
                             }
                         });
                     
                     ui.separator();
                     
+                    // Chart mode buttons
+                    ui.label("Plot:");
+                    ui.selectable_value(&mut self.chart_mode, ChartMode::Value, "Value");
+                    ui.selectable_value(&mut self.chart_mode, ChartMode::Indexed, "Index")
+                        .on_hover_text("Value rebased to 100 at the start of the visible range, to compare series");
+                    ui.selectable_value(&mut self.chart_mode, ChartMode::Twr, "TWR %")
+                        .on_hover_text("Cumulative time-weighted return from the start of the visible range");
+                    ui.selectable_value(&mut self.chart_mode, ChartMode::Drawdown, "Drawdown")
+                        .on_hover_text("How far below its highest point in the visible range, leaving out deposits");
+                    
+                    ui.separator();
+                    
                     // Time view buttons
                     ui.label("View:");
-                    if ui.selectable_label(self.time_view == TimeView::TenMinutes, "10m").clicked() {
-                        self.time_view = TimeView::TenMinutes;
-                    }
-                    if ui.selectable_label(self.time_view == TimeView::OneHour, "1h").clicked() {
-                        self.time_view = TimeView::OneHour;
-                    }
-                    if ui.selectable_label(self.time_view == TimeView::OneDay, "1d").clicked() {
-                        self.time_view = TimeView::OneDay;
+                    for (time_view, label) in [
+                        (TimeView::TenMinutes, "10m"),
+                        (TimeView::OneHour, "1h"),
+                        (TimeView::OneDay, "1d"),
+                        (TimeView::OneWeek, "1w"),
+                        (TimeView::OneMonth, "1mo"),
+                        (TimeView::OneYear, "1y"),
+                        (TimeView::All, "All"),
+                    ] {
+                        if ui.selectable_label(self.time_view == time_view, label).clicked() {
+                            self.time_view = time_view;
+                        }
                     }
                 });
                 
-                if self.total_value_history.len() >= 2 {
+                if self.chart_view == ChartView::Allocation {
+                    show_allocation_view(ui, &pies_data, &account, self.allocation_basis, &mut self.chart_series);
+                    return;
+                }
+                
+                let Ok(history) = self.history.try_lock() else {
+                    return;
+                };
+                if history.total.len() >= 2 {
                     // Filter data based on selected time view
                     let current_time = Utc::now().timestamp() as f64;
-                    let (cutoff_time, max_time_ago, x_label) = match self.time_view {
-                        TimeView::TenMinutes => (current_time - 600.0, 10.0, "Time (Minutes Ago)"),
-                        TimeView::OneHour => (current_time - 3600.0, 60.0, "Time (Minutes Ago)"),
-                        TimeView::OneDay => (current_time - 86400.0, 24.0, "Time (Hours Ago)"),
-                    };
+                    let cutoff_time = self.time_view.duration_secs()
+                        .map(|duration| current_time - duration)
+                        .unwrap_or(f64::NEG_INFINITY);
                     
-                    match self.selected_chart_pie {
-                        None => {
-                            // Show total portfolio value
-                            let filtered_total_points: Vec<_> = self.total_value_history
-                                .iter()
-                                .filter(|point| point.timestamp >= cutoff_time)
-                                .collect();
-                            
-                            if filtered_total_points.len() >= 2 {
-                                let total_plot_points: PlotPoints = filtered_total_points
-                                    .iter()
-                                    .map(|point| {
-                                        let time_ago = current_time - point.timestamp;
-                                        let x_value = match self.time_view {
-                                            TimeView::OneDay => -time_ago / 3600.0, // Hours ago
-                                            _ => -time_ago / 60.0, // Minutes ago
-                                        };
-                                        [x_value, point.total_value]
-                                    })
-                                    .collect();
-                                
-                                // Calculate Y-axis bounds for better scaling
-                                let values: Vec<f64> = filtered_total_points.iter().map(|p| p.total_value).collect();
-                                let min_value = values.iter().copied().fold(f64::INFINITY, f64::min);
-                                let max_value = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
-                                let padding = (max_value - min_value) * 0.05; // 5% padding
-                                
-                                let total_line = Line::new(total_plot_points)
-                                    .color(egui::Color32::from_rgb(70, 180, 220)) // Sky blue
-                                    .width(3.0)
-                                    .name("Total Portfolio Value");
-                                
-                                Plot::new("portfolio_value_plot")
-                                    .width(ui.available_width())
-                                    .height(ui.available_height() - 50.0)
-                                    .legend(egui_plot::Legend::default().position(egui_plot::Corner::LeftTop))
-                                    .x_axis_label(x_label)
-                                    .y_axis_label("Value ($)")
-                                    .include_x(-max_time_ago)
-                                    .include_x(0.0)
-                                    .include_y(min_value - padding)
-                                    .include_y(max_value + padding)
-                                    .show_grid(false)
-                                    .show(ui, |plot_ui| {
-                                        plot_ui.line(total_line);
-                                    });
-                            } else {
-                                ui.label("📊 Not enough data points for selected time range");
+                    let mut lines: Vec<(Vec<[f64; 2]>, String, egui::Color32)> = Vec::new();
+                    let mut anchor: Option<[f64; 2]> = None; // First sample of the first series, where benchmarks start
+                    for &series in &self.chart_series {
+                        let points: Vec<[f64; 2]> = match series {
+                            None => {
+                                // Show total portfolio value
+                                let start = history.total.partition_point(|point| point.timestamp < cutoff_time);
+                                let total_points = history.total[start..].iter();
+                                match self.chart_mode {
+                                    ChartMode::Value => total_points.map(|point| [point.timestamp, point.total_value]).collect(),
+                                    ChartMode::Indexed => indexed_series(total_points.map(|point| [point.timestamp, point.total_value])),
+                                    ChartMode::Twr => twr_percent_series(&returns::total_flow_samples(total_points)),
+                                    ChartMode::Drawdown => percent_series(risk::underwater(&returns::total_flow_samples(total_points))),
+                                }
                             }
+                            Some(selected_pie_id) => {
+                                // Show specific pie value
+                                let pie_points = history.pie(selected_pie_id);
+                                let pie_points = &pie_points[pie_points.partition_point(|point| point.timestamp < cutoff_time)..];
+                                match self.chart_mode {
+                                    ChartMode::Value => pie_points.iter().map(|point| [point.timestamp, point.value]).collect(),
+                                    ChartMode::Indexed => indexed_series(pie_points.iter().map(|point| [point.timestamp, point.value])),
+                                    ChartMode::Twr => twr_percent_series(&returns::pie_flow_samples(pie_points)),
+                                    ChartMode::Drawdown => percent_series(risk::underwater(&returns::pie_flow_samples(pie_points))),
+                                }
+                            }
+                        };
+                        if points.len() >= 2 {
+                            anchor.get_or_insert(points[0]);
+                            // Roughly one bucket per horizontal pixel keeps long ranges cheap to draw
+                            let points = downsample::min_max_last(&points, ui.available_width() as usize);
+                            lines.push((points, series_name(series, &pies_data), series_color(series)));
                         }
-                        Some(selected_pie_id) => {
-                            // Show specific pie value
-                            let pie_points: Vec<_> = self.pie_value_history
-                                .iter()
-                                .filter(|point| point.pie_id == selected_pie_id && point.timestamp >= cutoff_time)
-                                .collect();
-                            
-                            if pie_points.len() >= 2 {
-                                let pie_plot_points: PlotPoints = pie_points
-                                    .iter()
-                                    .map(|point| {
-                                        let time_ago = current_time - point.timestamp;
-                                        let x_value = match self.time_view {
-                                            TimeView::OneDay => -time_ago / 3600.0, // Hours ago
-                                            _ => -time_ago / 60.0, // Minutes ago
-                                        };
-                                        [x_value, point.value]
-                                    })
-                                    .collect();
-                                
-                                // Calculate Y-axis bounds for better scaling
-                                let values: Vec<f64> = pie_points.iter().map(|p| p.value).collect();
-                                let min_value = values.iter().copied().fold(f64::INFINITY, f64::min);
-                                let max_value = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
-                                let padding = (max_value - min_value).max(1.0) * 0.05; // 5% padding, minimum 1.0
-                                
-                                let pie_name = pie_points.first()
-                                    .map(|p| p.pie_name.clone())
-                                    .unwrap_or_else(|| format!("Pie {}", selected_pie_id));
-                                
-                                let pie_line = Line::new(pie_plot_points)
-                                    .color(egui::Color32::from_rgb(220, 70, 180)) // Pink
-                                    .width(3.0)
-                                    .name(pie_name);
-                                
-                                Plot::new("portfolio_value_plot")
-                                    .width(ui.available_width())
-                                    .height(ui.available_height() - 50.0)
-                                    .legend(egui_plot::Legend::default().position(egui_plot::Corner::LeftTop))
-                                    .x_axis_label(x_label)
-                                    .y_axis_label("Value ($)")
-                                    .include_x(-max_time_ago)
-                                    .include_x(0.0)
-                                    .include_y(min_value - padding)
-                                    .include_y(max_value + padding)
-                                    .show_grid(false)
-                                    .show(ui, |plot_ui| {
-                                        plot_ui.line(pie_line);
-                                    });
-                            } else {
-                                ui.label("📊 Not enough data points for selected pie in the time range");
+                    }
+                    
+                    // Benchmarks start where the first series does, at its value (or return)
+                    let mut benchmark_lines: Vec<(Vec<[f64; 2]>, String, egui::Color32)> = Vec::new();
+                    if let Some([from, base]) = anchor {
+                        for name in &self.chart_benchmarks {
+                            let Some(benchmark) = self.benchmarks.iter().find(|benchmark| benchmark.name == *name) else {
+                                continue;
+                            };
+                            let points: Vec<[f64; 2]> = match self.chart_mode {
+                                ChartMode::Value | ChartMode::Indexed => benchmark.rebased(from, current_time, base),
+                                ChartMode::Twr => benchmark.rebased(from, current_time, 100.0)
+                                    .into_iter()
+                                    .map(|[timestamp, value]| [timestamp, value - 100.0])
+                                    .collect(),
+                                ChartMode::Drawdown => percent_series(risk::drawdowns(&benchmark.rebased(from, current_time, 1.0))),
+                            };
+                            if points.len() >= 2 {
+                                benchmark_lines.push((points, format!("{} (benchmark)", name), benchmark_color(name)));
                             }
                         }
                     }
+                    
+                    if !lines.is_empty() {
+                        // Calculate Y-axis bounds for better scaling
+                        let all_points = || lines.iter().chain(&benchmark_lines).flat_map(|(points, _, _)| points.iter());
+                        let min_value = all_points().map(|p| p[1]).fold(f64::INFINITY, f64::min);
+                        let max_value = all_points().map(|p| p[1]).fold(f64::NEG_INFINITY, f64::max);
+                        let padding = (max_value - min_value).max(1.0) * 0.05; // 5% padding, minimum 1.0
+                        let (y_label, y_format): (&str, fn(f64) -> String) = match self.chart_mode {
+                            ChartMode::Value => ("Value ($)", |y| format!("${:.2}", y)),
+                            ChartMode::Indexed => ("Value (start = 100)", |y| format!("{:.2}", y)),
+                            ChartMode::Twr => ("Time-Weighted Return (%)", |y| format!("{:+.2}%", y)),
+                            ChartMode::Drawdown => ("Drawdown (%)", |y| format!("{:.2}%", y)),
+                        };
+                        let start_time = cutoff_time.max(all_points().map(|p| p[0]).fold(f64::INFINITY, f64::min));
+                        // Thinner lines keep overlapping series readable
+                        let width = if lines.len() > 1 { 2.0 } else { 3.0 };
+                        
+                        Plot::new("portfolio_value_plot")
+                            .width(ui.available_width())
+                            .height(ui.available_height() - 50.0)
+                            .legend(egui_plot::Legend::default().position(egui_plot::Corner::LeftTop))
+                            .x_axis_label("Date")
+                            .y_axis_label(y_label)
+                            .x_grid_spacer(time_grid_spacer)
+                            .x_axis_formatter(|mark, range| format_time_axis(mark.value, range.end() - range.start()))
+                            .label_formatter(|name, value| {
+                                let time = format_timestamp(value.x, "%Y-%m-%d %H:%M");
+                                if name.is_empty() {
+                                    format!("{}\n{}", time, y_format(value.y))
+                                } else {
+                                    format!("{}\n{}\n{}", name, time, y_format(value.y))
+                                }
+                            })
+                            .include_x(start_time)
+                            .include_x(current_time)
+                            .include_y(min_value - padding)
+                            .include_y(max_value + padding)
+                            .show_grid(false)
+                            .show(ui, |plot_ui| {
+                                for (points, name, color) in lines {
+                                    let line = Line::new(PlotPoints::from(points)).color(color).width(width).name(name);
+                                    // Shade the area under water
+                                    let line = if self.chart_mode == ChartMode::Drawdown { line.fill(0.0) } else { line };
+                                    plot_ui.line(line);
+                                }
+                                for (points, name, color) in benchmark_lines {
+                                    plot_ui.line(
+                                        Line::new(PlotPoints::from(points))
+                                            .color(color)
+                                            .width(2.0)
+                                            .style(egui_plot::LineStyle::dashed_loose())
+                                            .name(name),
+                                    );
+                                }
+                            });
+                    } else if self.chart_series.is_empty() {
+                        ui.label("📊 Pick a series to plot under Show");
+                    } else if self.chart_series == [None] {
+                        ui.label("📊 Not enough data points for selected time range");
+                    } else {
+                        ui.label("📊 Not enough data points for the selected pies in the time range");
+                    }
                 } else {
                     ui.label("📊 Collecting data for chart... Need at least 2 data points");
                 }
@@ -767,36 +1357,662 @@ impl eframe::App for PieTopApp {
 }
 
 
-fn save_map(map: &HashMap<usize, Pie>, path: &str) -> std::io::Result<()> {
-    let json = serde_json::to_string_pretty(map).unwrap();
-    let mut file = File::create(path)?;
-    file.write_all(json.as_bytes())?;
-    Ok(())
+/// Deposit split and full rebalance for one pie, from the weights of its last detail fetch.
+fn show_rebalance_planner(ui: &mut egui::Ui, pie: &Pie, deposit: &mut f64) {
+    ui.horizontal(|ui| {
+        ui.label("Deposit:");
+        ui.add(egui::DragValue::new(deposit).speed(10.0).range(0.0..=f64::MAX).prefix("$"));
+        ui.separator();
+        ui.label(format!("Pie value: ${:.2}", pie.result.price_avg_value));
+        if pie.cash > 0.0 {
+            ui.separator();
+            ui.label(format!("Pie cash: ${:.2}", pie.cash))
+                .on_hover_text("Uninvested cash in the pie; add it to the deposit to plan it in too");
+        }
+    });
+    ui.separator();
+
+    ui.strong("Deposit only");
+    ui.label(egui::RichText::new("Buys the most underweight instruments first; nothing is sold").weak());
+    show_plan_grid(ui, "deposit_plan", &rebalance::deposit_plan(pie, *deposit));
+    ui.separator();
+
+    ui.strong("Full rebalance");
+    ui.label(egui::RichText::new("Buys and sells to put every instrument on target after the deposit").weak());
+    show_plan_grid(ui, "full_rebalance", &rebalance::full_rebalance(pie, *deposit));
+    ui.separator();
+
+    let fetched = pie.details_updated_at
+        .map(|timestamp| format!(" (weights from {})", format_timestamp(timestamp, "%Y-%m-%d %H:%M")))
+        .unwrap_or_default();
+    ui.label(egui::RichText::new(format!("For planning only: no orders are placed{}", fetched)).small().weak());
+}
+
+/// One row per instrument with its trade and the weight it ends up at, followed by the totals.
+fn show_plan_grid(ui: &mut egui::Ui, id: &str, plan: &Plan) {
+    egui::Grid::new(id).striped(true).num_columns(5).show(ui, |ui| {
+        ui.strong("Instrument");
+        ui.strong("Weight");
+        ui.strong("Target");
+        ui.strong("Trade");
+        ui.strong("Weight after");
+        ui.end_row();
+
+        for line in &plan.lines {
+            ui.label(&line.ticker);
+            ui.label(format!("{:.2}%", line.current_share * 100.0));
+            ui.label(format!("{:.2}%", line.target_share * 100.0));
+            // Under a cent isn't worth an order
+            if line.amount >= 0.005 {
+                ui.colored_label(egui::Color32::GREEN, format!("Buy ${:.2}", line.amount));
+            } else if line.amount <= -0.005 {
+                ui.colored_label(egui::Color32::RED, format!("Sell ${:.2}", -line.amount));
+            } else {
+                ui.label("-");
+            }
+            let drift = line.drift_after();
+            let drift_color = if drift.abs() >= 5.0 {
+                egui::Color32::YELLOW
+            } else {
+                egui::Color32::WHITE
+            };
+            ui.colored_label(drift_color, format!("{:.2}%", line.share_after * 100.0))
+                .on_hover_text(format!("Drift {:+.2}%", drift));
+            ui.end_row();
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.label(format!("Buy ${:.2}", plan.bought()));
+        if plan.sold() >= 0.005 {
+            ui.separator();
+            ui.label(format!("Sell ${:.2}", plan.sold()));
+        }
+        ui.separator();
+        ui.label(format!("Value after: ${:.2}", plan.value_after));
+        ui.separator();
+        ui.label(format!("Largest drift: {:.2} points", plan.max_drift_after()));
+    });
+}
+
+/// The portfolio's risk figures for the summary bar.
+fn show_risk_summary(ui: &mut egui::Ui, metrics: &RiskMetrics) {
+    match metrics.volatility {
+        Some(volatility) => ui.label(format!("Volatility: {:.2}%", volatility * 100.0)),
+        None => ui.label("Volatility: -"),
+    }
+    .on_hover_text("Annualised standard deviation of daily returns");
+    ui.separator();
+    ui.colored_label(drawdown_color(metrics.max_drawdown), format!("Max Drawdown: {:.2}%", metrics.max_drawdown * 100.0))
+        .on_hover_text(drawdown_dates(metrics));
+    ui.separator();
+    ui.colored_label(drawdown_color(metrics.current_drawdown), format!("Current Drawdown: {:.2}%", metrics.current_drawdown * 100.0));
+    for (name, ratio) in [("Sharpe", metrics.sharpe), ("Sortino", metrics.sortino)] {
+        ui.separator();
+        match ratio {
+            Some(ratio) => ui.colored_label(ratio_color(ratio), format!("{}: {:.2}", name, ratio)),
+            None => ui.label(format!("{}: -", name)),
+        };
+    }
+}
+
+fn drawdown_color(drawdown: f64) -> egui::Color32 {
+    if drawdown < 0.0 { egui::Color32::RED } else { egui::Color32::WHITE }
+}
+
+fn ratio_color(ratio: f64) -> egui::Color32 {
+    if ratio > 0.0 {
+        egui::Color32::GREEN
+    } else if ratio < 0.0 {
+        egui::Color32::RED
+    } else {
+        egui::Color32::WHITE
+    }
+}
+
+/// When the maximum drawdown started and bottomed out.
+fn drawdown_dates(metrics: &RiskMetrics) -> String {
+    if metrics.max_drawdown >= 0.0 {
+        return "No fall from a peak in this period".to_string();
+    }
+    format!(
+        "Peak {} → trough {}",
+        format_timestamp(metrics.max_drawdown_peak, "%Y-%m-%d %H:%M"),
+        format_timestamp(metrics.max_drawdown_trough, "%Y-%m-%d %H:%M"),
+    )
+}
+
+/// Donut chart of each pie's share of the total, plus uninvested cash. Clicking a pie's slice or
+/// legend entry charts that pie alone, clicking it again goes back to the total.
+fn show_allocation_view(
+    ui: &mut egui::Ui,
+    pies_data: &[Pie],
+    account: &Account,
+    basis: AllocationBasis,
+    chart_series: &mut Vec<Option<u64>>,
+) {
+    // (pie id, name, amount, color); the cash slice has no id
+    let mut slices: Vec<(Option<u64>, String, f64, egui::Color32)> = pies_data.iter()
+        .map(|pie| {
+            let amount = match basis {
+                AllocationBasis::Value => pie.result.price_avg_value,
+                AllocationBasis::Invested => pie.result.price_avg_invested_value,
+            };
+            let name = pie.name.clone().unwrap_or_else(|| format!("Pie {}", pie.id));
+            (Some(pie.id), name, amount, pie_color(pie.id))
+        })
+        .filter(|slice| slice.2 > 0.0)
+        .collect();
+    slices.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap_or(std::cmp::Ordering::Equal));
+    let cash = account.cash.as_ref()
+        .map(|cash| cash.free + cash.pie_cash)
+        .unwrap_or_else(|| pies_data.iter().map(|pie| pie.cash).sum());
+    if cash > 0.0 {
+        slices.push((None, "Cash".to_string(), cash, egui::Color32::GRAY));
+    }
+    let total: f64 = slices.iter().map(|slice| slice.2).sum();
+    if total <= 0.0 {
+        ui.label("📊 Nothing to allocate yet");
+        return;
+    }
+
+    let mut clicked = None;
+    ui.horizontal_top(|ui| {
+        let size = ui.available_height().min(ui.available_width() * 0.6).max(120.0);
+        let (response, painter) = ui.allocate_painter(egui::vec2(size, size), egui::Sense::click());
+        let center = response.rect.center();
+        let outer = size / 2.0 - 10.0;
+        let inner = outer * 0.55;
+
+        // Slice under the pointer: angles run clockwise from twelve o'clock
+        let turn = std::f32::consts::TAU;
+        let hovered = response.hover_pos().and_then(|pos| {
+            let offset = pos - center;
+            if offset.length() < inner || offset.length() > outer + 8.0 {
+                return None;
+            }
+            let angle = (offset.y.atan2(offset.x) + turn / 4.0).rem_euclid(turn);
+            let mut end = 0.0;
+            slices.iter().position(|slice| {
+                end += (slice.2 / total) as f32 * turn;
+                angle < end
+            })
+        });
+
+        let mut start = -turn / 4.0;
+        for (index, (id, _, amount, color)) in slices.iter().enumerate() {
+            let sweep = (amount / total) as f32 * turn;
+            let is_selected = id.is_some() && chart_series.contains(id);
+            let outer = if is_selected { outer + 8.0 } else { outer };
+            // Dim the other slices while one is hovered
+            let color = if hovered.is_some_and(|hovered| hovered != index) { color.gamma_multiply(0.6) } else { *color };
+            let steps = ((sweep / turn) * 128.0).ceil().max(2.0) as usize;
+            let mut mesh = egui::Mesh::default();
+            for step in 0..=steps {
+                let angle = start + sweep * step as f32 / steps as f32;
+                let direction = egui::vec2(angle.cos(), angle.sin());
+                mesh.colored_vertex(center + direction * inner, color);
+                mesh.colored_vertex(center + direction * outer, color);
+                if step > 0 {
+                    let i = (step * 2) as u32;
+                    mesh.add_triangle(i - 2, i - 1, i);
+                    mesh.add_triangle(i - 1, i, i + 1);
+                }
+            }
+            painter.add(egui::Shape::mesh(mesh));
+            // Thin gap between neighbouring slices
+            let direction = egui::vec2(start.cos(), start.sin());
+            painter.line_segment(
+                [center + direction * inner, center + direction * (outer + 8.0)],
+                egui::Stroke::new(1.5, ui.visuals().panel_fill),
+            );
+            start += sweep;
+        }
+
+        let basis_label = match basis {
+            AllocationBasis::Value => "Current value",
+            AllocationBasis::Invested => "Invested value",
+        };
+        painter.text(center - egui::vec2(0.0, 9.0), egui::Align2::CENTER_CENTER, format!("${:.2}", total),
+            egui::FontId::proportional(18.0), ui.visuals().strong_text_color());
+        painter.text(center + egui::vec2(0.0, 11.0), egui::Align2::CENTER_CENTER, basis_label,
+            egui::FontId::proportional(12.0), ui.visuals().weak_text_color());
+
+        if let Some(index) = hovered {
+            let (id, name, amount, _) = &slices[index];
+            if response.clicked() && let Some(id) = id {
+                clicked = Some(*id);
+            }
+            response.on_hover_ui_at_pointer(|ui| {
+                ui.strong(name);
+                ui.label(format!("${:.2} ({:.1}%)", amount, amount / total * 100.0));
+                if id.is_some() {
+                    ui.weak("Click to chart this pie");
+                }
+            });
+        }
+
+        // Legend, largest first
+        ui.vertical(|ui| {
+            egui::ScrollArea::vertical().id_source("allocation_legend_scroll").show(ui, |ui| {
+                for (id, name, amount, color) in &slices {
+                    ui.horizontal(|ui| {
+                        let (swatch, _) = ui.allocate_exact_size(egui::vec2(12.0, 12.0), egui::Sense::hover());
+                        ui.painter().rect_filled(swatch, 2.0, *color);
+                        let text = format!("{}  {:.1}%  ${:.2}", name, amount / total * 100.0, amount);
+                        match id {
+                            Some(id) => {
+                                if ui.selectable_label(chart_series.contains(&Some(*id)), text).clicked() {
+                                    clicked = Some(*id);
+                                }
+                            }
+                            None => {
+                                ui.label(text);
+                            }
+                        }
+                    });
+                }
+            });
+        });
+    });
+
+    if let Some(pie_id) = clicked {
+        *chart_series = if *chart_series == [Some(pie_id)] { vec![None] } else { vec![Some(pie_id)] };
+    }
+}
+
+/// Legend and dropdown label of a chart series.
+fn series_name(series: Option<u64>, pies_data: &[Pie]) -> String {
+    match series {
+        None => "Total Portfolio".to_string(),
+        Some(pie_id) => pies_data.iter()
+            .find(|pie| pie.id == pie_id)
+            .and_then(|pie| pie.name.clone())
+            .unwrap_or_else(|| format!("Pie {}", pie_id)),
+    }
+}
+
+fn series_color(series: Option<u64>) -> egui::Color32 {
+    match series {
+        None => egui::Color32::from_rgb(70, 180, 220), // Sky blue
+        Some(pie_id) => pie_color(pie_id),
+    }
+}
+
+/// A colour per benchmark that stays the same across restarts.
+fn benchmark_color(name: &str) -> egui::Color32 {
+    // FNV-1a, so the colour doesn't depend on the standard library's hasher
+    let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3));
+    let hue = (hash % 360) as f32 / 360.0;
+    egui::ecolor::Hsva::new(hue, 0.35, 0.9, 1.0).into()
+}
+
+/// A colour per pie that stays the same across refreshes and orderings.
+fn pie_color(pie_id: u64) -> egui::Color32 {
+    // Golden-ratio steps spread consecutive ids around the hue circle
+    let hue = (pie_id as f64 * 0.618_033_988_749_895).fract() as f32;
+    egui::ecolor::Hsva::new(hue, 0.6, 0.85, 1.0).into()
+}
+
+/// The alerts fired since start-up, newest first.
+fn show_alerts_view(ui: &mut egui::Ui, alert_log: &[Alert], rule_count: usize) {
+    if rule_count == 0 {
+        ui.label(format!("No alert rules configured. Add them to {} to be notified of changes.", alerts::DEFAULT_PATH));
+        return;
+    }
+    if alert_log.is_empty() {
+        ui.label(format!("No alerts yet ({} rules active).", rule_count));
+        return;
+    }
+    egui::ScrollArea::vertical().id_source("alerts_scroll").show(ui, |ui| {
+        for alert in alert_log.iter().rev() {
+            ui.horizontal(|ui| {
+                ui.weak(format_timestamp(alert.timestamp, "%Y-%m-%d %H:%M:%S"));
+                ui.strong(&alert.title);
+                ui.label(&alert.message);
+            });
+        }
+    });
+}
+
+/// Dividend totals per pie, instrument and month, with the trailing-12-month yield.
+fn show_dividends_view(ui: &mut egui::Ui, account: &Account, pies_data: &[Pie]) {
+    let now = Utc::now().timestamp() as f64;
+    let items = &account.dividends;
+    let trailing = dividends::trailing_12_months(items, now);
+    let trailing_total: f64 = trailing.iter().map(|item| item.amount).sum();
+    let invested = account.cash.as_ref()
+        .map(|cash| cash.invested)
+        .unwrap_or_else(|| pies_data.iter().map(|p| p.result.price_avg_invested_value).sum());
+    
+    egui::ScrollArea::vertical().id_source("dividends_scroll").show(ui, |ui| {
+        ui.horizontal(|ui| {
+            let gained: f64 = pies_data.iter().map(|p| p.dividend_details.gained).sum();
+            let reinvested: f64 = pies_data.iter().map(|p| p.dividend_details.reinvested).sum();
+            let in_cash: f64 = pies_data.iter().map(|p| p.dividend_details.in_cash).sum();
+            ui.label(format!("Pies gained: ${:.2}", gained));
+            ui.separator();
+            ui.label(format!("Reinvested: ${:.2}", reinvested));
+            ui.separator();
+            ui.label(format!("In cash: ${:.2}", in_cash));
+            ui.separator();
+            ui.label(format!("Last 12 months: ${:.2}", trailing_total));
+            if let Some(dividend_yield) = dividends::yield_on_invested(trailing_total, invested) {
+                ui.separator();
+                ui.label(format!("Yield on invested: {:.2}%", dividend_yield * 100.0))
+                    .on_hover_text("Dividends of the last 12 months over the invested amount");
+            }
+        });
+        if account.dividends_updated_at.is_none() || account.dividends_resume.is_some() {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label(format!("Loading dividend history ({} so far)...", account.dividends.len()));
+            });
+        }
+        ui.separator();
+        
+        // Per pie: totals from the pie itself, last 12 months attributed from the history by ticker
+        let trailing_per_pie = dividends::attribute_to_pies(&trailing, pies_data, &account.positions);
+        ui.strong("Per pie");
+        egui::Grid::new("dividends_per_pie").striped(true).num_columns(6).show(ui, |ui| {
+            for title in ["Pie", "Gained", "Reinvested", "In Cash", "Last 12 Months", "Yield"] {
+                ui.strong(title);
+            }
+            ui.end_row();
+            for pie in pies_data {
+                let trailing_pie = trailing_per_pie.get(&pie.id).copied().unwrap_or_default();
+                ui.label(pie.name.clone().unwrap_or_else(|| format!("Pie {}", pie.id)));
+                ui.label(format!("${:.2}", pie.dividend_details.gained));
+                ui.label(format!("${:.2}", pie.dividend_details.reinvested));
+                ui.label(format!("${:.2}", pie.dividend_details.in_cash));
+                ui.label(format!("${:.2}", trailing_pie));
+                match dividends::yield_on_invested(trailing_pie, pie.result.price_avg_invested_value) {
+                    Some(dividend_yield) => ui.label(format!("{:.2}%", dividend_yield * 100.0)),
+                    None => ui.label("-"),
+                };
+                ui.end_row();
+            }
+        });
+        ui.separator();
+        
+        ui.columns(2, |columns| {
+            let ui = &mut columns[0];
+            ui.strong("Per instrument");
+            let trailing_by_ticker: HashMap<String, f64> = dividends::totals_by_ticker(&trailing).into_iter().collect();
+            egui::Grid::new("dividends_per_instrument").striped(true).num_columns(3).show(ui, |ui| {
+                for title in ["Ticker", "All Time", "Last 12 Months"] {
+                    ui.strong(title);
+                }
+                ui.end_row();
+                for (ticker, total) in dividends::totals_by_ticker(items) {
+                    let trailing_ticker = trailing_by_ticker.get(&ticker).copied().unwrap_or_default();
+                    ui.label(ticker);
+                    ui.label(format!("${:.2}", total));
+                    ui.label(format!("${:.2}", trailing_ticker));
+                    ui.end_row();
+                }
+            });
+            
+            let ui = &mut columns[1];
+            ui.strong("Per month");
+            let bars = month_bars(dividends::monthly_totals(items));
+            Plot::new("dividends_per_month_plot")
+                .height(180.0)
+                .x_grid_spacer(time_grid_spacer)
+                .x_axis_formatter(|mark, range| format_time_axis(mark.value, range.end() - range.start()))
+                .label_formatter(|_name, value| format!("{}\n${:.2}", format_timestamp(value.x, "%b %Y"), value.y))
+                .show_grid(false)
+                .allow_scroll(false)
+                .show(ui, |plot_ui| {
+                    plot_ui.bar_chart(egui_plot::BarChart::new(bars).color(egui::Color32::from_rgb(120, 200, 120)).name("Dividends"));
+                });
+            
+            ui.strong("Cumulative");
+            let cumulative = dividends::cumulative(items);
+            Plot::new("dividends_cumulative_plot")
+                .height(180.0)
+                .x_grid_spacer(time_grid_spacer)
+                .x_axis_formatter(|mark, range| format_time_axis(mark.value, range.end() - range.start()))
+                .label_formatter(|_name, value| format!("{}\n${:.2}", format_timestamp(value.x, "%Y-%m-%d"), value.y))
+                .show_grid(false)
+                .allow_scroll(false)
+                .show(ui, |plot_ui| {
+                    plot_ui.line(
+                        Line::new(PlotPoints::from(cumulative))
+                            .color(egui::Color32::from_rgb(120, 200, 120))
+                            .width(2.0)
+                            .name("Cumulative Dividends"),
+                    );
+                });
+        });
+        ui.separator();
+        
+        // Forecast: last year's payments repeated on today's holdings
+        let forecast = dividends::forecast(items, pies_data, &account.positions, now);
+        let forecast_total: f64 = forecast.iter().map(|payment| payment.amount).sum();
+        ui.horizontal(|ui| {
+            ui.strong("📅 Forecast, next 12 months:");
+            ui.label(format!("${:.2}", forecast_total))
+                .on_hover_text("Each dividend of the last 12 months repeated a year later, on the quantity held today");
+        });
+        ui.columns(2, |columns| {
+            let ui = &mut columns[0];
+            let bars = month_bars(dividends::forecast_monthly_totals(&forecast));
+            Plot::new("dividends_forecast_plot")
+                .height(180.0)
+                .x_grid_spacer(time_grid_spacer)
+                .x_axis_formatter(|mark, range| format_time_axis(mark.value, range.end() - range.start()))
+                .label_formatter(|_name, value| format!("{}\n${:.2}", format_timestamp(value.x, "%b %Y"), value.y))
+                .show_grid(false)
+                .allow_scroll(false)
+                .show(ui, |plot_ui| {
+                    plot_ui.bar_chart(egui_plot::BarChart::new(bars).color(egui::Color32::from_rgb(200, 180, 90)).name("Expected Dividends"));
+                });
+            
+            let ui = &mut columns[1];
+            egui::Grid::new("dividends_forecast_calendar").striped(true).num_columns(3).show(ui, |ui| {
+                for title in ["Expected On", "Ticker", "Amount"] {
+                    ui.strong(title);
+                }
+                ui.end_row();
+                for payment in &forecast {
+                    ui.label(format_timestamp(payment.expected_on, "%Y-%m-%d"));
+                    ui.label(&payment.ticker);
+                    ui.label(format!("${:.2}", payment.amount));
+                    ui.end_row();
+                }
+            });
+        });
+    });
+}
+
+/// One bar per `(year, month)` total, centred on the middle of the month on a Unix-seconds axis.
+fn month_bars(totals: std::collections::BTreeMap<(i32, u32), f64>) -> Vec<egui_plot::Bar> {
+    totals
+        .into_iter()
+        .filter_map(|((year, month), amount)| {
+            let start = chrono::NaiveDate::from_ymd_opt(year, month, 1)?
+                .and_hms_opt(0, 0, 0)?
+                .and_utc()
+                .timestamp() as f64;
+            Some(egui_plot::Bar::new(start + 15.0 * 86400.0, amount).width(25.0 * 86400.0))
+        })
+        .collect()
+}
+
+/// Values rebased so the first is 100; empty when the series starts at zero.
+fn indexed_series(points: impl Iterator<Item = [f64; 2]>) -> Vec<[f64; 2]> {
+    let mut points = points.peekable();
+    let Some(start) = points.peek().map(|point| point[1]).filter(|start| *start > 0.0) else {
+        return Vec::new();
+    };
+    points.map(|[timestamp, value]| [timestamp, value / start * 100.0]).collect()
 }
 
-fn load_map(path: &str) -> std::io::Result<HashMap<usize, Pie>> {
-    let mut file = File::open(path)?;
-    let mut data = String::new();
-    file.read_to_string(&mut data)?;
-    let map: HashMap<usize, Pie> = serde_json::from_str(&data).unwrap();
-    Ok(map)
+fn percent_series(fractions: Vec<[f64; 2]>) -> Vec<[f64; 2]> {
+    fractions.into_iter().map(|[timestamp, fraction]| [timestamp, fraction * 100.0]).collect()
+}
+
+/// Cumulative TWR of `samples` in percent, for plotting.
+fn twr_percent_series(samples: &[returns::FlowSample]) -> Vec<[f64; 2]> {
+    percent_series(returns::cumulative_twr(samples))
+}
+
+/// Grid marks at round time steps (minutes, hours, days, ...) for an x axis in Unix seconds.
+fn time_grid_spacer(input: GridInput) -> Vec<GridMark> {
+    const STEPS: [f64; 13] = [
+        60.0, 300.0, 900.0, 1800.0, 3600.0, 3.0 * 3600.0, 6.0 * 3600.0, 12.0 * 3600.0,
+        86400.0, 7.0 * 86400.0, 30.0 * 86400.0, 91.0 * 86400.0, 365.0 * 86400.0,
+    ];
+    let (min, max) = input.bounds;
+    let span = max - min;
+    if !span.is_finite() || span <= 0.0 {
+        return Vec::new();
+    }
+    let step = STEPS.iter().copied()
+        .find(|step| span / step <= 8.0)
+        .unwrap_or_else(|| (span / 8.0 / STEPS[12]).ceil() * STEPS[12]);
+    
+    let mut marks = Vec::new();
+    let mut value = (min / step).ceil() * step;
+    while value <= max {
+        marks.push(GridMark { value, step_size: step });
+        value += step;
+    }
+    marks
+}
+
+/// Axis label for a Unix timestamp, with precision depending on the visible span.
+fn format_time_axis(timestamp: f64, span: f64) -> String {
+    let format = if span <= 2.0 * 86400.0 {
+        "%H:%M"
+    } else if span <= 400.0 * 86400.0 {
+        "%b %d"
+    } else {
+        "%Y-%m"
+    };
+    format_timestamp(timestamp, format)
+}
+
+fn format_timestamp(timestamp: f64, format: &str) -> String {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0)
+        .map(|time| time.with_timezone(&chrono::Local).format(format).to_string())
+        .unwrap_or_default()
+}
+
+/// Stops with the same message and exit code as the subcommands give for a configuration mistake.
+fn exit_on_config_error<T>(result: Result<T, ConfigError>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(cli::EXIT_CONFIG);
+    })
 }
 
 #[tokio::main]
 async fn main() -> Result<(), eframe::Error> {
     dotenv().ok();
-    let token = env::var("TRADE212_API_TOKEN").expect("TRADE212_API_TOKEN must be set");
-    
+    let attach = match Cli::parse().command {
+        None => false,
+        Some(Command::Gui { attach }) => attach,
+        Some(command) => std::process::exit(cli::run(command).await),
+    };
+
     // Load existing pies data
     let pies: Arc<Mutex<HashMap<usize, Pie>>> = Arc::new(Mutex::new(HashMap::new()));
-    let pies_path = "pies.json";
+    let pies_path = snapshot::DEFAULT_PATH;
     if let Ok(loaded_pies) = load_map(pies_path) {
         let mut pies_guard = pies.lock().await;
         *pies_guard = loaded_pies;
     }
 
+    // Load the persisted value history
+    let history_path = history::DEFAULT_DIR;
+    let history = if attach {
+        History::attach(HistoryStore::open_read_only(history_path))
+    } else {
+        HistoryStore::open(history_path).and_then(History::load)
+    };
+    // Carrying on without it would record a new history over the top of the old one
+    let history = history.unwrap_or_else(|e| {
+        eprintln!("Failed to load history from {}: {}", history_path, e);
+        std::process::exit(cli::EXIT_IO);
+    });
+    let history = Arc::new(Mutex::new(history));
+
+    // Manually recorded deposits for pies funded before history was collected
+    let ledger_path = ledger::DEFAULT_PATH;
+    let ledger = Ledger::load(ledger_path).unwrap_or_else(|e| {
+        eprintln!("Failed to load ledger from {}: {}", ledger_path, e);
+        Ledger::default()
+    });
+
+    // Alert rules, checked after every refresh
+    let mut alert_config = AlertConfig::load(alerts::DEFAULT_PATH).unwrap_or_else(|e| {
+        eprintln!("Failed to load alert rules from {}: {}", alerts::DEFAULT_PATH, e);
+        AlertConfig::default()
+    });
+    if attach {
+        // The collector already delivers them to webhooks and email
+        alert_config.delivery = DeliveryConfig::default();
+    }
+    // What already fired, so restarting doesn't repeat it; an attached window reads the collector's
+    let alerts = Alerts::new(alert_config.clone())
+        .with_state(alerts::DEFAULT_STATE_PATH, !attach)
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to load alert state from {}: {}", alerts::DEFAULT_STATE_PATH, e);
+            Alerts::new(alert_config)
+        });
+    let alerts = Arc::new(Mutex::new(alerts));
+
+    // Either poll the API in the background or follow what a collector writes
+    let account: Arc<Mutex<Account>> = Arc::new(Mutex::new(Account::default()));
+    let (environment, poller_status) = if attach {
+        (None, spawn_follower(pies.clone(), account.clone(), history.clone(), alerts.clone()))
+    } else {
+        // The last account snapshot keeps the dividend fetch incremental across restarts
+        if let Ok(loaded_account) = load_account(snapshot::DEFAULT_ACCOUNT_PATH) {
+            *account.lock().await = loaded_account;
+        }
+        let config = exit_on_config_error(Config::from_env());
+        let client = Trading212Client::with_base_url(config.token, config.environment.base_url());
+        let poller_status = spawn_poller(client, pies.clone(), account.clone(), history.clone(), alerts.clone());
+        // Save the snapshots while the app runs; an attached window leaves that to the collector
+        spawn_saver(pies.clone(), account.clone());
+        (Some(config.environment), poller_status)
+    };
+
+    // Optional local HTTP server for metrics and the JSON API
+    let listen_address = exit_on_config_error(config::listen_address_from_env());
+    if let Some(address) = listen_address {
+        let state = ServerState {
+            pies: pies.clone(),
+            account: account.clone(),
+            history: history.clone(),
+            ledger: Arc::new(ledger.clone()),
+            poller_status: poller_status.clone(),
+        };
+        if let Err(e) = server::spawn(address, state).await {
+            eprintln!("Failed to listen on {}: {}", address, e);
+        }
+    }
+
+    // Benchmark series to compare against
+    let benchmarks = benchmark::load_all(benchmark::DEFAULT_DIR).unwrap_or_else(|e| {
+        eprintln!("Failed to load benchmarks from {}: {}", benchmark::DEFAULT_DIR, e);
+        Vec::new()
+    });
+
+    let risk_free_rate = exit_on_config_error(config::risk_free_rate_from_env());
+
+    // IRR, TWR and risk figures, recomputed off the UI thread as samples come in
+    let settings = RiskSettings { period: TwrPeriod::SinceInception, risk_free_rate };
+    let analytics = analytics::spawn(pies.clone(), history.clone(), ledger, settings);
+
     // Create the app
-    let app = PieTopApp::new(token, pies.clone());
+    let app = PieTopApp {
+        benchmarks,
+        risk_free_rate,
+        ..PieTopApp::new(environment, pies.clone(), account, history, analytics, poller_status, alerts)
+    };
     
     // Set up native options for the window
     let native_options = eframe::NativeOptions {
@@ -807,20 +2023,6 @@ async fn main() -> Result<(), eframe::Error> {
         ..Default::default()
     };
 
-    // Save pies data when app closes
-    let pies_for_save = pies.clone();
-    std::thread::spawn(move || {
-        std::thread::sleep(Duration::from_secs(1)); // Give some time for the app to start
-        loop {
-            std::thread::sleep(Duration::from_secs(5)); // Save every 5 seconds
-            if let Ok(pies_map) = pies_for_save.try_lock() {
-                if let Err(e) = save_map(&*pies_map, pies_path) {
-                    eprintln!("Failed to save pies: {}", e);
-                }
-            }
-        }
-    });
-
     // Run the egui app
     eframe::run_native(
         "Pie Portfolio Dashboard",
@@ -828,121 +2030,3 @@ async fn main() -> Result<(), eframe::Error> {
         Box::new(|_cc| Ok(Box::new(app))),
     )
 }
-
-async fn fetch_pies(token: &str, pies: Arc<Mutex<HashMap<usize, Pie>>>) -> Result<(), Box<dyn Error>> {
-    let url = "https://live.trading212.com/api/v0/equity/pies"; // Replace with real Trade212 API endpoint
-    let client = reqwest::Client::new();
-    let response = client
-        .get(url)
-        .header("Authorization", format!("{}", token))
-        .send()
-        .await?;
-    
-    // Check the response status first
-    let status = response.status();
-    if !status.is_success() {
-        if status == 429 {
-            // Rate limited - just return without error to avoid spam
-            return Ok(());
-        }
-        eprintln!("API Error: HTTP Status {}", status);
-        let error_text = response.text().await?;
-        eprintln!("API Error Response: {}", error_text);
-        return Err(format!("HTTP Error: {} - {}", status, error_text).into());
-    }
-    
-    // Get the response as text to see the actual format
-    let response_text = response.text().await?;
-    
-    // Check if it's an error response first
-    if response_text.contains("BusinessException") || response_text.contains("error") {
-        return Err(format!("API Business Error: {}", response_text).into());
-    }
-    
-    // Try to parse as Vec<Pie> first (array format)
-    let pies_v = if let Ok(pies_array) = serde_json::from_str::<Vec<Pie>>(&response_text) {
-        pies_array
-    } else {
-        // If that fails, try to parse as an object with pies
-        #[derive(Deserialize)]
-        struct PiesResponse {
-            #[serde(flatten)]
-            pies: HashMap<String, Pie>,
-        }
-        
-        if let Ok(pies_obj) = serde_json::from_str::<PiesResponse>(&response_text) {
-            pies_obj.pies.into_values().collect()
-        } else {
-            // If both fail, try direct object parsing
-            match serde_json::from_str::<HashMap<String, Pie>>(&response_text) {
-                Ok(pies_map) => pies_map.into_values().collect(),
-                Err(e) => {
-                    eprintln!("Failed to parse JSON as any expected format: {}", e);
-                    eprintln!("Raw response: {}", response_text);
-                    return Err(e.into());
-                }
-            }
-        }
-    };
-    
-    // Collect all pie IDs from the API response
-    let current_pie_ids: HashSet<usize> = pies_v.iter()
-        .map(|pie| pie.id as usize)
-        .collect();
-    
-    // Lock the pies HashMap once for all operations
-    let mut pies_map = pies.lock().await;
-    
-    // Remove pies that are no longer in the API response (deleted remotely)
-    let existing_pie_ids: Vec<usize> = pies_map.keys().cloned().collect();
-    for existing_id in existing_pie_ids {
-        if !current_pie_ids.contains(&existing_id) {
-            pies_map.remove(&existing_id);
-            println!("Removed deleted pie with ID: {}", existing_id);
-        }
-    }
-    
-    // Update or insert pies from the API response
-    for pie in pies_v {
-        let p = pies_map.entry(pie.id as usize).or_insert(pie.clone());
-        if p.created_at.is_none() || p.name.is_none() {
-            // If created_at or name is None, fetch the creation date and name
-            if let Ok((create_date, name)) = get_pie_details(&pie, &client, token).await {
-                if p.created_at.is_none() {
-                    p.created_at = Some(create_date);
-                }
-                if p.name.is_none() {
-                    p.name = Some(name);
-                }
-            }
-        }
-        p.result = pie.result.clone();
-    }
-    Ok(())
-}
-
-async fn get_pie_details(pie: &Pie, client: &reqwest::Client, token: &str) -> Result<(f64, String), Box<dyn Error>> {
-    let url = "https://live.trading212.com/api/v0/equity/pies/".to_owned() + &pie.id.to_string(); // Replace with real Trade212 API endpoint
-    let response = client
-        .get(url)
-        .header("Authorization", format!("{}", token))
-        .send()
-        .await?;
-    let pie_detail = response.json::<PieDetail>().await?;
-    Ok((pie_detail.settings.creation_date, pie_detail.settings.name))
-}
-
-fn calculate_annual_rate(
-    initial_value: f64,
-    final_value: f64,
-    create_date: f64,
-) -> f64 {
-    if initial_value <= 0.0 || create_date <= 0.0 {
-        return 0.0;
-    }
-    let now = chrono::Utc::now().timestamp() as f64; // Convert to years
-    if now <= create_date {
-        return 0.0;
-    }
-    ((final_value / initial_value).powf(365.0 * 86400.0 / (now - create_date)) - 1.0) * 100.0
-}
\ No newline at end of file
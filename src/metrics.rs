@@ -0,0 +1,135 @@
+//! Prometheus text exposition of the pie, account and poller state.
+
+use std::fmt::Write;
+
+use crate::models::{Account, Pie};
+use crate::poller::PollerStatus;
+
+/// Metric name, help text and how to read the value from a pie.
+type PieGauge = (&'static str, &'static str, fn(&Pie) -> f64);
+
+/// Renders everything the dashboard shows as Prometheus metrics (text format 0.0.4).
+pub fn render(pies: &[Pie], account: &Account, status: &PollerStatus) -> String {
+    let mut out = String::new();
+
+    let pie_gauges: [PieGauge; 6] = [
+        ("piers_pie_value", "Current value of the pie.", |p| p.result.price_avg_value),
+        ("piers_pie_invested_value", "Value invested in the pie.", |p| p.result.price_avg_invested_value),
+        ("piers_pie_result", "Result (current minus invested value) of the pie.", |p| p.result.price_avg_result),
+        ("piers_pie_result_coefficient", "Result of the pie as a fraction of the invested value.", |p| {
+            p.result.price_avg_result_coef
+        }),
+        ("piers_pie_cash", "Uninvested cash held in the pie.", |p| p.cash),
+        ("piers_pie_dividends_gained", "Dividends gained by the pie.", |p| p.dividend_details.gained),
+    ];
+    let mut pies: Vec<&Pie> = pies.iter().collect();
+    pies.sort_by_key(|pie| pie.id);
+    for (name, help, value) in pie_gauges {
+        header(&mut out, name, help, "gauge");
+        for pie in &pies {
+            let pie_name = pie.name.clone().unwrap_or_else(|| format!("Pie {}", pie.id));
+            let _ = writeln!(
+                out,
+                "{}{{pie_id=\"{}\",pie_name=\"{}\"}} {}",
+                name,
+                pie.id,
+                escape_label(&pie_name),
+                value(pie)
+            );
+        }
+    }
+
+    if let Some(info) = &account.info {
+        header(&mut out, "piers_account_info", "Account id and currency; always 1.", "gauge");
+        let _ = writeln!(
+            out,
+            "piers_account_info{{account_id=\"{}\",currency=\"{}\"}} 1",
+            info.id,
+            escape_label(&info.currency_code)
+        );
+    }
+    if let Some(cash) = &account.cash {
+        let account_gauges = [
+            ("piers_account_total", "Total account value.", cash.total),
+            ("piers_account_free_cash", "Cash available to invest.", cash.free),
+            ("piers_account_blocked_cash", "Cash blocked by pending orders.", cash.blocked.unwrap_or(0.0)),
+            ("piers_account_invested", "Value invested across the account.", cash.invested),
+            ("piers_account_pie_cash", "Uninvested cash held in pies.", cash.pie_cash),
+            ("piers_account_unrealised_result", "Unrealised profit/loss of open positions.", cash.ppl),
+            ("piers_account_realised_result", "Realised result.", cash.result),
+        ];
+        for (name, help, value) in account_gauges {
+            header(&mut out, name, help, "gauge");
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+    }
+
+    header(&mut out, "piers_poller_up", "1 if the last poll succeeded.", "gauge");
+    let up = status.last_success.is_some() && status.last_error.is_none();
+    let _ = writeln!(out, "piers_poller_up {}", u8::from(up));
+    if let Some(last_success) = status.last_success {
+        header(
+            &mut out,
+            "piers_poller_last_success_timestamp_seconds",
+            "Unix time of the last successful poll.",
+            "gauge",
+        );
+        let _ = writeln!(out, "piers_poller_last_success_timestamp_seconds {}", last_success.timestamp());
+    }
+    header(&mut out, "piers_poller_backoff_level", "Consecutive retryable poll failures.", "gauge");
+    let _ = writeln!(out, "piers_poller_backoff_level {}", status.backoff_level);
+    header(&mut out, "piers_poller_errors_total", "Failed polls.", "counter");
+    let _ = writeln!(out, "piers_poller_errors_total {}", status.error_count);
+    header(&mut out, "piers_poller_rate_limited_total", "Polls rejected with HTTP 429.", "counter");
+    let _ = writeln!(out, "piers_poller_rate_limited_total {}", status.rate_limited_count);
+
+    out
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pie(id: u64, name: Option<&str>, value: f64) -> Pie {
+        serde_json::from_value(serde_json::json!({
+            "id": id, "cash": 1.5, "dividendDetails": {"gained": 2.0, "reinvested": 0.0, "inCash": 0.0},
+            "result": {"priceAvgInvestedValue": 100.0, "priceAvgValue": value, "priceAvgResult": value - 100.0, "priceAvgResultCoef": value / 100.0 - 1.0},
+            "progress": null, "status": null, "created_at": null, "name": name
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn renders_one_sample_per_pie_with_escaped_names() {
+        let pies = [pie(2, Some("Say \"hi\" \\ bye\nnow"), 120.0), pie(1, None, 90.0)];
+        let status = PollerStatus { error_count: 3, ..PollerStatus::default() };
+        let out = render(&pies, &Account::default(), &status);
+
+        assert!(out.contains("# HELP piers_pie_value Current value of the pie.\n# TYPE piers_pie_value gauge\n"));
+        assert!(out.contains("# TYPE piers_poller_errors_total counter\npiers_poller_errors_total 3\n"));
+        let values: Vec<&str> = out.lines().filter(|line| line.starts_with("piers_pie_value{")).collect();
+        assert_eq!(
+            values,
+            [
+                r#"piers_pie_value{pie_id="1",pie_name="Pie 1"} 90"#,
+                r#"piers_pie_value{pie_id="2",pie_name="Say \"hi\" \\ bye\nnow"} 120"#,
+            ]
+        );
+        // Every sample follows its HELP and TYPE lines; no account figures without an account
+        for name in ["piers_pie_cash", "piers_pie_dividends_gained", "piers_poller_up"] {
+            assert_eq!(out.matches(&format!("# HELP {} ", name)).count(), 1);
+            assert_eq!(out.matches(&format!("# TYPE {} ", name)).count(), 1);
+        }
+        assert!(!out.contains("piers_account_"));
+        assert!(out.contains("piers_poller_up 0\n"));
+    }
+}
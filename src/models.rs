@@ -0,0 +1,219 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Pie {
+    pub id: u64,
+    pub cash: f64,
+    #[serde(rename = "dividendDetails")]
+    pub dividend_details: DividendDetails,
+    pub result: ResultDetails,
+    pub progress: Option<f64>,
+    pub status: Option<String>,
+    pub created_at: Option<f64>,
+    pub name: Option<String>,
+    /// Holdings from the pie detail endpoint; empty until the first detail fetch.
+    #[serde(default)]
+    pub instruments: Vec<Instrument>,
+    /// Unix timestamp (seconds) of the last detail fetch.
+    #[serde(default)]
+    pub details_updated_at: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DividendDetails {
+    pub gained: f64,
+    pub reinvested: f64,
+    #[serde(rename = "inCash")]
+    pub in_cash: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ResultDetails {
+    #[serde(rename = "priceAvgInvestedValue")]
+    pub price_avg_invested_value: f64,
+    #[serde(rename = "priceAvgValue")]
+    pub price_avg_value: f64,
+    #[serde(rename = "priceAvgResult")]
+    pub price_avg_result: f64,
+    #[serde(rename = "priceAvgResultCoef")]
+    pub price_avg_result_coef: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PieDetail {
+    #[serde(default)]
+    pub instruments: Vec<Instrument>,
+    pub settings: Setting,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Instrument {
+    pub ticker: String,
+    #[serde(rename = "ownedQuantity")]
+    pub owned_quantity: f64,
+    /// Target weight in the pie, as a fraction of 1.
+    #[serde(rename = "expectedShare")]
+    pub expected_share: f64,
+    /// Actual weight in the pie, as a fraction of 1.
+    #[serde(rename = "currentShare")]
+    pub current_share: f64,
+    pub result: ResultDetails,
+    #[serde(default)]
+    pub issues: Vec<InstrumentIssue>,
+}
+
+impl Instrument {
+    /// Current minus target weight, in percentage points.
+    pub fn drift(&self) -> f64 {
+        (self.current_share - self.expected_share) * 100.0
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct InstrumentIssue {
+    pub name: String,
+    pub severity: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Setting {
+    #[serde(rename = "creationDate")]
+    pub creation_date: f64,
+    pub name: String,
+}
+
+/// `GET /api/v0/equity/account/cash`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AccountCash {
+    pub free: f64,
+    pub blocked: Option<f64>,
+    pub invested: f64,
+    #[serde(rename = "pieCash")]
+    pub pie_cash: f64,
+    /// Unrealised profit/loss of open positions.
+    pub ppl: f64,
+    /// Realised result.
+    pub result: f64,
+    pub total: f64,
+}
+
+/// `GET /api/v0/equity/account/info`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AccountInfo {
+    pub id: u64,
+    #[serde(rename = "currencyCode")]
+    pub currency_code: String,
+}
+
+/// An open position from `GET /api/v0/equity/portfolio`.
+///
+/// Prices are in the instrument's currency; `ppl` and `fx_ppl` are in the account currency.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Position {
+    pub ticker: String,
+    pub quantity: f64,
+    #[serde(rename = "averagePrice")]
+    pub average_price: f64,
+    #[serde(rename = "currentPrice")]
+    pub current_price: f64,
+    pub ppl: f64,
+    #[serde(rename = "fxPpl")]
+    pub fx_ppl: Option<f64>,
+    /// Part of `quantity` held inside pies.
+    #[serde(rename = "pieQuantity", default)]
+    pub pie_quantity: f64,
+}
+
+impl Position {
+    /// Quantity held directly, outside of any pie.
+    pub fn outside_pies_quantity(&self) -> f64 {
+        (self.quantity - self.pie_quantity).max(0.0)
+    }
+
+    /// Fraction of the position held outside pies, used to apportion `ppl`/`fx_ppl`.
+    pub fn outside_pies_fraction(&self) -> f64 {
+        if self.quantity > 0.0 {
+            self.outside_pies_quantity() / self.quantity
+        } else {
+            0.0
+        }
+    }
+}
+
+/// A paid dividend from `GET /api/v0/history/dividends`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DividendItem {
+    pub ticker: String,
+    /// Net amount in the account currency.
+    pub amount: f64,
+    #[serde(rename = "grossAmountPerShare", default)]
+    pub gross_amount_per_share: f64,
+    #[serde(default)]
+    pub quantity: f64,
+    /// RFC 3339 date-time.
+    #[serde(rename = "paidOn")]
+    pub paid_on: String,
+    pub reference: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+}
+
+impl DividendItem {
+    /// `paid_on` as a Unix timestamp in seconds, `None` if it doesn't parse.
+    pub fn paid_on_timestamp(&self) -> Option<f64> {
+        chrono::DateTime::parse_from_rfc3339(&self.paid_on)
+            .ok()
+            .map(|time| time.timestamp() as f64)
+    }
+}
+
+/// One page of dividend history; `next_page_path` is relative to the API host.
+#[derive(Debug, Deserialize, Clone)]
+pub struct DividendsPage {
+    pub items: Vec<DividendItem>,
+    #[serde(rename = "nextPagePath")]
+    pub next_page_path: Option<String>,
+}
+
+/// Account-wide data, as opposed to the per-pie data in [`Pie`].
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct Account {
+    pub cash: Option<AccountCash>,
+    pub info: Option<AccountInfo>,
+    pub positions: Vec<Position>,
+    /// Paid dividends, newest first.
+    pub dividends: Vec<DividendItem>,
+    /// Unix timestamp (seconds) of the last dividend history fetch.
+    pub dividends_updated_at: Option<f64>,
+    /// Set while a dividend history fetch is part way through.
+    #[serde(default)]
+    pub dividends_resume: Option<DividendsResume>,
+}
+
+/// Where an interrupted dividend history fetch carries on.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct DividendsResume {
+    pub next_page_path: String,
+    /// How many of the newest `dividends` the fetch has added so far; the next page goes after them.
+    pub fetched: usize,
+}
+
+impl Account {
+    /// Positions with a part held outside pies, restricted to that part.
+    pub fn positions_outside_pies(&self) -> Vec<Position> {
+        self.positions
+            .iter()
+            .filter(|p| p.outside_pies_quantity() > 1e-9)
+            .map(|p| {
+                let fraction = p.outside_pies_fraction();
+                Position {
+                    quantity: p.outside_pies_quantity(),
+                    ppl: p.ppl * fraction,
+                    fx_ppl: p.fx_ppl.map(|fx| fx * fraction),
+                    pie_quantity: 0.0,
+                    ..p.clone()
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,105 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use crate::api::{ApiError, Trading212Client};
+use crate::diagnostics;
+
+/// Upper bound for the exponential backoff after repeated failures.
+const MAX_BACKOFF: Duration = Duration::from_secs(15 * 60);
+
+/// What the background poller is doing, for display in the status bar.
+#[derive(Debug, Clone, Default)]
+pub struct PollerStatus {
+    pub polling: bool,
+    pub next_poll: Option<DateTime<Utc>>,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    /// Number of consecutive retryable failures; 0 when healthy.
+    pub backoff_level: u32,
+    pub error_count: u64,
+    pub rate_limited_count: u64,
+}
+
+/// Runs one poll at a time, spaced by `interval`, the client's rate limits and a backoff on
+/// 429/5xx/transport errors.
+pub struct Poller {
+    client: Trading212Client,
+    interval: Duration,
+    status: Arc<Mutex<PollerStatus>>,
+}
+
+impl Poller {
+    pub fn new(client: Trading212Client, interval: Duration) -> Self {
+        Self {
+            client,
+            interval,
+            status: Arc::new(Mutex::new(PollerStatus::default())),
+        }
+    }
+
+    pub fn status(&self) -> Arc<Mutex<PollerStatus>> {
+        self.status.clone()
+    }
+
+    /// Spawns the polling loop; `poll` is awaited to completion before the next one is scheduled.
+    pub fn spawn<F, Fut>(self, mut poll: F) -> JoinHandle<()>
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), ApiError>> + Send,
+    {
+        tokio::spawn(async move {
+            loop {
+                self.status.lock().await.polling = true;
+                let result = poll().await;
+
+                let wait = {
+                    let mut status = self.status.lock().await;
+                    status.polling = false;
+                    let wait = match result {
+                        Ok(()) => {
+                            status.last_success = Some(Utc::now());
+                            status.last_error = None;
+                            status.backoff_level = 0;
+                            self.interval
+                        }
+                        Err(e) => {
+                            diagnostics::report(format!("Poll failed: {}", e));
+                            status.error_count += 1;
+                            if matches!(e, ApiError::RateLimited) {
+                                status.rate_limited_count += 1;
+                            }
+                            let wait = if e.is_retryable() {
+                                status.backoff_level += 1;
+                                backoff(self.interval, status.backoff_level)
+                            } else {
+                                self.interval
+                            };
+                            status.last_error = Some(e.to_string());
+                            wait
+                        }
+                    };
+                    let wait = wait.max(self.client.rate_limiter().idle_in());
+                    status.next_poll = chrono::Duration::from_std(wait)
+                        .ok()
+                        .map(|wait| Utc::now() + wait);
+                    wait
+                };
+
+                tokio::time::sleep(wait).await;
+            }
+        })
+    }
+}
+
+/// `interval * 2^level`, capped at [`MAX_BACKOFF`].
+fn backoff(interval: Duration, level: u32) -> Duration {
+    interval
+        .checked_mul(1 << level.min(16))
+        .unwrap_or(MAX_BACKOFF)
+        .min(MAX_BACKOFF)
+}
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::Utc;
+use reqwest::header::HeaderMap;
+use tokio::time::Instant;
+
+/// API endpoints with their own rate limit bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+    Pies,
+    Pie,
+    AccountCash,
+    AccountInfo,
+    Portfolio,
+    Dividends,
+}
+
+impl Endpoint {
+    /// Documented minimum spacing between two calls, used until the API tells us otherwise.
+    pub fn default_interval(self) -> Duration {
+        match self {
+            Endpoint::Pies => Duration::from_secs(30),
+            Endpoint::Pie => Duration::from_secs(5),
+            Endpoint::AccountCash => Duration::from_secs(2),
+            Endpoint::AccountInfo => Duration::from_secs(30),
+            Endpoint::Portfolio => Duration::from_secs(5),
+            Endpoint::Dividends => Duration::from_secs(10),
+        }
+    }
+}
+
+/// The `x-ratelimit-*` headers of a response.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RateLimitHeaders {
+    pub limit: Option<u32>,
+    /// Length of the rate limit window in seconds.
+    pub period: Option<u64>,
+    pub remaining: Option<u32>,
+    /// Unix timestamp (seconds) at which the window resets.
+    pub reset: Option<i64>,
+}
+
+impl RateLimitHeaders {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        fn parse<T: std::str::FromStr>(headers: &HeaderMap, name: &str) -> Option<T> {
+            headers.get(name)?.to_str().ok()?.trim().parse().ok()
+        }
+        Self {
+            limit: parse(headers, "x-ratelimit-limit"),
+            period: parse(headers, "x-ratelimit-period"),
+            remaining: parse(headers, "x-ratelimit-remaining"),
+            reset: parse(headers, "x-ratelimit-reset"),
+        }
+    }
+
+    /// How long from now until the window resets, if the server told us.
+    fn reset_in(&self) -> Option<Duration> {
+        let secs = self.reset? - Utc::now().timestamp();
+        Some(Duration::from_secs(secs.max(0) as u64))
+    }
+}
+
+#[derive(Debug, Default)]
+struct Bucket {
+    next_allowed: Option<Instant>,
+    interval: Option<Duration>,
+}
+
+/// Spaces out calls per [`Endpoint`] so that the API limits are never hit on purpose.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<Endpoint, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Waits until `endpoint` may be called and reserves the slot.
+    pub async fn acquire(&self, endpoint: Endpoint) {
+        loop {
+            let wait = {
+                let mut buckets = self.buckets.lock().unwrap();
+                let bucket = buckets.entry(endpoint).or_default();
+                let now = Instant::now();
+                match bucket.next_allowed {
+                    Some(next) if next > now => next - now,
+                    _ => {
+                        let interval = bucket.interval.unwrap_or(endpoint.default_interval());
+                        bucket.next_allowed = Some(now + interval);
+                        return;
+                    }
+                }
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Updates the bucket from the headers of a response to `endpoint`.
+    pub fn observe(&self, endpoint: Endpoint, headers: &RateLimitHeaders) {
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(endpoint).or_default();
+
+        if let (Some(limit), Some(period)) = (headers.limit, headers.period)
+            && limit > 0
+        {
+            bucket.interval = Some(Duration::from_secs(period) / limit);
+        }
+        if headers.remaining == Some(0)
+            && let Some(reset_in) = headers.reset_in()
+        {
+            defer_bucket(bucket, reset_in);
+        }
+    }
+
+    /// Blocks `endpoint` for at least `delay` from now.
+    pub fn defer(&self, endpoint: Endpoint, delay: Duration) {
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(endpoint).or_default();
+        defer_bucket(bucket, delay);
+    }
+
+    /// Time until every endpoint used so far can be called again without waiting.
+    pub fn idle_in(&self) -> Duration {
+        let now = Instant::now();
+        self.buckets
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|bucket| bucket.next_allowed)
+            .map(|next| next.saturating_duration_since(now))
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+fn defer_bucket(bucket: &mut Bucket, delay: Duration) {
+    let until = Instant::now() + delay;
+    if bucket.next_allowed.is_none_or(|next| next < until) {
+        bucket.next_allowed = Some(until);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn reads_rate_limit_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-limit", HeaderValue::from_static("6"));
+        headers.insert("x-ratelimit-period", HeaderValue::from_static(" 60 "));
+        headers.insert("x-ratelimit-remaining", HeaderValue::from_static("0"));
+        headers.insert("x-ratelimit-reset", HeaderValue::from_static("not a number"));
+        let parsed = RateLimitHeaders::from_headers(&headers);
+        assert_eq!(parsed, RateLimitHeaders { limit: Some(6), period: Some(60), remaining: Some(0), reset: None });
+    }
+
+    #[tokio::test]
+    async fn acquire_reserves_the_default_interval() {
+        let limiter = RateLimiter::new();
+        assert_eq!(limiter.idle_in(), Duration::ZERO);
+        limiter.acquire(Endpoint::Pies).await;
+        let idle = limiter.idle_in();
+        assert!(idle > Duration::from_secs(29) && idle <= Duration::from_secs(30), "{:?}", idle);
+    }
+
+    #[tokio::test]
+    async fn headers_set_the_interval_of_their_bucket_only() {
+        let limiter = RateLimiter::new();
+        let headers = RateLimitHeaders { limit: Some(60), period: Some(6), ..Default::default() };
+        limiter.observe(Endpoint::Pie, &headers);
+        limiter.acquire(Endpoint::Pie).await;
+        // 60 calls in 6 seconds: one every 100 ms instead of the documented 5 s
+        assert!(limiter.idle_in() <= Duration::from_millis(100));
+        limiter.acquire(Endpoint::Pie).await;
+        limiter.acquire(Endpoint::AccountCash).await;
+        assert!(limiter.idle_in() > Duration::from_secs(1));
+    }
+
+    #[test]
+    fn exhausted_window_blocks_until_reset() {
+        let limiter = RateLimiter::new();
+        let headers = RateLimitHeaders {
+            remaining: Some(0),
+            reset: Some(Utc::now().timestamp() + 20),
+            ..Default::default()
+        };
+        limiter.observe(Endpoint::Portfolio, &headers);
+        assert!(limiter.idle_in() > Duration::from_secs(18));
+    }
+
+    #[test]
+    fn defer_never_shortens_a_wait() {
+        let limiter = RateLimiter::new();
+        limiter.defer(Endpoint::Dividends, Duration::from_secs(60));
+        limiter.defer(Endpoint::Dividends, Duration::from_secs(1));
+        assert!(limiter.idle_in() > Duration::from_secs(59));
+    }
+}
@@ -0,0 +1,211 @@
+//! Rebalancing plans for a pie from the target and current weights of its instruments: how to
+//! split a deposit to get as close to target as possible without selling, and the buys and sells
+//! that would restore the targets exactly. Nothing here places orders.
+
+use serde::Serialize;
+
+use crate::models::Pie;
+
+/// One instrument's part of a [`Plan`]. Weights are fractions of 1, amounts in the pie's currency.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanLine {
+    pub ticker: String,
+    pub current_value: f64,
+    pub current_share: f64,
+    pub target_share: f64,
+    /// Positive to buy, negative to sell.
+    pub amount: f64,
+    pub share_after: f64,
+}
+
+impl PlanLine {
+    /// Weight after the plan minus target weight, in percentage points.
+    pub fn drift_after(&self) -> f64 {
+        (self.share_after - self.target_share) * 100.0
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Plan {
+    /// In the order of the pie's instruments.
+    pub lines: Vec<PlanLine>,
+    /// Value of the pie once the plan is carried out.
+    pub value_after: f64,
+}
+
+impl Plan {
+    /// The largest distance from target after the plan, in percentage points.
+    pub fn max_drift_after(&self) -> f64 {
+        self.lines.iter().map(|line| line.drift_after().abs()).fold(0.0, f64::max)
+    }
+
+    pub fn bought(&self) -> f64 {
+        self.lines.iter().map(|line| line.amount.max(0.0)).sum()
+    }
+
+    pub fn sold(&self) -> f64 {
+        self.lines.iter().map(|line| -line.amount.min(0.0)).sum()
+    }
+}
+
+/// Splits `deposit` across the pie's instruments without selling anything, so that the weights
+/// end up as close to target as they can (least squared drift). The most underweight instruments
+/// are topped up first; once they reach the same shortfall the rest is shared between them.
+pub fn deposit_plan(pie: &Pie, deposit: f64) -> Plan {
+    let deposit = deposit.max(0.0);
+    let (values, targets) = values_and_targets(pie);
+    let total_after = values.iter().sum::<f64>() + deposit;
+    let gaps: Vec<f64> = values.iter().zip(&targets).map(|(value, target)| target * total_after - value).collect();
+
+    // Every instrument below the level gets filled up to it; find the level that uses the
+    // deposit exactly
+    let mut sorted = gaps.clone();
+    sorted.sort_by(|a, b| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+    let mut level = f64::INFINITY;
+    let mut filled = 0.0;
+    for (count, gap) in sorted.iter().enumerate() {
+        filled += gap;
+        let candidate = (filled - deposit) / (count + 1) as f64;
+        if *gap > candidate {
+            level = candidate;
+        } else {
+            break;
+        }
+    }
+    let amounts: Vec<f64> = gaps.iter().map(|gap| (gap - level).max(0.0)).collect();
+    build_plan(pie, &values, &targets, &amounts)
+}
+
+/// The buys and sells that bring every instrument to its target weight once `deposit` is added
+/// (a negative deposit is a withdrawal).
+pub fn full_rebalance(pie: &Pie, deposit: f64) -> Plan {
+    let (values, targets) = values_and_targets(pie);
+    let total_after = (values.iter().sum::<f64>() + deposit).max(0.0);
+    let amounts: Vec<f64> = values.iter().zip(&targets).map(|(value, target)| target * total_after - value).collect();
+    build_plan(pie, &values, &targets, &amounts)
+}
+
+/// Current value and target weight of every instrument. Targets are scaled to add up to 1, so
+/// rounding in the API's shares doesn't leave part of a deposit unplanned.
+fn values_and_targets(pie: &Pie) -> (Vec<f64>, Vec<f64>) {
+    let values = pie.instruments.iter().map(|instrument| instrument.current_share * pie.result.price_avg_value).collect();
+    let target_sum: f64 = pie.instruments.iter().map(|instrument| instrument.expected_share).sum();
+    let targets = pie.instruments.iter()
+        .map(|instrument| if target_sum > 0.0 { instrument.expected_share / target_sum } else { 0.0 })
+        .collect();
+    (values, targets)
+}
+
+fn build_plan(pie: &Pie, values: &[f64], targets: &[f64], amounts: &[f64]) -> Plan {
+    let value_after: f64 = values.iter().zip(amounts).map(|(value, amount)| value + amount).sum();
+    let lines = pie.instruments.iter()
+        .zip(values.iter().zip(targets).zip(amounts))
+        .map(|(instrument, ((value, target), amount))| PlanLine {
+            ticker: instrument.ticker.clone(),
+            current_value: *value,
+            current_share: instrument.current_share,
+            target_share: *target,
+            amount: *amount,
+            share_after: if value_after > 0.0 { (value + amount) / value_after } else { 0.0 },
+        })
+        .collect();
+    Plan { lines, value_after }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A pie worth 1000 with instruments given as `(ticker, current share, target share)`.
+    fn pie(instruments: &[(&str, f64, f64)]) -> Pie {
+        let instruments: Vec<_> = instruments
+            .iter()
+            .map(|(ticker, current, target)| json!({
+                "ticker": ticker, "ownedQuantity": 1.0, "expectedShare": target, "currentShare": current,
+                "result": {"priceAvgInvestedValue": 0.0, "priceAvgValue": 0.0, "priceAvgResult": 0.0, "priceAvgResultCoef": 0.0}
+            }))
+            .collect();
+        serde_json::from_value(json!({
+            "id": 1, "cash": 0.0, "dividendDetails": {"gained": 0.0, "reinvested": 0.0, "inCash": 0.0},
+            "result": {"priceAvgInvestedValue": 1000.0, "priceAvgValue": 1000.0, "priceAvgResult": 0.0, "priceAvgResultCoef": 0.0},
+            "progress": null, "status": null, "created_at": null, "name": "One", "instruments": instruments
+        }))
+        .unwrap()
+    }
+
+    fn amounts(plan: &Plan) -> Vec<f64> {
+        plan.lines.iter().map(|line| line.amount).collect()
+    }
+
+    fn close_to(actual: &[f64], expected: &[f64]) -> bool {
+        actual.len() == expected.len() && actual.iter().zip(expected).all(|(a, e)| (a - e).abs() < 1e-9)
+    }
+
+    const DRIFTED: &[(&str, f64, f64)] = &[("A", 0.5, 0.3), ("B", 0.3, 0.3), ("C", 0.2, 0.4)];
+
+    #[test]
+    fn a_deposit_tops_up_the_most_underweight_first() {
+        let plan = deposit_plan(&pie(DRIFTED), 100.0);
+        assert!(close_to(&amounts(&plan), &[0.0, 0.0, 100.0]), "{plan:?}");
+        assert!((plan.value_after - 1100.0).abs() < 1e-9);
+        assert_eq!(plan.sold(), 0.0);
+
+        // C is topped up until it is as far short as B, then both are filled alike (each ends 40 short)
+        let plan = deposit_plan(&pie(DRIFTED), 400.0);
+        assert!(close_to(&amounts(&plan), &[0.0, 80.0, 320.0]), "{plan:?}");
+    }
+
+    #[test]
+    fn nothing_to_split_without_a_deposit() {
+        for deposit in [0.0, -50.0] {
+            let plan = deposit_plan(&pie(DRIFTED), deposit);
+            assert!(close_to(&amounts(&plan), &[0.0, 0.0, 0.0]), "{plan:?}");
+            assert!((plan.value_after - 1000.0).abs() < 1e-9);
+            assert!((plan.max_drift_after() - 20.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn a_deposit_larger_than_every_gap_lands_on_target() {
+        let plan = deposit_plan(&pie(DRIFTED), 2000.0);
+        assert!(close_to(&amounts(&plan), &[400.0, 600.0, 1000.0]), "{plan:?}");
+        assert!((plan.bought() - 2000.0).abs() < 1e-9);
+        assert!(plan.max_drift_after() < 1e-9);
+    }
+
+    #[test]
+    fn a_pie_without_instruments_has_an_empty_plan() {
+        let plan = deposit_plan(&pie(&[]), 100.0);
+        assert!(plan.lines.is_empty());
+        assert_eq!(plan.bought(), 0.0);
+        assert_eq!(plan.max_drift_after(), 0.0);
+    }
+
+    #[test]
+    fn a_full_rebalance_trades_to_target() {
+        let plan = full_rebalance(&pie(DRIFTED), 0.0);
+        assert!(close_to(&amounts(&plan), &[-200.0, 0.0, 200.0]), "{plan:?}");
+        assert!(close_to(&[plan.bought() - plan.sold()], &[0.0]));
+        assert!(plan.max_drift_after() < 1e-9);
+
+        // With a deposit the trades add up to it
+        let plan = full_rebalance(&pie(DRIFTED), 100.0);
+        assert!(close_to(&[plan.bought() - plan.sold(), plan.value_after], &[100.0, 1100.0]));
+        assert!(plan.max_drift_after() < 1e-9);
+    }
+
+    #[test]
+    fn a_balanced_pie_needs_no_trades() {
+        let plan = full_rebalance(&pie(&[("A", 0.6, 0.6), ("B", 0.4, 0.4)]), 0.0);
+        assert!(close_to(&amounts(&plan), &[0.0, 0.0]), "{plan:?}");
+        assert_eq!((plan.bought(), plan.sold()), (0.0, 0.0));
+    }
+
+    #[test]
+    fn instruments_without_a_target_are_sold_off() {
+        let plan = full_rebalance(&pie(&[("A", 0.5, 0.5), ("B", 0.3, 0.5), ("X", 0.2, 0.0)]), 0.0);
+        assert!(close_to(&amounts(&plan), &[0.0, 200.0, -200.0]), "{plan:?}");
+        assert_eq!(plan.lines[2].share_after, 0.0);
+    }
+}
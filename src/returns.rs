@@ -0,0 +1,409 @@
+use chrono::{Datelike, TimeZone, Utc};
+
+use crate::history::{History, PieValuePoint, TotalValuePoint};
+use crate::ledger::Ledger;
+use crate::models::Pie;
+
+const SECONDS_PER_YEAR: f64 = 365.0 * 86400.0;
+
+/// Changes in invested value smaller than this are treated as rounding noise, not cash flows.
+const MIN_FLOW: f64 = 0.005;
+
+/// Compound annual growth from `initial_value` to `final_value` since `create_date`, in percent.
+pub fn calculate_annual_rate(
+    initial_value: f64,
+    final_value: f64,
+    create_date: f64,
+) -> f64 {
+    if initial_value <= 0.0 || create_date <= 0.0 {
+        return 0.0;
+    }
+    let now = chrono::Utc::now().timestamp() as f64; // Convert to years
+    if now <= create_date {
+        return 0.0;
+    }
+    ((final_value / initial_value).powf(365.0 * 86400.0 / (now - create_date)) - 1.0) * 100.0
+}
+
+/// Money moved into (positive) or out of (negative) a pie or the portfolio.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CashFlow {
+    pub timestamp: f64, // Unix timestamp in seconds
+    pub amount: f64,
+}
+
+/// Annualised money-weighted return, as a fraction (0.07 = 7%/year).
+///
+/// `flows` are deposits/withdrawals; `final_value` is what the holding is worth at `at`.
+/// Returns `None` when there is nothing to solve (no deposits) or no root is found.
+pub fn xirr(flows: &[CashFlow], final_value: f64, at: f64) -> Option<f64> {
+    let start = flows.iter().map(|f| f.timestamp).fold(f64::INFINITY, f64::min);
+    if !start.is_finite() || at <= start || !flows.iter().any(|f| f.amount > 0.0) {
+        return None;
+    }
+
+    // Net present value at rate r, seen from the investor: deposits are outflows
+    let npv = |rate: f64| -> f64 {
+        let mut total = final_value / (1.0 + rate).powf((at - start) / SECONDS_PER_YEAR);
+        for flow in flows {
+            total -= flow.amount / (1.0 + rate).powf((flow.timestamp - start) / SECONDS_PER_YEAR);
+        }
+        total
+    };
+
+    // Newton's method from a 10% guess, falling back to bisection if it wanders off
+    let mut rate = 0.1;
+    for _ in 0..50 {
+        let value = npv(rate);
+        if value.abs() < 1e-7 {
+            return Some(rate);
+        }
+        let step = 1e-6;
+        let derivative = (npv(rate + step) - value) / step;
+        if derivative == 0.0 || !derivative.is_finite() {
+            break;
+        }
+        let next = rate - value / derivative;
+        if !next.is_finite() || next <= -0.9999 {
+            break;
+        }
+        if (next - rate).abs() < 1e-10 {
+            return Some(next);
+        }
+        rate = next;
+    }
+
+    let (mut low, mut high) = (-0.9999, 100.0);
+    let (mut npv_low, npv_high) = (npv(low), npv(high));
+    if !npv_low.is_finite() || !npv_high.is_finite() || npv_low.signum() == npv_high.signum() {
+        return None;
+    }
+    for _ in 0..200 {
+        let mid = (low + high) / 2.0;
+        let npv_mid = npv(mid);
+        if npv_mid.abs() < 1e-7 || (high - low) < 1e-10 {
+            return Some(mid);
+        }
+        if npv_mid.signum() == npv_low.signum() {
+            low = mid;
+            npv_low = npv_mid;
+        } else {
+            high = mid;
+        }
+    }
+    Some((low + high) / 2.0)
+}
+
+/// Cash flows implied by a pie's value history.
+///
+/// The first sample's invested value is treated as a single deposit at `initial_at` (usually
+/// the pie's creation date); afterwards every change in invested value that isn't explained
+/// by reinvested dividends is a deposit or withdrawal at the time it was first seen.
+/// `points` must belong to a single pie and be sorted by time.
+pub fn infer_cash_flows(points: &[PieValuePoint], initial_at: Option<f64>) -> Vec<CashFlow> {
+    let Some(first) = points.first() else {
+        return Vec::new();
+    };
+    let mut flows = vec![CashFlow {
+        timestamp: initial_at.unwrap_or(first.timestamp).min(first.timestamp),
+        amount: first.invested_value - first.dividends_reinvested,
+    }];
+    for pair in points.windows(2) {
+        let (previous, current) = (&pair[0], &pair[1]);
+        let amount = (current.invested_value - previous.invested_value)
+            - (current.dividends_reinvested - previous.dividends_reinvested);
+        if amount.abs() >= MIN_FLOW {
+            flows.push(CashFlow { timestamp: current.timestamp, amount });
+        }
+    }
+    flows
+}
+
+/// Combines flows recorded by hand with inferred ones.
+///
+/// When a pie has manual entries they replace the inferred flows up to the last manual entry,
+/// including the synthetic initial deposit.
+pub fn merge_cash_flows(manual: &[CashFlow], inferred: &[CashFlow]) -> Vec<CashFlow> {
+    let Some(last_manual) = manual.iter().map(|f| f.timestamp).reduce(f64::max) else {
+        return inferred.to_vec();
+    };
+    let mut flows: Vec<CashFlow> = manual.to_vec();
+    flows.extend(inferred.iter().skip(1).filter(|f| f.timestamp > last_manual));
+    flows.sort_by(|a, b| a.timestamp.partial_cmp(&b.timestamp).unwrap_or(std::cmp::Ordering::Equal));
+    flows
+}
+
+/// Deposits and withdrawals of `pie`, from its value history and the manual ledger.
+pub fn pie_cash_flows(history: &History, pie: &Pie, ledger: &Ledger) -> Vec<CashFlow> {
+    let points = history.pie(pie.id);
+    let inferred = if points.is_empty() {
+        // Nothing recorded yet: assume everything was invested when the pie was created
+        pie.created_at
+            .filter(|created_at| *created_at > 0.0)
+            .map(|timestamp| CashFlow { timestamp, amount: pie.result.price_avg_invested_value })
+            .into_iter()
+            .collect()
+    } else {
+        infer_cash_flows(points, pie.created_at)
+    };
+    merge_cash_flows(ledger.flows(pie.id), &inferred)
+}
+
+/// Money-weighted return of `pie` up to `at`, as a fraction.
+pub fn pie_irr(history: &History, pie: &Pie, ledger: &Ledger, at: f64) -> Option<f64> {
+    xirr(&pie_cash_flows(history, pie, ledger), pie.result.price_avg_value, at)
+}
+
+/// Money-weighted return of all `pies` together, as a fraction.
+pub fn portfolio_irr(history: &History, pies: &[Pie], ledger: &Ledger, at: f64) -> Option<f64> {
+    let flows: Vec<CashFlow> = pies.iter().flat_map(|pie| pie_cash_flows(history, pie, ledger)).collect();
+    let total_value = pies.iter().map(|p| p.result.price_avg_value).sum();
+    xirr(&flows, total_value, at)
+}
+
+/// A value sample together with the net deposit made since the previous sample.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlowSample {
+    pub timestamp: f64, // Unix timestamp in seconds
+    pub value: f64,
+    pub flow: f64,
+}
+
+/// Samples of one pie, with deposits inferred like [`infer_cash_flows`] does.
+pub fn pie_flow_samples<'a>(points: impl IntoIterator<Item = &'a PieValuePoint>) -> Vec<FlowSample> {
+    flow_samples(points.into_iter().map(|p| (p.timestamp, p.value, p.invested_value - p.dividends_reinvested)))
+}
+
+/// Samples of the total portfolio.
+pub fn total_flow_samples<'a>(points: impl IntoIterator<Item = &'a TotalValuePoint>) -> Vec<FlowSample> {
+    flow_samples(points.into_iter().map(|p| (p.timestamp, p.total_value, p.invested_value - p.dividends_reinvested)))
+}
+
+fn flow_samples(points: impl Iterator<Item = (f64, f64, f64)>) -> Vec<FlowSample> {
+    let mut previous_deposited: Option<f64> = None;
+    points
+        .map(|(timestamp, value, deposited)| {
+            let flow = match previous_deposited {
+                Some(previous) if (deposited - previous).abs() >= MIN_FLOW => deposited - previous,
+                _ => 0.0,
+            };
+            previous_deposited = Some(deposited);
+            FlowSample { timestamp, value, flow }
+        })
+        .collect()
+}
+
+/// Cumulative time-weighted return at each sample relative to the first, as `[timestamp, fraction]`.
+///
+/// Each sub-period return excludes the deposit made during it, so the result doesn't depend on
+/// when or how much money was added.
+pub fn cumulative_twr(samples: &[FlowSample]) -> Vec<[f64; 2]> {
+    let Some(first) = samples.first() else {
+        return Vec::new();
+    };
+    let mut growth = 1.0;
+    let mut series = vec![[first.timestamp, 0.0]];
+    for pair in samples.windows(2) {
+        let (previous, current) = (&pair[0], &pair[1]);
+        if previous.value > 0.0 {
+            growth *= (current.value - current.flow) / previous.value;
+        }
+        series.push([current.timestamp, growth - 1.0]);
+    }
+    series
+}
+
+/// Time-weighted return over all of `samples`, as a fraction.
+pub fn twr(samples: &[FlowSample]) -> Option<f64> {
+    if samples.len() < 2 {
+        return None;
+    }
+    cumulative_twr(samples).last().map(|point| point[1])
+}
+
+/// Time-weighted growth over the whole of a series of samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TwrSpan {
+    /// Timestamps of the first and last sample.
+    pub from: f64,
+    pub to: f64,
+    /// Growth factor (1.1 = +10%).
+    pub growth: f64,
+}
+
+/// The time-weighted growth from the first to the last of `samples`, for comparing with another
+/// series over the same dates; `None` with fewer than two samples.
+pub fn twr_span(samples: &[FlowSample]) -> Option<TwrSpan> {
+    let twr = twr(samples)?;
+    Some(TwrSpan { from: samples.first()?.timestamp, to: samples.last()?.timestamp, growth: 1.0 + twr })
+}
+
+/// Look-back windows for time-weighted returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TwrPeriod {
+    OneDay,
+    OneWeek,
+    OneMonth,
+    YearToDate,
+    SinceInception,
+}
+
+impl TwrPeriod {
+    pub const ALL: [TwrPeriod; 5] = [
+        TwrPeriod::OneDay,
+        TwrPeriod::OneWeek,
+        TwrPeriod::OneMonth,
+        TwrPeriod::YearToDate,
+        TwrPeriod::SinceInception,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            TwrPeriod::OneDay => "1D",
+            TwrPeriod::OneWeek => "1W",
+            TwrPeriod::OneMonth => "1M",
+            TwrPeriod::YearToDate => "YTD",
+            TwrPeriod::SinceInception => "Since inception",
+        }
+    }
+
+    /// Unix timestamp where the period starts, `None` for since inception.
+    pub fn start(self, now: f64) -> Option<f64> {
+        match self {
+            TwrPeriod::OneDay => Some(now - 86400.0),
+            TwrPeriod::OneWeek => Some(now - 7.0 * 86400.0),
+            TwrPeriod::OneMonth => Some(now - 30.0 * 86400.0),
+            TwrPeriod::YearToDate => {
+                let year = chrono::DateTime::from_timestamp(now as i64, 0)?.year();
+                Some(Utc.with_ymd_and_hms(year, 1, 1, 0, 0, 0).single()?.timestamp() as f64)
+            }
+            TwrPeriod::SinceInception => None,
+        }
+    }
+}
+
+/// Time-weighted return of `samples` restricted to `period`, as a fraction. `None` when the
+/// history doesn't reach back to the start of the period.
+pub fn period_twr(samples: &[FlowSample], period: TwrPeriod, now: f64) -> Option<f64> {
+    twr(period_samples(samples, period, now)?)
+}
+
+/// The part of `samples` covering `period`, from the last sample at or before its start so the
+/// whole period is covered. `None` when no sample is that old.
+pub fn period_samples(samples: &[FlowSample], period: TwrPeriod, now: f64) -> Option<&[FlowSample]> {
+    let first = match period.start(now) {
+        Some(start) => samples.iter().rposition(|s| s.timestamp <= start)?,
+        None => 0,
+    };
+    Some(&samples[first..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: f64 = 86400.0;
+
+    fn flow(timestamp: f64, amount: f64) -> CashFlow {
+        CashFlow { timestamp, amount }
+    }
+
+    fn sample(timestamp: f64, value: f64, flow: f64) -> FlowSample {
+        FlowSample { timestamp, value, flow }
+    }
+
+    fn point(timestamp: f64, invested_value: f64, dividends_reinvested: f64) -> PieValuePoint {
+        PieValuePoint { timestamp, pie_id: 1, value: invested_value, invested_value, dividends_gained: dividends_reinvested, dividends_reinvested }
+    }
+
+    #[test]
+    fn xirr_of_a_single_deposit_is_its_annual_growth() {
+        let irr = xirr(&[flow(0.0, 100.0)], 110.0, SECONDS_PER_YEAR).unwrap();
+        assert!((irr - 0.1).abs() < 1e-6);
+
+        // 100 for a year at 10% gives 110, another 100 for half a year at 10% gives 104.88
+        let flows = [flow(0.0, 100.0), flow(SECONDS_PER_YEAR, 100.0)];
+        let irr = xirr(&flows, 110.0 * 1.1_f64.sqrt() + 100.0 * 1.1_f64.sqrt(), 1.5 * SECONDS_PER_YEAR).unwrap();
+        assert!((irr - 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn xirr_needs_a_deposit_and_a_root() {
+        // Only withdrawals: nothing was put in
+        assert_eq!(xirr(&[flow(0.0, -100.0)], 50.0, SECONDS_PER_YEAR), None);
+        // 250 back for 100 put in a day earlier: the NPV stays positive at every rate
+        assert_eq!(xirr(&[flow(0.0, 100.0), flow(DAY, -200.0)], 50.0, SECONDS_PER_YEAR), None);
+        assert_eq!(xirr(&[], 100.0, SECONDS_PER_YEAR), None);
+        assert_eq!(xirr(&[flow(SECONDS_PER_YEAR, 100.0)], 110.0, SECONDS_PER_YEAR), None);
+    }
+
+    #[test]
+    fn cash_flows_leave_out_reinvested_dividends_and_noise() {
+        let points = [
+            point(10.0 * DAY, 100.0, 0.0),
+            point(11.0 * DAY, 150.0, 0.0),
+            point(12.0 * DAY, 152.0, 2.0),
+            point(13.0 * DAY, 152.001, 2.0),
+            point(14.0 * DAY, 132.001, 2.0),
+        ];
+        assert_eq!(
+            infer_cash_flows(&points, Some(DAY)),
+            [flow(DAY, 100.0), flow(11.0 * DAY, 50.0), flow(14.0 * DAY, -20.0)]
+        );
+        // A creation date after the first sample doesn't move the first deposit forward
+        assert_eq!(infer_cash_flows(&points[..1], Some(20.0 * DAY)), [flow(10.0 * DAY, 100.0)]);
+        assert!(infer_cash_flows(&[], None).is_empty());
+    }
+
+    #[test]
+    fn twr_ignores_deposits() {
+        // +10%, then 100 deposited, then -10%
+        let samples = [sample(0.0, 100.0, 0.0), sample(DAY, 110.0, 0.0), sample(2.0 * DAY, 210.0, 100.0), sample(3.0 * DAY, 189.0, 0.0)];
+        let series = cumulative_twr(&samples);
+        let returns: Vec<f64> = series.iter().map(|point| point[1]).collect();
+        assert_eq!(series.iter().map(|point| point[0]).collect::<Vec<_>>(), [0.0, DAY, 2.0 * DAY, 3.0 * DAY]);
+        for (actual, expected) in returns.iter().zip([0.0, 0.1, 0.1, -0.01]) {
+            assert!((actual - expected).abs() < 1e-12, "{returns:?}");
+        }
+        assert!(cumulative_twr(&[]).is_empty());
+        assert_eq!(twr(&samples[..1]), None);
+
+        let span = twr_span(&samples).unwrap();
+        assert_eq!((span.from, span.to), (0.0, 3.0 * DAY));
+        assert!((span.growth - 0.99).abs() < 1e-12);
+        assert_eq!(twr_span(&samples[..1]), None);
+    }
+
+    #[test]
+    fn period_twr_starts_at_the_last_sample_before_the_period() {
+        let now = 10.0 * DAY;
+        let samples = [
+            sample(now - 9.0 * DAY, 50.0, 0.0),
+            sample(now - 2.0 * DAY, 100.0, 0.0),
+            sample(now - 0.5 * DAY, 120.0, 0.0),
+            sample(now, 90.0, 0.0),
+        ];
+        assert_eq!(period_samples(&samples, TwrPeriod::OneDay, now).unwrap().len(), 3);
+        assert!((period_twr(&samples, TwrPeriod::OneDay, now).unwrap() + 0.1).abs() < 1e-12);
+        assert!((period_twr(&samples, TwrPeriod::SinceInception, now).unwrap() - 0.8).abs() < 1e-12);
+    }
+
+    #[test]
+    fn period_twr_needs_history_from_the_start_of_the_period() {
+        let now = 10.0 * DAY;
+        let samples = [sample(now - 9.0 * DAY, 50.0, 0.0), sample(now, 90.0, 0.0)];
+        assert_eq!(period_samples(&samples, TwrPeriod::OneMonth, now), None);
+        assert_eq!(period_twr(&samples, TwrPeriod::OneMonth, now), None);
+        assert!(period_twr(&samples, TwrPeriod::OneWeek, now).is_some());
+        assert_eq!(period_twr(&[], TwrPeriod::SinceInception, now), None);
+    }
+
+    #[test]
+    fn annual_rate_compounds_over_the_time_since_creation() {
+        let created = Utc::now().timestamp() as f64 - 2.0 * SECONDS_PER_YEAR;
+        assert!((calculate_annual_rate(100.0, 121.0, created) - 10.0).abs() < 1e-3);
+        assert_eq!(calculate_annual_rate(0.0, 121.0, created), 0.0);
+        assert_eq!(calculate_annual_rate(100.0, 121.0, 0.0), 0.0);
+        assert_eq!(calculate_annual_rate(100.0, 121.0, created + 3.0 * SECONDS_PER_YEAR), 0.0);
+    }
+}
@@ -0,0 +1,222 @@
+//! Risk figures from the recorded value history: volatility, drawdowns and risk-adjusted returns.
+//!
+//! Everything is computed on the time-weighted growth of a series, so deposits and withdrawals
+//! don't count as gains or losses.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::history::History;
+use crate::models::Pie;
+use crate::returns::{self, FlowSample, TwrPeriod};
+
+const DAY: f64 = 86400.0;
+const YEAR: f64 = 365.0 * DAY;
+
+/// Fractions, not percent; drawdowns are zero or negative.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RiskMetrics {
+    /// Annualised standard deviation of daily returns.
+    pub volatility: Option<f64>,
+    pub max_drawdown: f64,
+    /// When the largest fall started and bottomed out (Unix timestamps in seconds).
+    pub max_drawdown_peak: f64,
+    pub max_drawdown_trough: f64,
+    pub current_drawdown: f64,
+    pub sharpe: Option<f64>,
+    pub sortino: Option<f64>,
+}
+
+impl RiskMetrics {
+    /// Metrics over all of `samples`; `risk_free_rate` is annual. The ratios and volatility need
+    /// at least three days of samples.
+    pub fn new(samples: &[FlowSample], risk_free_rate: f64) -> Option<Self> {
+        if samples.len() < 2 {
+            return None;
+        }
+        let index = growth_index(samples);
+
+        let mut peak = index[0];
+        let mut max_drawdown = (0.0, index[0][0], index[0][0]);
+        for &[timestamp, value] in &index {
+            if value > peak[1] {
+                peak = [timestamp, value];
+            }
+            let drawdown = value / peak[1] - 1.0;
+            if drawdown < max_drawdown.0 {
+                max_drawdown = (drawdown, peak[0], timestamp);
+            }
+        }
+        let current_drawdown = index.last().map_or(0.0, |last| last[1] / peak[1] - 1.0);
+
+        let mut metrics = Self {
+            volatility: None,
+            max_drawdown: max_drawdown.0,
+            max_drawdown_peak: max_drawdown.1,
+            max_drawdown_trough: max_drawdown.2,
+            current_drawdown,
+            sharpe: None,
+            sortino: None,
+        };
+
+        // Daily closes keep the ratios comparable however often the history was sampled
+        let closes = daily_closes(&index);
+        let daily_returns: Vec<f64> = closes.windows(2).map(|pair| pair[1][1] / pair[0][1] - 1.0).collect();
+        if daily_returns.len() < 2 {
+            return Some(metrics);
+        }
+        let count = daily_returns.len() as f64;
+        // Periods per year from the actual spacing, so gaps in the history don't inflate it
+        let periods_per_year = YEAR * count / (closes[closes.len() - 1][0] - closes[0][0]);
+        let risk_free = (1.0 + risk_free_rate).powf(1.0 / periods_per_year) - 1.0;
+
+        let mean = daily_returns.iter().sum::<f64>() / count;
+        let variance = daily_returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (count - 1.0);
+        let deviation = variance.sqrt();
+        let excess = mean - risk_free;
+        let downside = (daily_returns.iter().map(|r| (r - risk_free).min(0.0).powi(2)).sum::<f64>() / count).sqrt();
+
+        metrics.volatility = Some(deviation * periods_per_year.sqrt());
+        metrics.sharpe = (deviation > 0.0).then(|| excess / deviation * periods_per_year.sqrt());
+        metrics.sortino = (downside > 0.0).then(|| excess / downside * periods_per_year.sqrt());
+        Some(metrics)
+    }
+}
+
+/// Growth of 1 invested at the first sample, at every sample.
+fn growth_index(samples: &[FlowSample]) -> Vec<[f64; 2]> {
+    returns::cumulative_twr(samples)
+        .into_iter()
+        .map(|[timestamp, twr]| [timestamp, 1.0 + twr])
+        .collect()
+}
+
+/// The first point, then the last point of each UTC day.
+fn daily_closes(index: &[[f64; 2]]) -> Vec<[f64; 2]> {
+    let mut closes: Vec<[f64; 2]> = Vec::new();
+    for (position, point) in index.iter().enumerate() {
+        let day = (point[0] / DAY).floor();
+        match closes.last_mut() {
+            Some(last) if position > 1 && (last[0] / DAY).floor() == day => *last = *point,
+            _ => closes.push(*point),
+        }
+    }
+    closes
+}
+
+/// How far below its running peak `series` is at each point, as a fraction (0 at a new high).
+pub fn drawdowns(series: &[[f64; 2]]) -> Vec<[f64; 2]> {
+    let mut peak = f64::NEG_INFINITY;
+    series
+        .iter()
+        .map(|&[timestamp, value]| {
+            peak = peak.max(value);
+            [timestamp, if peak > 0.0 { value / peak - 1.0 } else { 0.0 }]
+        })
+        .collect()
+}
+
+/// Drawdown of the time-weighted growth at each sample, for an underwater chart.
+pub fn underwater(samples: &[FlowSample]) -> Vec<[f64; 2]> {
+    drawdowns(&growth_index(samples))
+}
+
+/// [`RiskMetrics`] of every pie with enough history in `period`, keyed by pie id.
+pub fn pie_risks(
+    pies: &[Pie],
+    history: &History,
+    period: TwrPeriod,
+    risk_free_rate: f64,
+    now: f64,
+) -> HashMap<u64, RiskMetrics> {
+    pies.iter()
+        .filter_map(|pie| {
+            let samples = returns::pie_flow_samples(history.pie(pie.id));
+            let metrics = RiskMetrics::new(returns::period_samples(&samples, period, now)?, risk_free_rate)?;
+            Some((pie.id, metrics))
+        })
+        .collect()
+}
+
+/// [`RiskMetrics`] of the portfolio total in `period`.
+pub fn portfolio_risk(history: &History, period: TwrPeriod, risk_free_rate: f64, now: f64) -> Option<RiskMetrics> {
+    let samples = returns::total_flow_samples(&history.total);
+    RiskMetrics::new(returns::period_samples(&samples, period, now)?, risk_free_rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(timestamp: f64, value: f64, flow: f64) -> FlowSample {
+        FlowSample { timestamp, value, flow }
+    }
+
+    fn close_to(actual: f64, expected: f64) -> bool {
+        (actual - expected).abs() < 1e-9
+    }
+
+    #[test]
+    fn daily_closes_keep_the_first_point_and_the_last_of_each_day() {
+        let index = [
+            [100.0, 1.0],
+            [200.0, 1.1],
+            [300.0, 1.2],
+            [DAY + 10.0, 1.3],
+            [DAY + 500.0, 1.4],
+            [3.0 * DAY + 5.0, 1.5],
+        ];
+        assert_eq!(daily_closes(&index), [[100.0, 1.0], [300.0, 1.2], [DAY + 500.0, 1.4], [3.0 * DAY + 5.0, 1.5]]);
+        assert!(daily_closes(&[]).is_empty());
+    }
+
+    #[test]
+    fn metrics_from_daily_samples() {
+        // +10%, -10%, +10%, a day apart
+        let noon = DAY / 2.0;
+        let samples = [
+            sample(noon, 100.0, 0.0),
+            sample(noon + DAY, 110.0, 0.0),
+            sample(noon + 2.0 * DAY, 99.0, 0.0),
+            sample(noon + 3.0 * DAY, 108.9, 0.0),
+        ];
+        let metrics = RiskMetrics::new(&samples, 0.0).unwrap();
+        assert!(close_to(metrics.max_drawdown, -0.1));
+        assert_eq!((metrics.max_drawdown_peak, metrics.max_drawdown_trough), (noon + DAY, noon + 2.0 * DAY));
+        assert!(close_to(metrics.current_drawdown, -0.01));
+
+        // Daily returns 0.1, -0.1, 0.1: mean 1/30, sample deviation sqrt(0.04/3)
+        let deviation = (0.04_f64 / 3.0).sqrt();
+        let downside = (0.01_f64 / 3.0).sqrt();
+        assert!(close_to(metrics.volatility.unwrap(), deviation * 365.0_f64.sqrt()));
+        assert!(close_to(metrics.sharpe.unwrap(), (1.0 / 30.0) / deviation * 365.0_f64.sqrt()));
+        assert!(close_to(metrics.sortino.unwrap(), (1.0 / 30.0) / downside * 365.0_f64.sqrt()));
+
+        let with_risk_free = RiskMetrics::new(&samples, 0.05).unwrap();
+        assert!(with_risk_free.sharpe.unwrap() < metrics.sharpe.unwrap());
+        assert_eq!(with_risk_free.volatility, metrics.volatility);
+    }
+
+    #[test]
+    fn deposits_are_not_gains_or_drawdowns() {
+        let samples = [sample(0.0, 100.0, 0.0), sample(DAY, 200.0, 100.0), sample(2.0 * DAY, 190.0, 0.0)];
+        let metrics = RiskMetrics::new(&samples, 0.0).unwrap();
+        assert!(close_to(metrics.max_drawdown, -0.05));
+        // Level after the deposit, so the peak is still the first sample
+        assert_eq!((metrics.max_drawdown_peak, metrics.max_drawdown_trough), (0.0, 2.0 * DAY));
+    }
+
+    #[test]
+    fn ratios_need_two_daily_returns() {
+        assert!(RiskMetrics::new(&[], 0.0).is_none());
+        assert!(RiskMetrics::new(&[sample(0.0, 100.0, 0.0)], 0.0).is_none());
+
+        // Several samples, but all on one day
+        let samples: Vec<FlowSample> = (0..5).map(|i| sample(i as f64 * DAY / 4.0, 100.0 + i as f64, 0.0)).collect();
+        let metrics = RiskMetrics::new(&samples[..4], 0.0).unwrap();
+        assert_eq!(metrics.max_drawdown, 0.0);
+        assert!(metrics.volatility.is_none() && metrics.sharpe.is_none() && metrics.sortino.is_none());
+        assert!(RiskMetrics::new(&samples, 0.0).unwrap().volatility.is_some());
+    }
+}
@@ -0,0 +1,316 @@
+//! Local HTTP server exposing the dashboard's data, enabled with `PIERS_LISTEN`.
+//!
+//! Everything is read-only and served from the shared state, so clients never reach the API.
+//! `/` is a small web dashboard built on the JSON endpoints.
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::diagnostics;
+use crate::downsample;
+use crate::history::History;
+use crate::ledger::Ledger;
+use crate::metrics;
+use crate::models::{Account, Instrument, Pie};
+use crate::poller::PollerStatus;
+use crate::returns;
+use crate::summary::{pie_irrs, PieSummary, PortfolioSummary};
+
+/// The shared state the front ends display, as seen by request handlers.
+#[derive(Clone)]
+pub struct ServerState {
+    pub pies: Arc<Mutex<HashMap<usize, Pie>>>,
+    pub account: Arc<Mutex<Account>>,
+    pub history: Arc<Mutex<History>>,
+    pub ledger: Arc<Ledger>,
+    pub poller_status: Arc<Mutex<PollerStatus>>,
+}
+
+/// Binds `address` and serves in the background; fails only if the address can't be bound.
+pub async fn spawn(address: SocketAddr, state: ServerState) -> io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(address).await?;
+    let app = router(state);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            diagnostics::report(format!("HTTP server stopped: {}", e));
+        }
+    });
+    Ok(())
+}
+
+fn router(state: ServerState) -> Router {
+    Router::new()
+        .route("/", get(dashboard_handler))
+        .route("/status", get(status_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/pies", get(pies_handler))
+        .route("/pies/{id}", get(pie_handler))
+        .route("/summary", get(summary_handler))
+        .route("/history", get(history_handler))
+        .with_state(state)
+}
+
+/// `{"error": "..."}` with the given status.
+fn error(status: StatusCode, message: impl Into<String>) -> Response {
+    #[derive(Serialize)]
+    struct ErrorBody {
+        error: String,
+    }
+    (status, Json(ErrorBody { error: message.into() })).into_response()
+}
+
+async fn dashboard_handler() -> Html<&'static str> {
+    Html(include_str!("dashboard.html"))
+}
+
+/// [`PollerStatus`] with Unix timestamps, for the web dashboard's status line.
+#[derive(Serialize)]
+struct StatusBody {
+    polling: bool,
+    last_success: Option<i64>,
+    next_poll: Option<i64>,
+    last_error: Option<String>,
+    backoff_level: u32,
+    error_count: u64,
+    rate_limited_count: u64,
+}
+
+async fn status_handler(State(state): State<ServerState>) -> Json<StatusBody> {
+    let status = state.poller_status.lock().await.clone();
+    Json(StatusBody {
+        polling: status.polling,
+        last_success: status.last_success.map(|t| t.timestamp()),
+        next_poll: status.next_poll.map(|t| t.timestamp()),
+        last_error: status.last_error,
+        backoff_level: status.backoff_level,
+        error_count: status.error_count,
+        rate_limited_count: status.rate_limited_count,
+    })
+}
+
+async fn metrics_handler(State(state): State<ServerState>) -> impl IntoResponse {
+    let pies: Vec<Pie> = state.pies.lock().await.values().cloned().collect();
+    let account = state.account.lock().await.clone();
+    let status = state.poller_status.lock().await.clone();
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        metrics::render(&pies, &account, &status),
+    )
+}
+
+/// Pies ordered by id, with the figures of the dashboard table.
+async fn pies_handler(State(state): State<ServerState>) -> Json<Vec<PieSummary>> {
+    let mut pies: Vec<Pie> = state.pies.lock().await.values().cloned().collect();
+    pies.sort_by_key(|pie| pie.id);
+    let irr = {
+        let history = state.history.lock().await;
+        pie_irrs(&pies, &history, &state.ledger, Utc::now().timestamp() as f64)
+    };
+    Json(pies.iter().map(|pie| PieSummary::new(pie, irr.get(&pie.id).copied())).collect())
+}
+
+#[derive(Serialize)]
+struct PieDetailBody {
+    #[serde(flatten)]
+    summary: PieSummary,
+    instruments: Vec<Instrument>,
+}
+
+async fn pie_handler(State(state): State<ServerState>, Path(id): Path<u64>) -> Response {
+    let Some(pie) = state.pies.lock().await.get(&(id as usize)).cloned() else {
+        return error(StatusCode::NOT_FOUND, format!("no pie with id {}", id));
+    };
+    let irr = {
+        let history = state.history.lock().await;
+        returns::pie_irr(&history, &pie, &state.ledger, Utc::now().timestamp() as f64)
+    };
+    Json(PieDetailBody {
+        summary: PieSummary::new(&pie, irr),
+        instruments: pie.instruments,
+    })
+    .into_response()
+}
+
+async fn summary_handler(State(state): State<ServerState>) -> Json<PortfolioSummary> {
+    let pies: Vec<Pie> = state.pies.lock().await.values().cloned().collect();
+    let account = state.account.lock().await.clone();
+    let history = state.history.lock().await;
+    Json(PortfolioSummary::new(&pies, &account, &history, &state.ledger, Utc::now().timestamp() as f64))
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    /// Pie id; the portfolio total when absent.
+    pie: Option<u64>,
+    from: Option<String>,
+    to: Option<String>,
+    /// Reduce the answer to about this many samples, keeping spikes and the latest value.
+    points: Option<usize>,
+}
+
+/// Value samples of the total or one pie, optionally limited to `from`..=`to`.
+async fn history_handler(State(state): State<ServerState>, Query(query): Query<HistoryQuery>) -> Response {
+    let from = match query.from.as_deref().map(|value| parse_time(value, false)).transpose() {
+        Ok(from) => from.unwrap_or(f64::NEG_INFINITY),
+        Err(message) => return error(StatusCode::BAD_REQUEST, message),
+    };
+    let to = match query.to.as_deref().map(|value| parse_time(value, true)).transpose() {
+        Ok(to) => to.unwrap_or(f64::INFINITY),
+        Err(message) => return error(StatusCode::BAD_REQUEST, message),
+    };
+
+    let history = state.history.lock().await;
+    let in_range = |timestamp: f64| timestamp >= from && timestamp <= to;
+    match query.pie {
+        None => {
+            let points: Vec<_> = history.total.iter().filter(|p| in_range(p.timestamp)).collect();
+            Json(reduce(points, query.points, |p| [p.timestamp, p.total_value])).into_response()
+        }
+        Some(pie_id) => {
+            let points: Vec<_> = history.pie(pie_id).iter().filter(|p| in_range(p.timestamp)).collect();
+            Json(reduce(points, query.points, |p| [p.timestamp, p.value])).into_response()
+        }
+    }
+}
+
+/// Keeps the samples [`downsample::min_max_last`] would plot out of `points`, if a limit is given.
+fn reduce<T>(points: Vec<T>, limit: Option<usize>, xy: impl Fn(&T) -> [f64; 2]) -> Vec<T> {
+    let Some(limit) = limit.filter(|limit| *limit > 0) else {
+        return points;
+    };
+    let series: Vec<[f64; 2]> = points.iter().map(&xy).collect();
+    let kept: HashSet<u64> = downsample::min_max_last(&series, limit.div_ceil(3))
+        .iter()
+        .map(|point| point[0].to_bits())
+        .collect();
+    points.into_iter().filter(|p| kept.contains(&xy(p)[0].to_bits())).collect()
+}
+
+/// Accepts a Unix timestamp in seconds or a `YYYY-MM-DD` date (UTC, inclusive at both ends).
+fn parse_time(value: &str, end_of_day: bool) -> Result<f64, String> {
+    if let Ok(timestamp) = value.parse::<f64>() {
+        return Ok(timestamp);
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| format!("expected a Unix timestamp or YYYY-MM-DD, got {:?}", value))?;
+    let time = if end_of_day { date.and_hms_opt(23, 59, 59) } else { date.and_hms_opt(0, 0, 0) };
+    Ok(time.unwrap_or_default().and_utc().timestamp() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    const DAY: f64 = 86400.0;
+    /// 2024-01-02 00:00 UTC
+    const JAN_2: f64 = 1704153600.0;
+
+    fn pie(id: u64, value: f64) -> Pie {
+        serde_json::from_value(json!({
+            "id": id, "cash": 0.0, "dividendDetails": {"gained": 0.0, "reinvested": 0.0, "inCash": 0.0},
+            "result": {"priceAvgInvestedValue": 100.0, "priceAvgValue": value, "priceAvgResult": value - 100.0, "priceAvgResultCoef": value / 100.0 - 1.0},
+            "progress": null, "status": null, "created_at": null, "name": format!("Pie {}", id)
+        }))
+        .unwrap()
+    }
+
+    /// Serves two pies with a sample on each of three days, returning the base URL.
+    async fn serve() -> String {
+        let mut history = History::in_memory();
+        for day in 0..3 {
+            let value = 100.0 + day as f64;
+            history.record(JAN_2 + day as f64 * DAY, &[pie(1, value), pie(2, 2.0 * value)]).unwrap();
+        }
+        let state = ServerState {
+            pies: Arc::new(Mutex::new(HashMap::from([(1, pie(1, 102.0)), (2, pie(2, 204.0))]))),
+            account: Arc::new(Mutex::new(Account::default())),
+            history: Arc::new(Mutex::new(history)),
+            ledger: Arc::new(Ledger::default()),
+            poller_status: Arc::new(Mutex::new(PollerStatus::default())),
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router(state)).await });
+        base_url
+    }
+
+    async fn get_json(url: String) -> (u16, Value) {
+        let response = reqwest::get(url).await.unwrap();
+        (response.status().as_u16(), response.json().await.unwrap())
+    }
+
+    fn timestamps(body: &Value) -> Vec<f64> {
+        body.as_array().unwrap().iter().map(|point| point["timestamp"].as_f64().unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn pies_are_looked_up_by_id() {
+        let base_url = serve().await;
+        let (status, body) = get_json(format!("{}/pies/2", base_url)).await;
+        assert_eq!((status, body["name"].as_str()), (200, Some("Pie 2")));
+        let (status, body) = get_json(format!("{}/pies/3", base_url)).await;
+        assert_eq!((status, body), (404, json!({"error": "no pie with id 3"})));
+    }
+
+    #[tokio::test]
+    async fn history_is_filtered_by_pie_and_dates() {
+        let base_url = serve().await;
+        let (status, body) = get_json(format!("{}/history", base_url)).await;
+        assert_eq!(status, 200);
+        assert_eq!(timestamps(&body), [JAN_2, JAN_2 + DAY, JAN_2 + 2.0 * DAY]);
+        assert_eq!(body[2]["total_value"].as_f64(), Some(306.0));
+
+        // Dates include the whole of both days
+        let (_, body) = get_json(format!("{}/history?pie=2&from=2024-01-03&to=2024-01-03", base_url)).await;
+        assert_eq!(timestamps(&body), [JAN_2 + DAY]);
+        assert_eq!(body[0]["value"].as_f64(), Some(202.0));
+        let (_, body) = get_json(format!("{}/history?pie=1&from={}", base_url, JAN_2 + DAY + 1.0)).await;
+        assert_eq!(timestamps(&body), [JAN_2 + 2.0 * DAY]);
+        let (_, body) = get_json(format!("{}/history?pie=9", base_url)).await;
+        assert_eq!(body, json!([]));
+    }
+
+    #[tokio::test]
+    async fn bad_dates_are_rejected() {
+        let base_url = serve().await;
+        let (status, body) = get_json(format!("{}/history?from=02/01/2024", base_url)).await;
+        assert_eq!(status, 400);
+        assert_eq!(body["error"], "expected a Unix timestamp or YYYY-MM-DD, got \"02/01/2024\"");
+        let (status, _) = get_json(format!("{}/history?pie=1&to=yesterday", base_url)).await;
+        assert_eq!(status, 400);
+    }
+
+    #[tokio::test]
+    async fn serves_the_web_dashboard_and_its_summary() {
+        let base_url = serve().await;
+        let response = reqwest::get(format!("{}/", base_url)).await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert!(response.headers()[reqwest::header::CONTENT_TYPE].to_str().unwrap().starts_with("text/html"));
+        assert_eq!(response.text().await.unwrap(), include_str!("dashboard.html"));
+
+        let (status, summary) = get_json(format!("{}/summary", base_url)).await;
+        assert_eq!(status, 200);
+        // Everything dashboard.html reads, with the account figures empty until the account is fetched
+        for field in ["pies_initial", "pies_current", "pies_return_percent", "pies_return_value"] {
+            assert!(summary[field].is_number(), "{field}: {summary}");
+        }
+        for field in ["pies_irr_percent", "pies_twr_percent", "currency", "total_equity", "free_cash"] {
+            assert!(summary.get(field).is_some(), "{field}: {summary}");
+        }
+        assert_eq!(summary["pies_current"].as_f64(), Some(306.0));
+        assert!((summary["pies_twr_percent"].as_f64().unwrap() - 2.0).abs() < 1e-9);
+        assert_eq!(summary["total_equity"], Value::Null);
+    }
+}
@@ -0,0 +1,134 @@
+//! The latest pie and account snapshots (`pies.json`, `account.json`), kept so names and creation
+//! dates survive restarts and so a front end can follow what `piers collect` fetched.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use crate::alerts::{self, Alerts};
+use crate::diagnostics;
+use crate::history::History;
+use crate::models::{Account, Pie};
+use crate::poller::PollerStatus;
+
+pub const DEFAULT_PATH: &str = "pies.json";
+pub const DEFAULT_ACCOUNT_PATH: &str = "account.json";
+
+/// How often an attached front end re-reads the snapshots and history.
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often a polling front end writes the snapshots.
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+pub fn save_map(map: &HashMap<usize, Pie>, path: &str) -> std::io::Result<()> {
+    save(map, path)
+}
+
+pub fn load_map(path: &str) -> std::io::Result<HashMap<usize, Pie>> {
+    load(path)
+}
+
+pub fn save_account(account: &Account, path: &str) -> std::io::Result<()> {
+    save(account, path)
+}
+
+pub fn load_account(path: &str) -> std::io::Result<Account> {
+    load(path)
+}
+
+/// Writes `pies` and `account` to the default snapshot files every few seconds, so names, creation
+/// dates and the dividend history fetched so far survive a restart.
+pub fn spawn_saver(pies: Arc<Mutex<HashMap<usize, Pie>>>, account: Arc<Mutex<Account>>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(SAVE_INTERVAL).await;
+            if let Err(e) = save_map(&*pies.lock().await, DEFAULT_PATH) {
+                diagnostics::report(format!("Failed to save pies: {}", e));
+            }
+            if let Err(e) = save_account(&*account.lock().await, DEFAULT_ACCOUNT_PATH) {
+                diagnostics::report(format!("Failed to save account: {}", e));
+            }
+        }
+    })
+}
+
+/// Writes to a temporary file first so a reader never sees half a snapshot.
+fn save<T: Serialize>(value: &T, path: &str) -> std::io::Result<()> {
+    let json = serde_json::to_string_pretty(value)?;
+    let tmp_path = format!("{}.tmp", path);
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(json.as_bytes())?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+fn load<T: DeserializeOwned>(path: &str) -> std::io::Result<T> {
+    let mut file = File::open(path)?;
+    let mut data = String::new();
+    file.read_to_string(&mut data)?;
+    let value: T = serde_json::from_str(&data)?;
+    Ok(value)
+}
+
+/// Keeps `pies`, `account` and an attached `history` in step with the files another process writes,
+/// checking `alerts` after each read.
+///
+/// Stands in for the poller: the returned status reports when the collector last wrote a sample.
+pub fn spawn_follower(
+    pies: Arc<Mutex<HashMap<usize, Pie>>>,
+    account: Arc<Mutex<Account>>,
+    history: Arc<Mutex<History>>,
+    alerts: Arc<Mutex<Alerts>>,
+) -> Arc<Mutex<PollerStatus>> {
+    let status = Arc::new(Mutex::new(PollerStatus::default()));
+    let status_for_task = status.clone();
+    tokio::spawn(async move {
+        loop {
+            let mut errors = Vec::new();
+            match load_map(DEFAULT_PATH) {
+                Ok(map) => *pies.lock().await = map,
+                Err(e) => errors.push(format!("{}: {}", DEFAULT_PATH, e)),
+            }
+            // The account snapshot is optional: a collector may not have written it yet
+            if Path::new(DEFAULT_ACCOUNT_PATH).exists() {
+                match load_account(DEFAULT_ACCOUNT_PATH) {
+                    Ok(loaded) => *account.lock().await = loaded,
+                    Err(e) => errors.push(format!("{}: {}", DEFAULT_ACCOUNT_PATH, e)),
+                }
+            }
+            let last_sample = {
+                let mut history = history.lock().await;
+                if let Err(e) = history.refresh() {
+                    errors.push(format!("history: {}", e));
+                }
+                history.total.last().map(|point| point.timestamp)
+            };
+            alerts::check(&pies, &history, &alerts).await;
+
+            {
+                let mut status = status_for_task.lock().await;
+                status.last_success = last_sample.and_then(|t| DateTime::from_timestamp(t as i64, 0));
+                if errors.is_empty() {
+                    status.last_error = None;
+                } else {
+                    status.error_count += 1;
+                    status.last_error = Some(errors.join("; "));
+                }
+                status.next_poll = chrono::Duration::from_std(FOLLOW_INTERVAL)
+                    .ok()
+                    .map(|wait| Utc::now() + wait);
+            }
+            tokio::time::sleep(FOLLOW_INTERVAL).await;
+        }
+    });
+    status
+}
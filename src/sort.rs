@@ -0,0 +1,154 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::models::Pie;
+use crate::returns;
+use crate::risk::RiskMetrics;
+
+/// Column the pie table is sorted by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortColumn {
+    None,
+    InitialValue,
+    CurrentValue,
+    Return,
+    ReturnValue,
+    AnnualRate,
+    Irr,
+    /// Time-weighted return over the period the table shows.
+    Twr,
+    Dividends,
+    Volatility,
+    MaxDrawdown,
+    CurrentDrawdown,
+    Sharpe,
+    Sortino,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortDirection {
+    Ascending,
+    Descending,
+}
+
+impl SortDirection {
+    pub fn toggled(self) -> Self {
+        match self {
+            SortDirection::Ascending => SortDirection::Descending,
+            SortDirection::Descending => SortDirection::Ascending,
+        }
+    }
+
+    /// Turns the `ordering` of an ascending sort around for a descending one.
+    pub fn apply(self, ordering: Ordering) -> Ordering {
+        match self {
+            SortDirection::Ascending => ordering,
+            SortDirection::Descending => ordering.reverse(),
+        }
+    }
+}
+
+/// What clicking a column header does: the sorted column flips direction, any other column
+/// becomes the sort column, largest first.
+pub fn select_column(
+    current: SortColumn,
+    direction: SortDirection,
+    column: SortColumn,
+) -> (SortColumn, SortDirection) {
+    if current == column {
+        (column, direction.toggled())
+    } else {
+        (column, SortDirection::Descending)
+    }
+}
+
+/// Sorts `pies` the way the dashboard table does; `irr` holds each pie's money-weighted return,
+/// `twr` its time-weighted return and `risk` its risk metrics. Pies without a figure sort as the
+/// lowest.
+pub fn sort_pies(
+    pies: &mut [Pie],
+    column: SortColumn,
+    direction: SortDirection,
+    irr: &HashMap<u64, f64>,
+    twr: &HashMap<u64, f64>,
+    risk: &HashMap<u64, RiskMetrics>,
+) {
+    if column == SortColumn::None {
+        return;
+    }
+    let key = |pie: &Pie| sort_key(pie, column, irr, twr, risk).unwrap_or(f64::NEG_INFINITY);
+    pies.sort_by(|a, b| direction.apply(key(a).partial_cmp(&key(b)).unwrap_or(Ordering::Equal)));
+}
+
+/// The figure `column` sorts `pie` by.
+fn sort_key(
+    pie: &Pie,
+    column: SortColumn,
+    irr: &HashMap<u64, f64>,
+    twr: &HashMap<u64, f64>,
+    risk: &HashMap<u64, RiskMetrics>,
+) -> Option<f64> {
+    let metrics = risk.get(&pie.id);
+    match column {
+        SortColumn::None => None,
+        SortColumn::InitialValue => Some(pie.result.price_avg_invested_value),
+        SortColumn::CurrentValue => Some(pie.result.price_avg_value),
+        SortColumn::Return => Some(pie.result.price_avg_result_coef),
+        SortColumn::ReturnValue => Some(pie.result.price_avg_value - pie.result.price_avg_invested_value),
+        SortColumn::AnnualRate => Some(returns::calculate_annual_rate(
+            pie.result.price_avg_invested_value,
+            pie.result.price_avg_value,
+            pie.created_at.unwrap_or_default(),
+        )),
+        SortColumn::Irr => irr.get(&pie.id).copied(),
+        SortColumn::Twr => twr.get(&pie.id).copied(),
+        SortColumn::Dividends => Some(pie.dividend_details.gained),
+        SortColumn::Volatility => metrics.and_then(|m| m.volatility),
+        SortColumn::MaxDrawdown => metrics.map(|m| m.max_drawdown),
+        SortColumn::CurrentDrawdown => metrics.map(|m| m.current_drawdown),
+        SortColumn::Sharpe => metrics.and_then(|m| m.sharpe),
+        SortColumn::Sortino => metrics.and_then(|m| m.sortino),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pie(id: u64, value: f64) -> Pie {
+        serde_json::from_value(serde_json::json!({
+            "id": id, "cash": 0.0, "dividendDetails": {"gained": 0.0, "reinvested": 0.0, "inCash": 0.0},
+            "result": {"priceAvgInvestedValue": 100.0, "priceAvgValue": value, "priceAvgResult": value - 100.0, "priceAvgResultCoef": value / 100.0 - 1.0},
+            "progress": null, "status": null
+        }))
+        .unwrap()
+    }
+
+    fn ids(pies: &[Pie]) -> Vec<u64> {
+        pies.iter().map(|pie| pie.id).collect()
+    }
+
+    #[test]
+    fn sorts_both_ways_with_missing_figures_lowest() {
+        let mut pies = vec![pie(1, 120.0), pie(2, 90.0), pie(3, 150.0)];
+        let (no_irr, no_twr, no_risk) = (HashMap::new(), HashMap::new(), HashMap::new());
+        sort_pies(&mut pies, SortColumn::CurrentValue, SortDirection::Descending, &no_irr, &no_twr, &no_risk);
+        assert_eq!(ids(&pies), [3, 1, 2]);
+        sort_pies(&mut pies, SortColumn::Return, SortDirection::Ascending, &no_irr, &no_twr, &no_risk);
+        assert_eq!(ids(&pies), [2, 1, 3]);
+
+        let irr = HashMap::from([(1, 0.05), (3, -0.02)]);
+        sort_pies(&mut pies, SortColumn::Irr, SortDirection::Descending, &irr, &no_twr, &no_risk);
+        assert_eq!(ids(&pies), [1, 3, 2]);
+        sort_pies(&mut pies, SortColumn::Irr, SortDirection::Ascending, &irr, &no_twr, &no_risk);
+        assert_eq!(ids(&pies), [2, 3, 1]);
+
+        // Unsorted keeps the current order
+        sort_pies(&mut pies, SortColumn::None, SortDirection::Descending, &irr, &no_twr, &no_risk);
+        assert_eq!(ids(&pies), [2, 3, 1]);
+
+        let twr = HashMap::from([(2, 0.01), (3, 0.04)]);
+        sort_pies(&mut pies, SortColumn::Twr, SortDirection::Descending, &irr, &twr, &no_risk);
+        assert_eq!(ids(&pies), [3, 2, 1]);
+    }
+}
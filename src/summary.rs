@@ -0,0 +1,103 @@
+//! Normalised per-pie and portfolio figures, shared by the command line and the HTTP API.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::history::History;
+use crate::ledger::Ledger;
+use crate::models::{Account, Pie};
+use crate::returns::{self, TwrPeriod};
+
+/// One row of the dashboard's pie table. Percentages are in percent, not fractions.
+#[derive(Debug, Clone, Serialize)]
+pub struct PieSummary {
+    pub id: u64,
+    pub name: String,
+    pub status: String,
+    pub created_at: Option<f64>, // Unix timestamp in seconds
+    pub initial: f64,
+    pub current: f64,
+    pub return_percent: f64,
+    pub return_value: f64,
+    pub annual_rate_percent: f64,
+    pub irr_percent: Option<f64>,
+    pub cash: f64,
+    pub dividends_gained: f64,
+    pub dividends_reinvested: f64,
+    pub dividends_in_cash: f64,
+}
+
+impl PieSummary {
+    /// `irr` is the pie's money-weighted return as a fraction, see [`pie_irrs`].
+    pub fn new(pie: &Pie, irr: Option<f64>) -> Self {
+        let initial = pie.result.price_avg_invested_value;
+        let current = pie.result.price_avg_value;
+        Self {
+            id: pie.id,
+            name: pie.name.clone().unwrap_or_else(|| format!("Pie {}", pie.id)),
+            status: pie.status.clone().unwrap_or_else(|| "Active".to_string()),
+            created_at: pie.created_at,
+            initial,
+            current,
+            return_percent: pie.result.price_avg_result_coef * 100.0,
+            return_value: current - initial,
+            annual_rate_percent: returns::calculate_annual_rate(initial, current, pie.created_at.unwrap_or_default()),
+            irr_percent: irr.map(|irr| irr * 100.0),
+            cash: pie.cash,
+            dividends_gained: pie.dividend_details.gained,
+            dividends_reinvested: pie.dividend_details.reinvested,
+            dividends_in_cash: pie.dividend_details.in_cash,
+        }
+    }
+}
+
+/// Totals over all pies plus the account figures, when the account has been fetched.
+#[derive(Debug, Clone, Serialize)]
+pub struct PortfolioSummary {
+    pub pies_initial: f64,
+    pub pies_current: f64,
+    pub pies_return_percent: f64,
+    pub pies_return_value: f64,
+    pub pies_irr_percent: Option<f64>,
+    pub pies_twr_percent: Option<f64>,
+    pub currency: Option<String>,
+    pub total_equity: Option<f64>,
+    pub free_cash: Option<f64>,
+    pub blocked_cash: Option<f64>,
+    pub invested: Option<f64>,
+    pub pie_cash: Option<f64>,
+    pub unrealised_pl: Option<f64>,
+}
+
+impl PortfolioSummary {
+    pub fn new(pies: &[Pie], account: &Account, history: &History, ledger: &Ledger, now: f64) -> Self {
+        let initial: f64 = pies.iter().map(|p| p.result.price_avg_invested_value).sum();
+        let current: f64 = pies.iter().map(|p| p.result.price_avg_value).sum();
+        let irr = returns::portfolio_irr(history, pies, ledger, now);
+        let twr = returns::period_twr(&returns::total_flow_samples(&history.total), TwrPeriod::SinceInception, now);
+        let cash = account.cash.as_ref();
+        Self {
+            pies_initial: initial,
+            pies_current: current,
+            pies_return_percent: if initial != 0.0 { (current - initial) / initial * 100.0 } else { 0.0 },
+            pies_return_value: current - initial,
+            pies_irr_percent: irr.map(|irr| irr * 100.0),
+            pies_twr_percent: twr.map(|twr| twr * 100.0),
+            currency: account.info.as_ref().map(|info| info.currency_code.clone()),
+            total_equity: cash.map(|cash| cash.total),
+            free_cash: cash.map(|cash| cash.free),
+            blocked_cash: cash.and_then(|cash| cash.blocked),
+            invested: cash.map(|cash| cash.invested),
+            pie_cash: cash.map(|cash| cash.pie_cash),
+            unrealised_pl: cash.map(|cash| cash.ppl),
+        }
+    }
+}
+
+/// Money-weighted return of every pie that has one, as a fraction, keyed by pie id.
+pub fn pie_irrs(pies: &[Pie], history: &History, ledger: &Ledger, now: f64) -> HashMap<u64, f64> {
+    pies.iter()
+        .filter_map(|pie| returns::pie_irr(history, pie, ledger, now).map(|irr| (pie.id, irr)))
+        .collect()
+}
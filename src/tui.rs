@@ -0,0 +1,345 @@
+//! Terminal front end: the pie table and a value sparkline, for machines without a display.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Cell, Paragraph, Row, Sparkline, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use tokio::sync::Mutex;
+
+use crate::alerts::{self, AlertConfig, Alerts};
+use crate::api::Trading212Client;
+use crate::config::{Config, Environment};
+use crate::diagnostics;
+use crate::fetch::spawn_poller;
+use crate::history::{self, History, HistoryStore};
+use crate::ledger::{self, Ledger};
+use crate::models::Pie;
+use crate::poller::PollerStatus;
+use crate::returns::calculate_annual_rate;
+use crate::server::{self, ServerState};
+use crate::snapshot::{self, load_account, load_map, save_account, save_map, spawn_saver};
+use crate::sort::{select_column, sort_pies, SortColumn, SortDirection};
+
+/// How often the screen is redrawn when no key is pressed.
+const TICK: Duration = Duration::from_millis(500);
+
+/// How long a diagnostic stays in the footer.
+const DIAGNOSTIC_SECS: i64 = 30;
+
+/// Sort columns reachable with the number keys 1-5, in table order.
+const SORT_KEYS: [SortColumn; 5] = [
+    SortColumn::InitialValue,
+    SortColumn::CurrentValue,
+    SortColumn::Return,
+    SortColumn::ReturnValue,
+    SortColumn::AnnualRate,
+];
+
+/// Polls the API like the GUI does and shows the results until the user quits; with a
+/// `listen_address` the local HTTP server runs alongside.
+pub async fn run(config: Config, listen_address: Option<SocketAddr>) -> io::Result<()> {
+    let pies = Arc::new(Mutex::new(load_map(snapshot::DEFAULT_PATH).unwrap_or_default()));
+    let history = HistoryStore::open(history::DEFAULT_DIR)
+        .and_then(History::load)
+        .map_err(|e| io::Error::new(e.kind(), format!("Failed to load history from {}: {}", history::DEFAULT_DIR, e)))?;
+    let history = Arc::new(Mutex::new(history));
+
+    let client = Trading212Client::with_base_url(config.token, config.environment.base_url());
+    // The last account snapshot keeps the dividend fetch incremental across restarts
+    let account = Arc::new(Mutex::new(load_account(snapshot::DEFAULT_ACCOUNT_PATH).unwrap_or_default()));
+    let alerts = Alerts::new(AlertConfig::load(alerts::DEFAULT_PATH)?).with_state(alerts::DEFAULT_STATE_PATH, true)?;
+    let alerts = Arc::new(Mutex::new(alerts));
+    let poller_status = spawn_poller(client, pies.clone(), account.clone(), history.clone(), alerts);
+    spawn_saver(pies.clone(), account.clone());
+    if let Some(address) = listen_address {
+        let state = ServerState {
+            pies: pies.clone(),
+            account: account.clone(),
+            history: history.clone(),
+            ledger: Arc::new(Ledger::load(ledger::DEFAULT_PATH)?),
+            poller_status: poller_status.clone(),
+        };
+        server::spawn(address, state).await?;
+    }
+
+    let app = TuiApp {
+        environment: config.environment,
+        pies: pies.clone(),
+        history,
+        poller_status,
+        sort_column: SortColumn::None,
+        sort_direction: SortDirection::Descending,
+        table_state: TableState::default().with_selected(0),
+        chart_pie: None,
+    };
+    // Anything written to stderr would land in the middle of the screen
+    diagnostics::log_to_file(diagnostics::DEFAULT_LOG_PATH)?;
+    let result = tokio::task::spawn_blocking(move || {
+        let mut terminal = ratatui::init();
+        let result = app.run(&mut terminal);
+        ratatui::restore();
+        result
+    })
+    .await
+    .map_err(io::Error::other);
+    diagnostics::log_to_stderr();
+    let result = result?;
+
+    save_map(&*pies.lock().await, snapshot::DEFAULT_PATH)?;
+    save_account(&*account.lock().await, snapshot::DEFAULT_ACCOUNT_PATH)?;
+    result
+}
+
+struct TuiApp {
+    environment: Environment,
+    pies: Arc<Mutex<HashMap<usize, Pie>>>,
+    history: Arc<Mutex<History>>,
+    poller_status: Arc<Mutex<PollerStatus>>,
+    sort_column: SortColumn,
+    sort_direction: SortDirection,
+    table_state: TableState,
+    /// Pie whose value the sparkline shows; `None` for the portfolio total.
+    chart_pie: Option<u64>,
+}
+
+impl TuiApp {
+    /// Runs on a blocking thread, so locking the shared state with `blocking_lock` is fine here.
+    fn run(mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        loop {
+            let mut pies: Vec<Pie> = self.pies.blocking_lock().values().cloned().collect();
+            pies.sort_by_key(|pie| pie.id);
+            sort_pies(&mut pies, self.sort_column, self.sort_direction, &HashMap::new(), &HashMap::new(), &HashMap::new());
+            if self.table_state.selected().is_some_and(|i| i >= pies.len()) {
+                self.table_state.select(pies.len().checked_sub(1));
+            }
+
+            terminal.draw(|frame| self.draw(frame, &pies))?;
+
+            if !event::poll(TICK)? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') => return Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+                KeyCode::Esc if self.chart_pie.is_some() => self.chart_pie = None,
+                KeyCode::Esc => return Ok(()),
+                KeyCode::Down | KeyCode::Char('j') => self.table_state.select_next(),
+                KeyCode::Up | KeyCode::Char('k') => self.table_state.select_previous(),
+                KeyCode::PageDown => self.table_state.scroll_down_by(10),
+                KeyCode::PageUp => self.table_state.scroll_up_by(10),
+                KeyCode::Home | KeyCode::Char('g') => self.table_state.select_first(),
+                KeyCode::End | KeyCode::Char('G') => self.table_state.select_last(),
+                KeyCode::Enter => {
+                    let selected = self.table_state.selected().and_then(|i| pies.get(i)).map(|pie| pie.id);
+                    self.chart_pie = if self.chart_pie == selected { None } else { selected };
+                }
+                KeyCode::Char('0') => self.sort_column = SortColumn::None,
+                KeyCode::Char(c @ '1'..='5') => {
+                    let column = SORT_KEYS[c as usize - '1' as usize];
+                    (self.sort_column, self.sort_direction) =
+                        select_column(self.sort_column, self.sort_direction, column);
+                }
+                KeyCode::Char('r') => self.sort_direction = self.sort_direction.toggled(),
+                _ => {}
+            }
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame, pies: &[Pie]) {
+        let [header, chart, table, footer] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Length(8),
+            Constraint::Min(5),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        self.draw_header(frame, header, pies);
+        self.draw_sparkline(frame, chart, pies);
+        self.draw_table(frame, table, pies);
+        // A recent diagnostic takes the place of the key help for a while
+        let help = match diagnostics::last() {
+            Some((at, message)) if (Utc::now() - at).num_seconds() < DIAGNOSTIC_SECS => {
+                Paragraph::new(format!("{} (see {})", message, diagnostics::DEFAULT_LOG_PATH))
+                    .style(Style::default().fg(Color::Yellow))
+            }
+            _ => Paragraph::new("↑/↓ select  1-5 sort (again to reverse)  0 unsorted  r reverse  Enter chart pie  q quit")
+                .style(Style::default().fg(Color::DarkGray)),
+        };
+        frame.render_widget(help, footer);
+    }
+
+    fn draw_header(&self, frame: &mut Frame, area: Rect, pies: &[Pie]) {
+        let status = self.poller_status.blocking_lock().clone();
+        let env_color = match self.environment {
+            Environment::Live => Color::Green,
+            Environment::Demo => Color::Yellow,
+            Environment::Custom(_) => Color::LightBlue,
+        };
+
+        let total_initial: f64 = pies.iter().map(|p| p.result.price_avg_invested_value).sum();
+        let total_now: f64 = pies.iter().map(|p| p.result.price_avg_value).sum();
+        let total_result_percent = if total_initial != 0.0 {
+            (total_now - total_initial) / total_initial * 100.0
+        } else {
+            0.0
+        };
+
+        let now = Utc::now();
+        let mut spans = vec![
+            Span::styled(format!("{}", self.environment), Style::default().fg(env_color)),
+            Span::raw(format!(
+                "  Initial {:.2}  Current {:.2}  Return ",
+                total_initial, total_now
+            )),
+            Span::styled(format!("{:.2}%", total_result_percent), return_style(total_result_percent)),
+            Span::raw("  |  "),
+        ];
+        spans.push(Span::raw(match status.last_success {
+            Some(last) => format!("Last update: {}s ago", (now - last).num_seconds()),
+            None => "Last update: never".to_string(),
+        }));
+        if status.polling {
+            spans.push(Span::raw("  Refreshing..."));
+        } else if let Some(next) = status.next_poll {
+            spans.push(Span::raw(format!("  Next refresh in {}s", (next - now).num_seconds().max(0))));
+        }
+        if status.backoff_level > 0 {
+            spans.push(Span::styled(
+                format!("  Backoff level {}", status.backoff_level),
+                Style::default().fg(Color::Yellow),
+            ));
+        }
+        if let Some(error) = &status.last_error {
+            spans.push(Span::styled(format!("  {}", error), Style::default().fg(Color::Red)));
+        }
+
+        frame.render_widget(
+            Paragraph::new(Line::from(spans)).block(Block::bordered().title(" Pie Portfolio Dashboard ")),
+            area,
+        );
+    }
+
+    fn draw_sparkline(&self, frame: &mut Frame, area: Rect, pies: &[Pie]) {
+        let (title, values): (String, Vec<f64>) = {
+            let history = self.history.blocking_lock();
+            match self.chart_pie {
+                None => ("Total value".to_string(), history.total.iter().map(|p| p.total_value).collect()),
+                Some(pie_id) => {
+                    let name = pies
+                        .iter()
+                        .find(|pie| pie.id == pie_id)
+                        .and_then(|pie| pie.name.clone())
+                        .unwrap_or_else(|| format!("Pie {}", pie_id));
+                    let values = history.pie(pie_id).iter().map(|p| p.value).collect();
+                    (name, values)
+                }
+            }
+        };
+
+        let width = area.width.saturating_sub(2) as usize;
+        let values = resample(&values, width);
+        let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let title = match values.last() {
+            Some(last) => format!(" {}  last {:.2}  min {:.2}  max {:.2} ", title, last, min, max),
+            None => format!(" {}  no history yet ", title),
+        };
+
+        // Bars start at the minimum so small moves on a large value stay visible
+        let data: Vec<u64> = values
+            .iter()
+            .map(|value| if max > min { ((value - min) / (max - min) * 100.0) as u64 + 1 } else { 50 })
+            .collect();
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(title))
+                .data(data)
+                .style(Style::default().fg(Color::Cyan)),
+            area,
+        );
+    }
+
+    fn draw_table(&mut self, frame: &mut Frame, area: Rect, pies: &[Pie]) {
+        let titles = ["Initial Value", "Current Value", "Return %", "Return Value", "Annual Rate %"];
+        let mut header = vec![Cell::from("Name")];
+        for (column, title) in SORT_KEYS.iter().zip(titles) {
+            let title = if self.sort_column == *column {
+                match self.sort_direction {
+                    SortDirection::Ascending => format!("{} ↑", title),
+                    SortDirection::Descending => format!("{} ↓", title),
+                }
+            } else {
+                title.to_string()
+            };
+            header.push(Cell::from(title));
+        }
+        header.push(Cell::from("Status"));
+
+        let rows: Vec<Row> = pies
+            .iter()
+            .map(|pie| {
+                let initial = pie.result.price_avg_invested_value;
+                let current = pie.result.price_avg_value;
+                let return_percent = pie.result.price_avg_result_coef * 100.0;
+                let annual_rate = calculate_annual_rate(initial, current, pie.created_at.unwrap_or_default());
+                let marker = if self.chart_pie == Some(pie.id) { "● " } else { "" };
+                Row::new(vec![
+                    Cell::from(format!("{}{}", marker, pie.name.clone().unwrap_or_else(|| format!("Pie {}", pie.id)))),
+                    Cell::from(format!("{:.2}", initial)),
+                    Cell::from(format!("{:.2}", current)),
+                    Cell::from(format!("{:.2}%", return_percent)).style(return_style(return_percent)),
+                    Cell::from(format!("{:.2}", current - initial)).style(return_style(current - initial)),
+                    Cell::from(format!("{:.2}%", annual_rate)).style(return_style(annual_rate)),
+                    Cell::from(pie.status.clone().unwrap_or_else(|| "Active".to_string())),
+                ])
+            })
+            .collect();
+
+        let widths = [
+            Constraint::Fill(2),
+            Constraint::Length(15),
+            Constraint::Length(15),
+            Constraint::Length(11),
+            Constraint::Length(14),
+            Constraint::Length(15),
+            Constraint::Fill(1),
+        ];
+        let table = Table::new(rows, widths)
+            .header(Row::new(header).style(Style::default().add_modifier(Modifier::BOLD)))
+            .block(Block::bordered().title(format!(" Pies ({}) ", pies.len())))
+            .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, area, &mut self.table_state);
+    }
+}
+
+fn return_style(value: f64) -> Style {
+    if value >= 0.0 {
+        Style::default().fg(Color::Green)
+    } else {
+        Style::default().fg(Color::Red)
+    }
+}
+
+/// Picks at most `width` values spread over the whole series, always keeping the latest.
+fn resample(values: &[f64], width: usize) -> Vec<f64> {
+    if width == 0 || values.len() <= width {
+        return values.to_vec();
+    }
+    (1..=width).map(|i| values[i * values.len() / width - 1]).collect()
+}